@@ -0,0 +1,21 @@
+// records the commit the binary is built from, solutions keep it in their metadata
+fn main() {
+    let output = std::process::Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output();
+    if let Ok(output) = output {
+        if output.status.success() {
+            let commit = String::from_utf8_lossy(&output.stdout);
+            println!("cargo:rustc-env=GIT_COMMIT={}", commit.trim());
+        }
+    }
+
+    // built again when the checked out commit changes
+    let Ok(head) = std::fs::read_to_string(".git/HEAD") else {
+        return;
+    };
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Some(branch) = head.trim().strip_prefix("ref: ") {
+        println!("cargo:rerun-if-changed=.git/{branch}");
+    }
+}
@@ -1,11 +1,13 @@
 use oxhttp::model::{Body, HeaderName, Method, Request, Status};
 use oxhttp::Client;
 
-pub fn send_string(message: String) -> Option<String> {
-    send_encoded(encode(message)?)
+pub fn send_string(message: String) -> Result<String, String> {
+    send_encoded(encode(message).ok_or("the message can't be encoded")?)
 }
 
-pub fn send_encoded(body: impl Into<Body>) -> Option<String> {
+// fails without a token, when the server can't be reached or when it doesn't answer OK
+pub fn send_encoded(body: impl Into<Body>) -> Result<String, String> {
+    let token = std::env::var("ICFP2024_TOKEN").map_err(|_| "ICFP2024_TOKEN is not set")?;
     let client = Client::new();
     let mut request = Request::builder(
         Method::POST,
@@ -13,23 +15,18 @@ pub fn send_encoded(body: impl Into<Body>) -> Option<String> {
     )
     .with_body(body);
     request
-        .append_header(
-            HeaderName::AUTHORIZATION,
-            format!(
-                "Bearer {}",
-                std::env::var("ICFP2024_TOKEN").expect("Set the ICFP2024_TOKEN env var")
-            ),
-        )
+        .append_header(HeaderName::AUTHORIZATION, format!("Bearer {token}"))
         .unwrap();
-    let response = client.request(request).expect("Failed to send message");
+    let response = client
+        .request(request)
+        .map_err(|err| format!("failed to reach the server: {err}"))?;
     if response.status() != Status::OK {
-        return None;
+        return Err(format!("the server answered {}", response.status()));
     }
-    let body = response
+    response
         .into_body()
         .to_string()
-        .expect("Failed to parse the response body");
-    Some(body)
+        .map_err(|err| format!("failed to read the response: {err}"))
 }
 
 // TODO: use crate::lexer::unmap_string()
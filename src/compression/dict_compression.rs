@@ -5,7 +5,7 @@ use petgraph::{data::FromElements, graph::UnGraph, visit::DfsEvent};
 use crate::{
     geometry::Vector2D,
     icfp::{serialize_str, Node, Value},
-    runner::{Problem, Solution, Solver},
+    runner::{Budget, Problem, Solution, Solver},
 };
 
 use crate::{
@@ -27,7 +27,7 @@ impl Solver for LambdamanTreeWalkLzCompressed {
         self.tree_walk.initialize(problem, _solution)
     }
 
-    fn solve(&mut self) -> Solution {
+    fn solve(&mut self, budget: &Budget) -> Option<Solution> {
         let (graph, player_pos_node_idx) = self.tree_walk.build_graph();
 
         println!("Nodes count: {}", graph.node_count());
@@ -80,8 +80,12 @@ impl Solver for LambdamanTreeWalkLzCompressed {
 
         let mut best_len = serialize_str(plain_node.clone()).len();
         let mut best_ast = plain_node.clone();
+        budget.report(&Solution::new(best_ast.clone(), best_len as u64));
 
         for l in (MIN_VARIABLE_DICT_ENTRY_COST + 1)..(best_len / 2) {
+            if budget.is_over() {
+                break;
+            }
             let node = lz_compress_to_ast(
                 format!("solve {} ", self.tree_walk.problem.name).as_str(),
                 path.as_str(),
@@ -104,11 +108,15 @@ impl Solver for LambdamanTreeWalkLzCompressed {
                 if compressed_len < best_len {
                     best_len = compressed_len;
                     best_ast = node;
-                    println!("New best compression with chunk size: {}", l)
+                    println!("New best compression with chunk size: {}", l);
+                    budget.report(&Solution::new(best_ast.clone(), best_len as u64));
                 }
             }
         }
-        Solution::new(best_ast.clone(), serialize_str(best_ast).len() as u64)
+        Some(Solution::new(
+            best_ast.clone(),
+            serialize_str(best_ast).len() as u64,
+        ))
     }
 }
 
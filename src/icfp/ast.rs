@@ -1,4 +1,4 @@
-use std::{fmt::Display, rc::Rc};
+use std::{collections::HashSet, fmt::Display, rc::Rc};
 
 use display_tree::{AsTree, DisplayTree};
 
@@ -180,4 +180,37 @@ impl Node {
     pub fn bind(var: VarId, value: NodeRef, body: NodeRef) -> NodeRef {
         Self::apply(EvalStrat::Value, Self::lambda(var, body), value)
     }
+
+    pub fn free_variables(&self) -> HashSet<VarId> {
+        match self {
+            Node::Value(_) => HashSet::new(),
+            Node::Lambda { var, body } => {
+                let mut vars = body.free_variables();
+                vars.remove(var);
+                vars
+            }
+            Node::Variable(var) => HashSet::from([*var]),
+            Node::Apply { f, value, .. } => {
+                let mut vars = f.free_variables();
+                vars.extend(value.free_variables());
+                vars
+            }
+            Node::BinaryOp { left, right, .. } => {
+                let mut vars = left.free_variables();
+                vars.extend(right.free_variables());
+                vars
+            }
+            Node::UnuaryOp { body, .. } => body.free_variables(),
+            Node::If {
+                cond,
+                then_do,
+                else_do,
+            } => {
+                let mut vars = cond.free_variables();
+                vars.extend(then_do.free_variables());
+                vars.extend(else_do.free_variables());
+                vars
+            }
+        }
+    }
 }
@@ -5,6 +5,11 @@ const ALPHABET: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123
 pub type Base94UInt = BigUint;
 pub type Base94Int = BigInt;
 
+// whether a character can be part of an ICFP string
+pub fn is_icfp_char(c: char) -> bool {
+    ALPHABET.contains(c)
+}
+
 pub fn base94_to_int(s: &str) -> Option<BigUint> {
     let bytes = s.as_bytes().iter().map(|b| b - 33).collect::<Vec<_>>();
     BigUint::from_radix_be(&bytes, 94)
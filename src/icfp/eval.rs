@@ -1,4 +1,10 @@
-use std::rc::Rc;
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    panic::{catch_unwind, AssertUnwindSafe},
+    rc::{Rc, Weak},
+    sync::Once,
+};
 
 use num::bigint::ToBigInt;
 
@@ -7,18 +13,149 @@ use super::{
     NodeRef, UnuaryOp, Value, VarId,
 };
 
+// the number of beta reductions, and of strict reductions, after which evaluation gives up
+pub const EVALUATION_LIMIT: u32 = 10_000_000;
+
 pub fn evaluate(tree: Rc<Node>) -> Value {
-    Evaluator::new().evaluate(tree)
+    Evaluator::new(EVALUATION_LIMIT).evaluate(tree)
+}
+
+// also returns the number of beta reductions it took
+pub fn evaluate_counting(tree: Rc<Node>) -> (Value, u32) {
+    let mut evaluator = Evaluator::new(EVALUATION_LIMIT);
+    let value = evaluator.evaluate(tree);
+    (value, evaluator.num_substitutions)
+}
+
+thread_local! {
+    // set while a failure is expected, and reported as an error
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+// runs `f`, returning the message of its panic instead of printing it
+fn quietly<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    // the panics of quiet threads aren't printed
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let default = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !QUIET.with(Cell::get) {
+                default(info)
+            }
+        }));
+    });
+
+    let quiet = QUIET.replace(true);
+    let res = catch_unwind(AssertUnwindSafe(f));
+    QUIET.set(quiet);
+    res.map_err(|payload| match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "evaluation failed".to_owned()),
+    })
+}
+
+// evaluates within `limit` reductions, returning why evaluation failed instead of panicking
+pub fn try_evaluate(tree: Rc<Node>, limit: u32) -> Result<Value, String> {
+    quietly(|| Evaluator::new(limit).evaluate(tree))
+}
+
+// evaluates within `limit` beta reductions, like the server which doesn't limit strict reductions
+pub fn try_evaluate_beta(tree: Rc<Node>, limit: u32) -> Result<Value, String> {
+    let mut evaluator = Evaluator::new(limit);
+    evaluator.strict_limit = u32::MAX;
+    quietly(|| evaluator.evaluate(tree))
+}
+
+#[derive(Debug)]
+pub struct EvalError {
+    pub message: String,
+    // the node of the evaluated tree whose operator failed, if an operator failed
+    pub node: Option<NodeRef>,
+}
+
+// evaluates like `evaluate_counting`, telling which node of the tree failed instead of panicking
+pub fn evaluate_traced(tree: Rc<Node>) -> Result<(Value, u32), EvalError> {
+    let mut evaluator = Evaluator::new(EVALUATION_LIMIT);
+    let message = match quietly(|| evaluator.evaluate(tree.clone())) {
+        Ok(value) => return Ok((value, evaluator.num_substitutions)),
+        Err(message) if evaluator.exceeded => {
+            return Err(EvalError {
+                message,
+                node: None,
+            })
+        }
+        Err(message) => message,
+    };
+
+    // evaluation is deterministic: it fails the same way again, this time keeping track of
+    // where the operators come from, which is too slow to do every time
+    let mut evaluator = Evaluator::new(EVALUATION_LIMIT);
+    evaluator.origins = Some(HashMap::new());
+    let _ = quietly(|| evaluator.evaluate(tree));
+    let node = evaluator
+        .operator
+        .take()
+        .map(|node| evaluator.origin(&node));
+    Err(EvalError { message, node })
 }
 
 struct Evaluator {
     num_substitutions: u32,
+    limit: u32,
+    strict_limit: u32,
+    // whether evaluation gave up after too many reductions
+    exceeded: bool,
+    // when tracing, the nodes of the tree each rebuilt operator comes from, by address.
+    // the weak refs keep the addresses from being reused while they are in the map
+    origins: Option<HashMap<*const Node, (Weak<Node>, NodeRef)>>,
+    // the number of origins after which the dropped nodes are forgotten
+    prune_at: usize,
+    // when tracing, the operator being computed
+    operator: Option<NodeRef>,
 }
 
 impl Evaluator {
-    fn new() -> Self {
+    fn new(limit: u32) -> Self {
         Self {
             num_substitutions: 0,
+            limit,
+            strict_limit: limit,
+            exceeded: false,
+            origins: None,
+            prune_at: 1024,
+            operator: None,
+        }
+    }
+
+    // the node of the tree a node was rebuilt from
+    fn origin(&self, node: &NodeRef) -> NodeRef {
+        self.origins
+            .as_ref()
+            .and_then(|origins| origins.get(&Rc::as_ptr(node)))
+            .map_or_else(|| node.clone(), |(_, origin)| origin.clone())
+    }
+
+    // records that `new` is a rebuilt `old`, when tracing
+    fn derive(&mut self, old: &NodeRef, new: NodeRef) -> NodeRef {
+        if self.origins.is_none() {
+            return new;
+        }
+        let origin = self.origin(old);
+        let origins = self.origins.as_mut().unwrap();
+        origins.insert(Rc::as_ptr(&new), (Rc::downgrade(&new), origin));
+        if origins.len() > self.prune_at {
+            origins.retain(|_, (node, _)| node.strong_count() > 0);
+            self.prune_at = 2 * origins.len().max(1024);
+        }
+        new
+    }
+
+    fn computing(&mut self, operator: &NodeRef) {
+        if self.origins.is_some() {
+            self.operator = Some(operator.clone());
         }
     }
 
@@ -32,14 +169,15 @@ impl Evaluator {
             // tree.pretty_print(&mut std::io::stderr()).unwrap();
 
             loop {
-                let (new_tree, reduced) = Self::strict_reduction(tree.clone());
+                let (new_tree, reduced) = self.strict_reduction(tree.clone());
                 if reduced {
                     tree = new_tree;
                     strict_reductions += 1;
                 } else {
                     break;
                 }
-                if strict_reductions > 10_000_000 {
+                if strict_reductions > self.strict_limit {
+                    self.exceeded = true;
                     panic!("Too many strict reductions");
                 }
             }
@@ -50,7 +188,8 @@ impl Evaluator {
                 } else {
                     panic!("Didn't reduce to a value");
                 }
-            } else if self.num_substitutions > 10_000_000 {
+            } else if self.num_substitutions > self.limit {
+                self.exceeded = true;
                 panic!("Too many substitutions");
             }
         }
@@ -66,7 +205,7 @@ impl Evaluator {
                 let node = self.beta_reduction(f.clone());
                 match node.as_ref() {
                     Node::Lambda { var, body } => {
-                        let node = Self::substitute(body.clone(), *var, value.clone());
+                        let node = self.substitute(body.clone(), *var, value.clone());
                         self.num_substitutions += 1;
                         node
                     }
@@ -77,15 +216,21 @@ impl Evaluator {
                     }),
                 }
             }
-            Node::BinaryOp { op, left, right } => Rc::new(Node::BinaryOp {
-                op: *op,
-                left: self.beta_reduction(left.clone()),
-                right: self.beta_reduction(right.clone()),
-            }),
-            Node::UnuaryOp { op, body } => Rc::new(Node::UnuaryOp {
-                op: *op,
-                body: self.beta_reduction(body.clone()),
-            }),
+            Node::BinaryOp { op, left, right } => {
+                let node = Rc::new(Node::BinaryOp {
+                    op: *op,
+                    left: self.beta_reduction(left.clone()),
+                    right: self.beta_reduction(right.clone()),
+                });
+                self.derive(&tree, node)
+            }
+            Node::UnuaryOp { op, body } => {
+                let node = Rc::new(Node::UnuaryOp {
+                    op: *op,
+                    body: self.beta_reduction(body.clone()),
+                });
+                self.derive(&tree, node)
+            }
             Node::If {
                 cond,
                 then_do,
@@ -99,11 +244,11 @@ impl Evaluator {
     }
 
     // Computes strict nodes and folds
-    fn strict_reduction(tree: Rc<Node>) -> (Rc<Node>, bool) {
+    fn strict_reduction(&mut self, tree: Rc<Node>) -> (Rc<Node>, bool) {
         match tree.as_ref() {
             Node::Value(_) => (tree, false),
             Node::Lambda { var, body } => {
-                let (body, reduced) = Self::strict_reduction(body.clone());
+                let (body, reduced) = self.strict_reduction(body.clone());
                 if reduced {
                     (
                         Rc::new(Node::Lambda {
@@ -118,8 +263,8 @@ impl Evaluator {
             }
             Node::Variable(_) => (tree, false),
             Node::Apply { strat, f, value } => {
-                let (f, reduced_f) = Self::strict_reduction(f.clone());
-                let (value, reduced_value) = Self::strict_reduction(value.clone());
+                let (f, reduced_f) = self.strict_reduction(f.clone());
+                let (value, reduced_value) = self.strict_reduction(value.clone());
                 if reduced_f || reduced_value {
                     (
                         Rc::new(Node::Apply {
@@ -134,8 +279,9 @@ impl Evaluator {
                 }
             }
             Node::BinaryOp { op, left, right } => {
-                let (left, reduced_left) = Self::strict_reduction(left.clone());
-                let (right, reduced_right) = Self::strict_reduction(right.clone());
+                let (left, reduced_left) = self.strict_reduction(left.clone());
+                let (right, reduced_right) = self.strict_reduction(right.clone());
+                self.computing(&tree);
                 if let (Node::Value(l), Node::Value(r)) = (left.as_ref(), right.as_ref()) {
                     match op {
                         BinaryOp::IntAdd => (int(l.as_int() + r.as_int()), true),
@@ -181,51 +327,70 @@ impl Evaluator {
                                 match op {
                                     BinaryOp::IntAdd => {
                                         return (
-                                            Rc::new(Node::BinaryOp {
-                                                op: *op,
-                                                left: int(l.as_int() + r.as_int()),
-                                                right: next.clone(),
-                                            }),
+                                            self.derive(
+                                                &tree,
+                                                Rc::new(Node::BinaryOp {
+                                                    op: *op,
+                                                    left: int(l.as_int() + r.as_int()),
+                                                    right: next.clone(),
+                                                }),
+                                            ),
                                             true,
                                         );
                                     }
                                     BinaryOp::IntMul => {
                                         return (
-                                            Rc::new(Node::BinaryOp {
-                                                op: *op,
-                                                left: int(l.as_int() * r.as_int()),
-                                                right: next.clone(),
-                                            }),
+                                            self.derive(
+                                                &tree,
+                                                Rc::new(Node::BinaryOp {
+                                                    op: *op,
+                                                    left: int(l.as_int() * r.as_int()),
+                                                    right: next.clone(),
+                                                }),
+                                            ),
                                             true,
                                         );
                                     }
                                     BinaryOp::BoolAnd => {
                                         return (
-                                            Rc::new(Node::BinaryOp {
-                                                op: *op,
-                                                left: bool(l.as_bool() && r.as_bool()),
-                                                right: next.clone(),
-                                            }),
+                                            self.derive(
+                                                &tree,
+                                                Rc::new(Node::BinaryOp {
+                                                    op: *op,
+                                                    left: bool(l.as_bool() && r.as_bool()),
+                                                    right: next.clone(),
+                                                }),
+                                            ),
                                             true,
                                         );
                                     }
                                     BinaryOp::BoolOr => {
                                         return (
-                                            Rc::new(Node::BinaryOp {
-                                                op: *op,
-                                                left: bool(l.as_bool() || r.as_bool()),
-                                                right: next.clone(),
-                                            }),
+                                            self.derive(
+                                                &tree,
+                                                Rc::new(Node::BinaryOp {
+                                                    op: *op,
+                                                    left: bool(l.as_bool() || r.as_bool()),
+                                                    right: next.clone(),
+                                                }),
+                                            ),
                                             true,
                                         );
                                     }
                                     BinaryOp::StrConcat => {
                                         return (
-                                            Rc::new(Node::BinaryOp {
-                                                op: *op,
-                                                left: str(format!("{}{}", l.as_str(), r.as_str())),
-                                                right: next.clone(),
-                                            }),
+                                            self.derive(
+                                                &tree,
+                                                Rc::new(Node::BinaryOp {
+                                                    op: *op,
+                                                    left: str(format!(
+                                                        "{}{}",
+                                                        l.as_str(),
+                                                        r.as_str()
+                                                    )),
+                                                    right: next.clone(),
+                                                }),
+                                            ),
                                             true,
                                         );
                                     }
@@ -324,11 +489,14 @@ impl Evaluator {
 
                     if reduced_left || reduced_right {
                         (
-                            Rc::new(Node::BinaryOp {
-                                op: *op,
-                                left,
-                                right,
-                            }),
+                            self.derive(
+                                &tree,
+                                Rc::new(Node::BinaryOp {
+                                    op: *op,
+                                    left,
+                                    right,
+                                }),
+                            ),
                             true,
                         )
                     } else {
@@ -337,7 +505,8 @@ impl Evaluator {
                 }
             }
             Node::UnuaryOp { op, body } => {
-                let (body, reduced) = Self::strict_reduction(body.clone());
+                let (body, reduced) = self.strict_reduction(body.clone());
+                self.computing(&tree);
                 if let Node::Value(v) = body.as_ref() {
                     match op {
                         UnuaryOp::IntNeg => (int(-v.as_int()), true),
@@ -357,7 +526,10 @@ impl Evaluator {
                         ),
                     }
                 } else if reduced {
-                    (Rc::new(Node::UnuaryOp { op: *op, body }), true)
+                    (
+                        self.derive(&tree, Rc::new(Node::UnuaryOp { op: *op, body })),
+                        true,
+                    )
                 } else {
                     (tree, false)
                 }
@@ -367,13 +539,13 @@ impl Evaluator {
                 then_do,
                 else_do,
             } => {
-                let (cond, reduced) = Self::strict_reduction(cond.clone());
+                let (cond, reduced) = self.strict_reduction(cond.clone());
                 if let Node::Value(Value::Bool(b)) = cond.as_ref() {
                     if *b {
-                        let (then_do, _) = Self::strict_reduction(then_do.clone());
+                        let (then_do, _) = self.strict_reduction(then_do.clone());
                         (then_do, true)
                     } else {
-                        let (else_do, _) = Self::strict_reduction(else_do.clone());
+                        let (else_do, _) = self.strict_reduction(else_do.clone());
                         (else_do, true)
                     }
                 } else if reduced {
@@ -392,14 +564,14 @@ impl Evaluator {
         }
     }
 
-    fn substitute(node: Rc<Node>, var: VarId, value: Rc<Node>) -> Rc<Node> {
+    fn substitute(&mut self, node: Rc<Node>, var: VarId, value: Rc<Node>) -> Rc<Node> {
         match node.as_ref() {
             Node::Value(_) => node,
             Node::Lambda { var: v, body } => {
                 if *v != var {
                     Rc::new(Node::Lambda {
                         var: *v,
-                        body: Self::substitute(body.clone(), var, value),
+                        body: self.substitute(body.clone(), var, value),
                     })
                 } else {
                     node
@@ -414,26 +586,32 @@ impl Evaluator {
             }
             Node::Apply { strat, f, value: v } => Rc::new(Node::Apply {
                 strat: *strat,
-                f: Self::substitute(f.clone(), var, value.clone()),
-                value: Self::substitute(v.clone(), var, value),
-            }),
-            Node::BinaryOp { op, left, right } => Rc::new(Node::BinaryOp {
-                op: *op,
-                left: Self::substitute(left.clone(), var, value.clone()),
-                right: Self::substitute(right.clone(), var, value),
-            }),
-            Node::UnuaryOp { op, body } => Rc::new(Node::UnuaryOp {
-                op: *op,
-                body: Self::substitute(body.clone(), var, value),
+                f: self.substitute(f.clone(), var, value.clone()),
+                value: self.substitute(v.clone(), var, value),
             }),
+            Node::BinaryOp { op, left, right } => {
+                let new = Rc::new(Node::BinaryOp {
+                    op: *op,
+                    left: self.substitute(left.clone(), var, value.clone()),
+                    right: self.substitute(right.clone(), var, value),
+                });
+                self.derive(&node, new)
+            }
+            Node::UnuaryOp { op, body } => {
+                let new = Rc::new(Node::UnuaryOp {
+                    op: *op,
+                    body: self.substitute(body.clone(), var, value),
+                });
+                self.derive(&node, new)
+            }
             Node::If {
                 cond,
                 then_do,
                 else_do,
             } => Rc::new(Node::If {
-                cond: Self::substitute(cond.clone(), var, value.clone()),
-                then_do: Self::substitute(then_do.clone(), var, value.clone()),
-                else_do: Self::substitute(else_do.clone(), var, value),
+                cond: self.substitute(cond.clone(), var, value.clone()),
+                then_do: self.substitute(then_do.clone(), var, value.clone()),
+                else_do: self.substitute(else_do.clone(), var, value),
             }),
         }
     }
@@ -609,7 +787,7 @@ mod tests {
     #[test]
     fn num_substitutions() {
         const TASK: &str = "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%";
-        let mut evaluator = Evaluator::new();
+        let mut evaluator = Evaluator::new(EVALUATION_LIMIT);
         let result = evaluator.evaluate(parse(&mut Token::lexer(TASK)).unwrap());
         assert_eq!(result, Value::Int(16.into()));
         assert_eq!(evaluator.num_substitutions, 109);
@@ -7,7 +7,9 @@ mod serializer;
 
 pub use ast::{BinaryOp, EvalStrat, Node, NodeRef, UnuaryOp, Value, VarId};
 pub use base94::*;
-pub use eval::evaluate;
+pub use eval::{
+    evaluate, evaluate_counting, evaluate_traced, try_evaluate, try_evaluate_beta, EVALUATION_LIMIT,
+};
 pub use lexer::Token;
 pub use parser::parse;
-pub use serializer::serialize_str;
+pub use serializer::{serialize, serialize_str};
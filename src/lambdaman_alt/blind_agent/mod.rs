@@ -3,7 +3,7 @@ use std::marker::PhantomData;
 use crate::{
     geometry::Move,
     icfp::serialize_str,
-    runner::{Problem, Solution, Solver},
+    runner::{Budget, Problem, Solution, Solver},
 };
 
 use super::model::LambdamanModel;
@@ -20,6 +20,7 @@ pub trait Agent {
 }
 
 struct Attempt {
+    seed: u32,
     code: String,
     model: LambdamanModel,
 }
@@ -63,6 +64,7 @@ impl<T: Agent> BlindAgentSolver<T> {
         }
 
         Attempt {
+            seed,
             code: agent.emit_agent(model.move_count),
             model,
         }
@@ -79,10 +81,13 @@ impl<T: Agent> Solver for BlindAgentSolver<T> {
         self.model = LambdamanModel::load(&self.problem.load());
     }
 
-    fn solve(&mut self) -> Solution {
+    fn solve(&mut self, budget: &Budget) -> Option<Solution> {
         let mut best_attempt: Option<Attempt> = None;
 
         for _ in 0..1000 {
+            if budget.is_over() {
+                break;
+            }
             let seed: u32 = rand::random();
             let attempt = self.attempt(seed, 1_000_000);
 
@@ -100,7 +105,7 @@ impl<T: Agent> Solver for BlindAgentSolver<T> {
             }
         }
 
-        let best_attempt = best_attempt.unwrap();
+        let best_attempt = best_attempt?;
 
         if !best_attempt.model.is_solved() {
             eprintln!(
@@ -108,25 +113,28 @@ impl<T: Agent> Solver for BlindAgentSolver<T> {
                 best_attempt.model.fruit_count
             );
             best_attempt.model.print();
-            std::process::exit(1);
+            return None;
         }
 
         let problem_name = self.problem.name.as_str();
         let code = format!(r#""solve {problem_name} " . {}"#, best_attempt.code);
 
-        eprintln!("found solution:\n{code}");
+        eprintln!(
+            "found solution:\n{}",
+            crate::lasm::format(&code).unwrap_or_else(|_| code.clone())
+        );
 
-        let node = match crate::lasm::parse(&code) {
+        let node = match crate::lasm::parse(&code).and_then(crate::lasm::compile) {
             Ok(node) => node,
             Err(err) => {
-                eprintln!(
-                    "Parsing failed:\n{}",
-                    nom::error::convert_error(code.as_str(), err)
-                );
+                let sources = [crate::lasm::SourceFile::new("<agent>", code.as_str())];
+                eprint!("{}", err.render(&sources));
                 panic!();
             }
         };
-        let node = crate::lasm::compile(node);
-        Solution::new(node.clone(), serialize_str(node).len() as u64)
+        Some(
+            Solution::new(node.clone(), serialize_str(node).len() as u64)
+                .with_seed(best_attempt.seed as u64),
+        )
     }
 }
@@ -34,14 +34,14 @@ impl Agent for RandomStepAgent {
     fn emit_agent(self, iter_count: usize) -> String {
         format!(
             r#"
-            "let rec generate_instructions seed num_insn = (
+            (let rec generate_instructions seed num_insn acc = (
                 if num_insn < 1 {{
-                    ""
+                    acc
                 }} else {{
-                    (("ULDR" drop ((seed / 42) % 4)) take 1)
-                    . (generate_instructions (({FACTOR} * seed + {ADD}) % {MODULO}) (num_insn - 1))
+                    generate_instructions (({FACTOR} * seed + {ADD}) % {MODULO}) (num_insn - 1)
+                        (acc . (("ULDR" drop ((seed / 42) % 4)) take 1))
                 }}
-            ); in generate_instructions {} {iter_count}
+            ); in generate_instructions {} {iter_count} "")
         "#,
             self.seed
         )
@@ -1,6 +1,8 @@
 mod blind_agent;
 mod model;
 mod tree_walk;
+mod verifier;
 
 pub use blind_agent::{BlindAgentSolver, RandomStepAgent};
 pub use tree_walk::LambdamanTreeWalk;
+pub use verifier::LambdamanVerifier;
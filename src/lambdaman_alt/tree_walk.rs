@@ -3,13 +3,13 @@ use std::{collections::HashMap, rc::Rc};
 use petgraph::{
     data::FromElements,
     graph::{NodeIndex, UnGraph},
-    visit::DfsEvent,
+    visit::{Control, DfsEvent},
 };
 
 use crate::{
     geometry::Vector2D,
     icfp::{serialize_str, Node, Value},
-    runner::{Problem, Solution, Solver},
+    runner::{Budget, Problem, Solution, Solver},
 };
 
 use super::model::{Cell, LambdamanModel};
@@ -74,7 +74,7 @@ impl Solver for LambdamanTreeWalk {
         self.model = LambdamanModel::load(&self.problem.load());
     }
 
-    fn solve(&mut self) -> Solution {
+    fn solve(&mut self, budget: &Budget) -> Option<Solution> {
         let (graph, player_pos_node_idx) = self.build_graph();
 
         println!("Nodes count: {}", graph.node_count());
@@ -87,7 +87,11 @@ impl Solver for LambdamanTreeWalk {
         println!("Edges count: {}", graph.edge_count());
 
         let mut node_path = vec![];
-        petgraph::visit::depth_first_search(&tree, Some(player_pos_node_idx), |e| {
+        let walk = petgraph::visit::depth_first_search(&tree, Some(player_pos_node_idx), |e| {
+            // a partial walk isn't a solution
+            if budget.is_over() {
+                return Control::Break(());
+            }
             if let DfsEvent::Discover(node, _) = e {
                 if node_path.is_empty() {
                     node_path.push(node);
@@ -102,7 +106,11 @@ impl Solver for LambdamanTreeWalk {
                     );
                 }
             }
+            Control::Continue
         });
+        if walk.break_value().is_some() {
+            return None;
+        }
 
         println!("Node path: {:?}", node_path);
 
@@ -129,6 +137,9 @@ impl Solver for LambdamanTreeWalk {
             "solve {} {}",
             self.problem.name, path
         ))));
-        Solution::new(node.clone(), serialize_str(node).len() as u64)
+        Some(Solution::new(
+            node.clone(),
+            serialize_str(node).len() as u64,
+        ))
     }
 }
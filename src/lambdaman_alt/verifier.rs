@@ -0,0 +1,25 @@
+use crate::{geometry::Move, runner::Verifier};
+
+use super::model::LambdamanModel;
+
+// the moves must eat all the pills
+#[derive(Debug, Clone, Default)]
+pub struct LambdamanVerifier;
+
+impl Verifier for LambdamanVerifier {
+    fn verify(&self, problem: &str, answer: &str) -> Result<(), String> {
+        let mut model = LambdamanModel::load(problem);
+        let pills = model.fruit_count;
+        for c in answer.chars() {
+            let mov = Move::try_from(c).map_err(|_| format!("invalid move {c:?}"))?;
+            model.apply(mov);
+        }
+        if !model.is_solved() {
+            return Err(format!(
+                "{} of the {pills} pills are left",
+                model.fruit_count
+            ));
+        }
+        Ok(())
+    }
+}
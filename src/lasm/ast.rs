@@ -1,6 +1,8 @@
-use std::rc::Rc;
+use std::{fmt::Display, ops::Deref, rc::Rc};
 
-pub use crate::icfp::{BinaryOp, UnuaryOp, Value};
+use super::source::Span;
+
+pub use crate::icfp::{Base94Int, BinaryOp, EvalStrat, UnuaryOp, Value};
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Iden(String);
@@ -11,29 +13,252 @@ impl Iden {
     }
 }
 
+impl Display for Iden {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl From<String> for Iden {
     fn from(value: String) -> Self {
         Self::new(value)
     }
 }
 
-pub type LNodeRef = Rc<LNode>;
+// a shared node, along with where it comes from in the source
+#[derive(Clone)]
+pub struct LNodeRef {
+    node: Rc<LNode>,
+    pub span: Span,
+}
+
+impl LNodeRef {
+    pub fn new(node: LNode) -> Self {
+        Self {
+            node: Rc::new(node),
+            span: Span::default(),
+        }
+    }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+    pub fn with_span(self, span: Span) -> Self {
+        Self { span, ..self }
+    }
+
+    // rebuilds the tree with all its spans transformed
+    pub fn map_spans(&self, f: &impl Fn(Span) -> Span) -> LNodeRef {
+        let node = match self.as_ref() {
+            LNode::Litteral(_) | LNode::Variable(_) => self.node.clone(),
+            LNode::Let {
+                strat,
+                bindings,
+                body,
+            } => Rc::new(LNode::Let {
+                strat: *strat,
+                bindings: bindings.iter().map(|b| b.map_spans(f)).collect(),
+                body: body.map_spans(f),
+            }),
+            LNode::Apply { strat, func, param } => Rc::new(LNode::Apply {
+                strat: *strat,
+                func: func.map_spans(f),
+                param: param.map_spans(f),
+            }),
+            LNode::BinaryOp { op, left, right } => Rc::new(LNode::BinaryOp {
+                op: *op,
+                left: left.map_spans(f),
+                right: right.map_spans(f),
+            }),
+            LNode::UnuaryOp { op, body } => Rc::new(LNode::UnuaryOp {
+                op: *op,
+                body: body.map_spans(f),
+            }),
+            LNode::If {
+                cond,
+                then_do,
+                else_do,
+            } => Rc::new(LNode::If {
+                cond: cond.map_spans(f),
+                then_do: then_do.map_spans(f),
+                else_do: else_do.map_spans(f),
+            }),
+            LNode::Tuple(items) => Rc::new(LNode::Tuple(
+                items.iter().map(|item| item.map_spans(f)).collect(),
+            )),
+            LNode::Nil => self.node.clone(),
+            LNode::Cons { head, tail } => Rc::new(LNode::Cons {
+                head: head.map_spans(f),
+                tail: tail.map_spans(f),
+            }),
+            LNode::Match { value, arms } => Rc::new(LNode::Match {
+                value: value.map_spans(f),
+                arms: arms
+                    .iter()
+                    .map(|(pattern, body)| (pattern.clone(), body.map_spans(f)))
+                    .collect(),
+            }),
+        };
+        LNodeRef {
+            node,
+            span: f(self.span),
+        }
+    }
+}
+
+impl Deref for LNodeRef {
+    type Target = LNode;
+
+    fn deref(&self) -> &LNode {
+        &self.node
+    }
+}
+
+impl AsRef<LNode> for LNodeRef {
+    fn as_ref(&self) -> &LNode {
+        &self.node
+    }
+}
+
+impl From<LNode> for LNodeRef {
+    fn from(value: LNode) -> Self {
+        Self::new(value)
+    }
+}
+
+// spans are ignored when comparing nodes
+impl PartialEq for LNodeRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl Eq for LNodeRef {}
+
+impl std::fmt::Debug for LNodeRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Binding {
     pub rec: bool,
+    // constant bindings are evaluated at compile time
+    pub constant: bool,
     pub name: Iden,
     pub params: Vec<Iden>,
     pub value: LNodeRef,
+    // where the name is defined
+    pub span: Span,
+}
+
+impl PartialEq for Binding {
+    fn eq(&self, other: &Self) -> bool {
+        self.rec == other.rec
+            && self.constant == other.constant
+            && self.name == other.name
+            && self.params == other.params
+            && self.value == other.value
+    }
 }
 
+impl Eq for Binding {}
+
 impl Binding {
     pub fn new(rec: bool, name: Iden, params: Vec<Iden>, value: LNodeRef) -> Self {
         Binding {
             rec,
+            constant: false,
             name,
             params,
             value,
+            span: Span::default(),
+        }
+    }
+
+    pub fn with_span(self, span: Span) -> Self {
+        Self { span, ..self }
+    }
+
+    pub fn map_spans(&self, f: &impl Fn(Span) -> Span) -> Self {
+        Self {
+            value: self.value.map_spans(f),
+            span: f(self.span),
+            ..self.clone()
+        }
+    }
+
+    pub fn constant(name: Iden, value: LNodeRef) -> Self {
+        Binding {
+            constant: true,
+            ..Self::new(false, name, vec![], value)
+        }
+    }
+}
+
+// an inline test: `test "name" { expr } == expected;`
+#[derive(Clone, Debug)]
+pub struct Test {
+    pub name: String,
+    pub expr: LNodeRef,
+    pub expected: LNodeRef,
+    // where the test is declared
+    pub span: Span,
+}
+
+impl PartialEq for Test {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.expr == other.expr && self.expected == other.expected
+    }
+}
+
+impl Eq for Test {}
+
+impl Test {
+    pub fn map_spans(&self, f: &impl Fn(Span) -> Span) -> Self {
+        Self {
+            name: self.name.clone(),
+            expr: self.expr.map_spans(f),
+            expected: self.expected.map_spans(f),
+            span: f(self.span),
+        }
+    }
+}
+
+// a source file: either a library exporting bindings, or a program with a body
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program {
+    pub imports: Vec<String>,
+    pub bindings: Vec<Binding>,
+    pub body: Option<LNodeRef>,
+    // tests aren't part of the compiled output
+    pub tests: Vec<Test>,
+}
+
+impl Program {
+    pub fn map_spans(&self, f: &impl Fn(Span) -> Span) -> Self {
+        Self {
+            imports: self.imports.clone(),
+            bindings: self.bindings.iter().map(|b| b.map_spans(f)).collect(),
+            body: self.body.as_ref().map(|body| body.map_spans(f)),
+            tests: self.tests.iter().map(|t| t.map_spans(f)).collect(),
+        }
+    }
+}
+
+// what a match arm destructures
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Tuple(Vec<Iden>),
+    Nil,
+    Cons(Iden, Iden),
+}
+
+impl Pattern {
+    // the names bound by the pattern
+    pub fn names(&self) -> Vec<Iden> {
+        match self {
+            Pattern::Tuple(names) => names.clone(),
+            Pattern::Nil => vec![],
+            Pattern::Cons(head, tail) => vec![head.clone(), tail.clone()],
         }
     }
 }
@@ -42,11 +267,13 @@ impl Binding {
 pub enum LNode {
     Litteral(Value),
     Let {
+        strat: EvalStrat,
         bindings: Vec<Binding>,
         body: LNodeRef,
     },
     Variable(Iden),
     Apply {
+        strat: EvalStrat,
         func: LNodeRef,
         param: LNodeRef,
     },
@@ -64,11 +291,22 @@ pub enum LNode {
         then_do: LNodeRef,
         else_do: LNodeRef,
     },
+    // tuples and lists are desugared to Scott encodings by the compiler
+    Tuple(Vec<LNodeRef>),
+    Nil,
+    Cons {
+        head: LNodeRef,
+        tail: LNodeRef,
+    },
+    Match {
+        value: LNodeRef,
+        arms: Vec<(Pattern, LNodeRef)>,
+    },
 }
 
 impl LNode {
     pub fn cond(cond: LNodeRef, then_do: LNodeRef, else_do: LNodeRef) -> LNodeRef {
-        Rc::new(Self::If {
+        LNodeRef::new(Self::If {
             cond,
             then_do,
             else_do,
@@ -76,26 +314,30 @@ impl LNode {
     }
 
     pub fn binary_op(op: BinaryOp, left: LNodeRef, right: LNodeRef) -> LNodeRef {
-        Rc::new(Self::BinaryOp { op, left, right })
+        LNodeRef::new(Self::BinaryOp { op, left, right })
     }
 
     pub fn unuary_op(op: UnuaryOp, body: LNodeRef) -> LNodeRef {
-        Rc::new(Self::UnuaryOp { op, body })
+        LNodeRef::new(Self::UnuaryOp { op, body })
     }
 
     pub fn apply(func: LNodeRef, param: LNodeRef) -> LNodeRef {
-        Rc::new(Self::Apply { func, param })
+        Self::apply_strat(EvalStrat::Name, func, param)
+    }
+
+    pub fn apply_strat(strat: EvalStrat, func: LNodeRef, param: LNodeRef) -> LNodeRef {
+        LNodeRef::new(Self::Apply { strat, func, param })
     }
 
     pub fn var(name: impl Into<Iden>) -> LNodeRef {
-        Rc::new(Self::Variable(name.into()))
+        LNodeRef::new(Self::Variable(name.into()))
     }
 
     pub fn value(val: Value) -> LNodeRef {
-        Rc::new(Self::Litteral(val))
+        LNodeRef::new(Self::Litteral(val))
     }
 
-    pub fn int(val: i64) -> LNodeRef {
+    pub fn int(val: impl Into<Base94Int>) -> LNodeRef {
         Self::value(Value::Int(val.into()))
     }
 
@@ -106,4 +348,16 @@ impl LNode {
     pub fn str(val: impl Into<String>) -> LNodeRef {
         Self::value(Value::Str(val.into()))
     }
+
+    pub fn tuple(items: Vec<LNodeRef>) -> LNodeRef {
+        LNodeRef::new(Self::Tuple(items))
+    }
+
+    pub fn nil() -> LNodeRef {
+        LNodeRef::new(Self::Nil)
+    }
+
+    pub fn cons(head: LNodeRef, tail: LNodeRef) -> LNodeRef {
+        LNodeRef::new(Self::Cons { head, tail })
+    }
 }
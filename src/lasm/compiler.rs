@@ -1,14 +1,44 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
-use crate::icfp::{EvalStrat, Node, NodeRef, VarId};
+use crate::icfp::{
+    evaluate_traced, is_icfp_char, try_evaluate, EvalStrat, Node, NodeRef, Value, VarId,
+    EVALUATION_LIMIT,
+};
 
-use super::{ast::Binding, Iden, LNode, LNodeRef};
+use super::{
+    ast::{Binding, Pattern},
+    const_eval::fold_constants,
+    interpreter::builtin_call,
+    optimizer::{binding_sizes, optimize, size, BindingSize},
+    recursion::compile_tail_recursive,
+    source::{Diagnostic, SourceMap, Span},
+    typing::type_check,
+    Iden, LNode, LNodeRef,
+};
+
+type CompileResult<T> = Result<T, Diagnostic>;
 
 struct Compiler {
     // TODO: smart iden allocation?
     iden_count: u64,
     idens: HashMap<Iden, VarId>,
     y_combinator: Option<(VarId, NodeRef)>,
+    // values of the constant bindings in scope
+    consts: HashMap<Iden, Value>,
+    // names bound by the program in scope, which shadow the builtins
+    bound: HashSet<Iden>,
+    source_map: SourceMap,
+    // whether to minimize the size of the output
+    optimize: bool,
+    // the number of reductions a constant may take to evaluate
+    const_eval_limit: u32,
+    // whether tail recursive functions get their own fixpoint instead of the Y combinator
+    tail_recursion: bool,
+    // how many functions got their own fixpoint
+    tail_recursive_count: usize,
 }
 
 impl Compiler {
@@ -17,6 +47,13 @@ impl Compiler {
             iden_count: 0,
             idens: HashMap::new(),
             y_combinator: None,
+            consts: HashMap::new(),
+            bound: HashSet::new(),
+            source_map: SourceMap::default(),
+            optimize: false,
+            const_eval_limit: EVALUATION_LIMIT,
+            tail_recursion: true,
+            tail_recursive_count: 0,
         }
     }
 
@@ -36,6 +73,58 @@ impl Compiler {
         id
     }
 
+    fn iden_name(&self, var: VarId) -> String {
+        self.idens
+            .iter()
+            .find(|(_, id)| **id == var)
+            .map(|(iden, _)| iden.to_string())
+            .unwrap_or_else(|| var.to_string())
+    }
+
+    // finds where a variable is used in the source
+    fn variable_span(&self, node: &NodeRef, var: VarId) -> Option<Span> {
+        match node.as_ref() {
+            Node::Value(_) => None,
+            Node::Variable(v) if *v == var => self.source_map.get(node),
+            Node::Variable(_) => None,
+            Node::Lambda { body, .. } | Node::UnuaryOp { body, .. } => {
+                self.variable_span(body, var)
+            }
+            Node::Apply { f, value, .. } => self
+                .variable_span(f, var)
+                .or_else(|| self.variable_span(value, var)),
+            Node::BinaryOp { left, right, .. } => self
+                .variable_span(left, var)
+                .or_else(|| self.variable_span(right, var)),
+            Node::If {
+                cond,
+                then_do,
+                else_do,
+            } => self
+                .variable_span(cond, var)
+                .or_else(|| self.variable_span(then_do, var))
+                .or_else(|| self.variable_span(else_do, var)),
+        }
+    }
+
+    // fails if the compiled node has free variables
+    fn check_closed(
+        &self,
+        node: &NodeRef,
+        error: impl Fn(&str) -> String,
+        default_span: Span,
+    ) -> CompileResult<()> {
+        let mut free_variables: Vec<_> = node.free_variables().into_iter().collect();
+        free_variables.sort_by_key(|var| var.id());
+        match free_variables.first() {
+            Some(var) => Err(Diagnostic::new(
+                error(&self.iden_name(*var)),
+                self.variable_span(node, *var).unwrap_or(default_span),
+            )),
+            None => Ok(()),
+        }
+    }
+
     // the y combinator is only created once, and added at the top level
     fn get_y_combinator(&mut self) -> NodeRef {
         if let Some((id, _)) = self.y_combinator {
@@ -82,82 +171,286 @@ impl Compiler {
         Node::var(id)
     }
 
+    // evaluate a constant binding, which may use the bindings which precede it
+    fn eval_const(&mut self, binding: &Binding, scope: &[Binding]) -> CompileResult<Value> {
+        let source = LNode::Let {
+            strat: EvalStrat::Value,
+            bindings: scope.iter().filter(|b| !b.constant).cloned().collect(),
+            body: binding.value.clone(),
+        };
+        let mut compiler = Compiler::new();
+        compiler.consts = self.consts.clone();
+        compiler.bound = self.bound.clone();
+        compiler.const_eval_limit = self.const_eval_limit;
+        let node = compiler.compile(LNodeRef::new(source).with_span(binding.value.span))?;
+        compiler.check_closed(
+            &node,
+            |iden| format!("const {} isn't closed, it uses {}", binding.name, iden),
+            binding.span,
+        )?;
+        // constants may divide by zero or never finish, like any program
+        try_evaluate(node, self.const_eval_limit).map_err(|err| {
+            Diagnostic::new(
+                format!("const {} failed to evaluate: {}", binding.name, err),
+                binding.span,
+            )
+        })
+    }
+
     // simplify a binding
-    fn compile_binding(&mut self, binding: &Binding) -> (VarId, NodeRef) {
+    fn compile_binding(&mut self, binding: &Binding) -> CompileResult<(VarId, NodeRef)> {
         let var_id = self.resolve(&binding.name);
-        let mut body = self.compile_node(&binding.value);
+
+        // parameters shadow constants
+        let consts = self.consts.clone();
+        let bound = self.bound.clone();
+        for param in binding.params.iter() {
+            self.consts.remove(param);
+            self.bound.insert(param.clone());
+        }
+        let body = self.compile_node(&binding.value);
+        self.consts = consts;
+        self.bound = bound;
+        let mut body = body?;
 
         // if the binding is a variable
         if binding.params.is_empty() {
-            assert!(!binding.rec);
-            return (var_id, body);
+            if binding.rec {
+                return Err(Diagnostic::new(
+                    format!("{} is recursive but isn't a function", binding.name),
+                    binding.span,
+                ));
+            }
+            return Ok((var_id, body));
         }
 
         // if it is a function, bind parameters
-        for param in binding.params.iter().rev() {
-            body = {
-                let var = self.resolve(param);
-                Node::lambda(var, body)
-            }
-        }
+        let params: Vec<VarId> = binding.params.iter().map(|p| self.resolve(p)).collect();
 
-        // if the function is recursive, apply the Y combinator to a lambda of the binding's name
-        body = {
-            let f = self.get_y_combinator();
-            let value = Node::lambda(var_id, body);
-            Node::apply(EvalStrat::Value, f, value)
+        // tail recursive functions get a cheaper fixpoint, without the Y combinator
+        let tail_recursive = match self.tail_recursion {
+            true => compile_tail_recursive(var_id, &params, &body, &mut self.source_map),
+            false => None,
+        };
+        body = match tail_recursive {
+            Some(body) => {
+                self.tail_recursive_count += 1;
+                body
+            }
+            // if the function is recursive, apply the Y combinator to a lambda of the binding's name
+            None => {
+                let body = params
+                    .iter()
+                    .rev()
+                    .fold(body, |body, var| Node::lambda(*var, body));
+                let f = self.get_y_combinator();
+                let value = Node::lambda(var_id, body);
+                Node::apply(EvalStrat::Value, f, value)
+            }
         };
-        (var_id, body)
+        self.source_map.insert(&body, binding.span);
+        Ok((var_id, body))
     }
 
-    fn compile_node(&mut self, source: &LNode) -> NodeRef {
-        Rc::new(match source {
+    fn compile_node(&mut self, source: &LNodeRef) -> CompileResult<NodeRef> {
+        let node = Rc::new(match source.as_ref() {
+            super::LNode::Litteral(Value::Str(s)) if !s.chars().all(is_icfp_char) => {
+                let c = s.chars().find(|c| !is_icfp_char(*c)).unwrap();
+                return Err(
+                    Diagnostic::new(format!("ICFP strings can't hold {c:?}"), source.span)
+                        .with_note("tabs are only supported by the interpreter"),
+                );
+            }
             super::LNode::Litteral(val) => Node::Value(val.clone()),
-            super::LNode::Variable(var) => Node::Variable(self.resolve(var)),
-            super::LNode::Apply { func, param } => Node::Apply {
-                strat: EvalStrat::Name,
-                f: self.compile_node(func),
-                value: self.compile_node(param),
+            super::LNode::Variable(var) => match self.consts.get(var) {
+                Some(val) => Node::Value(val.clone()),
+                None => Node::Variable(self.resolve(var)),
+            },
+            // builtins are only meaningful to the interpreter
+            super::LNode::Apply { func, param, .. }
+                if builtin_call(func, |name| self.bound.contains(name)).is_some() =>
+            {
+                return self.compile_node(param);
+            }
+            super::LNode::Apply { strat, func, param } => Node::Apply {
+                strat: *strat,
+                f: self.compile_node(func)?,
+                value: self.compile_node(param)?,
             },
             super::LNode::UnuaryOp { op, body } => Node::UnuaryOp {
                 op: *op,
-                body: self.compile_node(body),
+                body: self.compile_node(body)?,
             },
             super::LNode::BinaryOp { op, left, right } => Node::BinaryOp {
                 op: *op,
-                left: self.compile_node(left),
-                right: self.compile_node(right),
+                left: self.compile_node(left)?,
+                right: self.compile_node(right)?,
             },
             super::LNode::If {
                 cond,
                 then_do,
                 else_do,
             } => Node::If {
-                cond: self.compile_node(cond),
-                then_do: self.compile_node(then_do),
-                else_do: self.compile_node(else_do),
+                cond: self.compile_node(cond)?,
+                then_do: self.compile_node(then_do)?,
+                else_do: self.compile_node(else_do)?,
             },
-            super::LNode::Let { bindings, body } => {
-                let bindings: Vec<_> = bindings
-                    .iter()
-                    .map(|binding| self.compile_binding(binding))
-                    .collect();
-                let body = self.compile_node(body);
-                return bindings
-                    .iter()
-                    .rev()
-                    .fold(body, |acc, (var_id, var_value)| {
-                        let var = *var_id;
-                        let value = var_value.clone();
-                        Node::bind(var, value, acc)
-                    });
+            super::LNode::Let {
+                strat,
+                bindings,
+                body,
+            } => {
+                let consts = self.consts.clone();
+                let bound = self.bound.clone();
+                let res = self.compile_let(*strat, bindings, body);
+                self.consts = consts;
+                self.bound = bound;
+                return res;
             }
-        })
+            super::LNode::Tuple(_)
+            | super::LNode::Nil
+            | super::LNode::Cons { .. }
+            | super::LNode::Match { .. } => {
+                let node = self.compile_data(source)?;
+                self.source_map.insert(&node, source.span);
+                return Ok(node);
+            }
+        });
+        self.source_map.insert(&node, source.span);
+        Ok(node)
+    }
+
+    // compiles a match arm, the names of the pattern are bound by lambdas
+    fn compile_arm(&mut self, pattern: &Pattern, body: &LNodeRef) -> CompileResult<NodeRef> {
+        let names = pattern.names();
+        let consts = self.consts.clone();
+        let bound = self.bound.clone();
+        for name in names.iter() {
+            self.consts.remove(name);
+            self.bound.insert(name.clone());
+        }
+        let body = self.compile_node(body);
+        self.consts = consts;
+        self.bound = bound;
+        let mut body = body?;
+        for name in names.iter().rev() {
+            body = Node::lambda(self.resolve(name), body);
+        }
+        Ok(body)
+    }
+
+    // tuples and lists use Scott encodings:
+    // (a, b) is λf. f a b, nil is λn. λc. n and cons h t is λn. λc. c h t.
+    // items are lazy, like in the interpreter, so they are computed at most once
+    fn compile_data(&mut self, source: &LNodeRef) -> CompileResult<NodeRef> {
+        let apply = |f, value| Node::apply(EvalStrat::Lazy, f, value);
+        match source.as_ref() {
+            LNode::Tuple(items) => {
+                let f = self.allocate_varid();
+                let mut body = Node::var(f);
+                for item in items {
+                    body = apply(body, self.compile_node(item)?);
+                }
+                Ok(Node::lambda(f, body))
+            }
+            LNode::Nil => {
+                let n = self.allocate_varid();
+                let c = self.allocate_varid();
+                Ok(Node::lambda(n, Node::lambda(c, Node::var(n))))
+            }
+            LNode::Cons { head, tail } => {
+                let n = self.allocate_varid();
+                let c = self.allocate_varid();
+                let body = apply(
+                    apply(Node::var(c), self.compile_node(head)?),
+                    self.compile_node(tail)?,
+                );
+                Ok(Node::lambda(n, Node::lambda(c, body)))
+            }
+            LNode::Match { value, arms } => {
+                let value = self.compile_node(value)?;
+                let arm = |pred: fn(&Pattern) -> bool| {
+                    let mut matching = arms.iter().filter(|(pattern, _)| pred(pattern));
+                    match (matching.next(), matching.next()) {
+                        (_, Some((_, body))) => Err(Diagnostic::new(
+                            "this pattern is already matched",
+                            body.span,
+                        )),
+                        (arm, None) => Ok(arm),
+                    }
+                };
+
+                // a tuple is applied to a function of its items
+                if let Some((pattern, body)) = arm(|p| matches!(p, Pattern::Tuple(_)))? {
+                    if arms.len() > 1 {
+                        return Err(Diagnostic::new(
+                            "tuples can't be matched along with lists",
+                            source.span,
+                        ));
+                    }
+                    let body = self.compile_arm(pattern, body)?;
+                    return Ok(apply(value, body));
+                }
+
+                // a list is applied to the nil case, then to a function of the head and the tail
+                let missing = |name| {
+                    Diagnostic::new(format!("the match doesn't handle {}", name), source.span)
+                };
+                let (nil_pattern, nil_body) =
+                    arm(|p| matches!(p, Pattern::Nil))?.ok_or_else(|| missing("nil"))?;
+                let (cons_pattern, cons_body) =
+                    arm(|p| matches!(p, Pattern::Cons(..)))?.ok_or_else(|| missing("cons"))?;
+                let nil_body = self.compile_arm(nil_pattern, nil_body)?;
+                let cons_body = self.compile_arm(cons_pattern, cons_body)?;
+                Ok(apply(apply(value, nil_body), cons_body))
+            }
+            _ => unreachable!("not a data node"),
+        }
+    }
+
+    fn compile_let(
+        &mut self,
+        strat: EvalStrat,
+        bindings: &[Binding],
+        body: &LNodeRef,
+    ) -> CompileResult<NodeRef> {
+        let live = live_bindings(bindings, body);
+        let mut compiled = vec![];
+        for (i, binding) in bindings.iter().enumerate() {
+            if !live[i] {
+                continue;
+            }
+            if binding.constant {
+                let value = self.eval_const(binding, &bindings[..i])?;
+                self.consts.insert(binding.name.clone(), value);
+                self.bound.insert(binding.name.clone());
+                continue;
+            }
+
+            // bindings shadow constants, functions from their own body
+            if !binding.params.is_empty() {
+                self.consts.remove(&binding.name);
+                self.bound.insert(binding.name.clone());
+            }
+            compiled.push((binding.span, self.compile_binding(binding)?));
+            self.consts.remove(&binding.name);
+            self.bound.insert(binding.name.clone());
+        }
+        let body = self.compile_node(body)?;
+        Ok(compiled
+            .iter()
+            .rev()
+            .fold(body, |acc, (span, (var_id, var_value))| {
+                let f = Node::lambda(*var_id, acc);
+                let node = Node::apply(strat, f, var_value.clone());
+                self.source_map.insert(&node, *span);
+                node
+            }))
     }
 
-    pub fn compile(&mut self, source: LNodeRef) -> NodeRef {
-        let res = self.compile_node(source.as_ref());
-        if let Some((id, node)) = &self.y_combinator {
+    pub fn compile(&mut self, source: LNodeRef) -> CompileResult<NodeRef> {
+        let res = self.compile_node(&source)?;
+        let res = if let Some((id, node)) = &self.y_combinator {
             {
                 let var = *id;
                 let value = node.clone();
@@ -165,10 +458,189 @@ impl Compiler {
             }
         } else {
             res
+        };
+        let res = fold_constants(res, &mut self.source_map);
+        if self.optimize {
+            return Ok(optimize(res, &mut self.source_map));
+        }
+        Ok(res)
+    }
+}
+
+// collects all the variable names used in a node, ignoring shadowing
+fn collect_variables(node: &LNode, names: &mut HashSet<Iden>) {
+    match node {
+        LNode::Litteral(_) => {}
+        LNode::Variable(iden) => {
+            names.insert(iden.clone());
+        }
+        LNode::Let { bindings, body, .. } => {
+            for binding in bindings {
+                collect_variables(&binding.value, names);
+            }
+            collect_variables(body, names);
+        }
+        LNode::Apply { func, param, .. } => {
+            collect_variables(func, names);
+            collect_variables(param, names);
+        }
+        LNode::BinaryOp { left, right, .. } => {
+            collect_variables(left, names);
+            collect_variables(right, names);
+        }
+        LNode::UnuaryOp { body, .. } => collect_variables(body, names),
+        LNode::If {
+            cond,
+            then_do,
+            else_do,
+        } => {
+            collect_variables(cond, names);
+            collect_variables(then_do, names);
+            collect_variables(else_do, names);
+        }
+        LNode::Tuple(items) => {
+            for item in items {
+                collect_variables(item, names);
+            }
+        }
+        LNode::Nil => {}
+        LNode::Cons { head, tail } => {
+            collect_variables(head, names);
+            collect_variables(tail, names);
+        }
+        LNode::Match { value, arms } => {
+            collect_variables(value, names);
+            for (_, body) in arms {
+                collect_variables(body, names);
+            }
+        }
+    }
+}
+
+// finds the bindings which are used by the body or by any binding in use (dead code elimination)
+// constants are evaluated at compile time, what they use isn't needed at runtime
+fn live_bindings(bindings: &[Binding], body: &LNode) -> Vec<bool> {
+    let mut live = HashSet::new();
+    collect_variables(body, &mut live);
+
+    let mut res = vec![false; bindings.len()];
+    for (i, binding) in bindings.iter().enumerate().rev() {
+        if live.contains(&binding.name) {
+            if !binding.constant {
+                collect_variables(&binding.value, &mut live);
+            }
+            res[i] = true;
+        }
+    }
+    res
+}
+
+fn compile_checked(source: LNodeRef, compiler: &mut Compiler) -> CompileResult<NodeRef> {
+    type_check(&source)?;
+    let span = source.span;
+    let node = compiler.compile(source)?;
+    compiler.check_closed(&node, |iden| format!("unbound variable {}", iden), span)?;
+    Ok(node)
+}
+
+// type checks and compiles a program, along with a map from the compiled nodes to the source
+pub fn compile_with_source_map(source: LNodeRef) -> Result<(NodeRef, SourceMap), Diagnostic> {
+    let mut compiler = Compiler::new();
+    let node = compile_checked(source, &mut compiler)?;
+    Ok((node, compiler.source_map))
+}
+
+// compiles a program as small as possible, along with its source map and the size of each top
+// level binding
+pub fn compile_optimized(
+    source: LNodeRef,
+) -> Result<(NodeRef, SourceMap, Vec<BindingSize>), Diagnostic> {
+    compile_optimized_with_limit(source, EVALUATION_LIMIT)
+}
+
+// compiles a program as small as possible, giving up on constants which take more than
+// const_eval_limit reductions to evaluate
+pub fn compile_optimized_with_limit(
+    source: LNodeRef,
+    const_eval_limit: u32,
+) -> Result<(NodeRef, SourceMap, Vec<BindingSize>), Diagnostic> {
+    let minimized = |tail_recursion| {
+        let mut compiler = Compiler::new();
+        compiler.optimize = true;
+        compiler.const_eval_limit = const_eval_limit;
+        compiler.tail_recursion = tail_recursion;
+        let node = compile_checked(source.clone(), &mut compiler)?;
+        Ok((node, compiler))
+    };
+    let (mut node, mut compiler) = minimized(true)?;
+
+    // the y combinator is shared by all the recursive functions, so using it for the tail
+    // recursive ones too may be smaller
+    if compiler.tail_recursive_count > 0 {
+        let (y_node, y_compiler) = minimized(false)?;
+        if size(&y_node) < size(&node) {
+            (node, compiler) = (y_node, y_compiler);
         }
     }
+    let y_combinator = compiler.y_combinator.as_ref().map(|(id, _)| *id);
+    let sizes = binding_sizes(&source, &node, y_combinator, &compiler.source_map);
+    Ok((node, compiler.source_map, sizes))
+}
+
+pub fn compile(source: LNodeRef) -> Result<NodeRef, Diagnostic> {
+    compile_with_source_map(source).map(|(node, _)| node)
 }
 
-pub fn compile(source: LNodeRef) -> NodeRef {
-    Compiler::new().compile(source)
+// evaluates a compiled program along with its number of beta reductions. when an operator
+// fails, the LASM code it comes from is quoted
+pub fn evaluate(node: NodeRef, source_map: &SourceMap) -> Result<(Value, u32), Diagnostic> {
+    evaluate_traced(node.clone()).map_err(|err| {
+        let span = err
+            .node
+            .and_then(|node| source_map.get(&node))
+            .or_else(|| source_map.get(&node))
+            .unwrap_or_default();
+        Diagnostic::new(format!("evaluation failed: {}", err.message), span)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::icfp::serialize_str;
+    use crate::lasm::parse;
+
+    use super::*;
+
+    #[test]
+    fn test_y_combinator_candidate() {
+        let tail = r#"
+            let rec count n acc = if n < 1 { acc } else { count (n - 1) (acc + n) };
+                rec fact n = if n < 2 { 1 } else { n * fact (n - 1) };
+            in count 10 0
+        "#;
+        let node = parse(tail).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.tail_recursion = false;
+        let compiled = compile_checked(node.clone(), &mut compiler).unwrap();
+        let (optimized, _, sizes) = compile_optimized(node).unwrap();
+        assert!(serialize_str(optimized).len() + 40 < serialize_str(compiled).len());
+        assert!(sizes.iter().all(|s| s.name != "<y combinator>"));
+
+        // once other functions need the y combinator, tail recursive ones share it
+        let shared = r#"
+            let rec count n acc = if n < 1 { acc } else { count (n - 1) (acc + n) };
+                rec fact n = if n < 2 { 1 } else { n * fact (n - 1) };
+                rec fib n = if n < 2 { n } else { fib (n - 1) + fib (n - 2) };
+            in count 10 (fact 3 + fib 4)
+        "#;
+        let node = parse(shared).unwrap();
+        let (optimized, _, sizes) = compile_optimized(node.clone()).unwrap();
+        let compiled = compile(node).unwrap();
+        assert_eq!(
+            crate::icfp::evaluate(optimized.clone()).as_int(),
+            &64.into()
+        );
+        assert!(serialize_str(optimized).len() < serialize_str(compiled).len());
+        assert!(sizes.iter().any(|s| s.name == "<y combinator>"));
+    }
 }
@@ -0,0 +1,185 @@
+use std::rc::Rc;
+
+use num::{Signed, Zero};
+
+use crate::icfp::{evaluate, serialize_str, BinaryOp, Node, NodeRef, UnuaryOp, Value};
+
+use super::source::SourceMap;
+
+// whether evaluating the operator on these operands succeeds
+pub fn binary_op_is_valid(op: BinaryOp, left: &Value, right: &Value) -> bool {
+    match (op, left, right) {
+        (BinaryOp::IntDiv | BinaryOp::IntMod, Value::Int(_), Value::Int(r)) => !r.is_zero(),
+        (
+            BinaryOp::IntAdd
+            | BinaryOp::IntSub
+            | BinaryOp::IntMul
+            | BinaryOp::IntLt
+            | BinaryOp::IntGt,
+            Value::Int(_),
+            Value::Int(_),
+        ) => true,
+        (BinaryOp::BoolOr | BinaryOp::BoolAnd, Value::Bool(_), Value::Bool(_)) => true,
+        (BinaryOp::StrConcat, Value::Str(_), Value::Str(_)) => true,
+        (BinaryOp::StrTake | BinaryOp::StrDrop, Value::Int(_), Value::Str(_)) => true,
+        (BinaryOp::Eq, left, right) => {
+            std::mem::discriminant(left) == std::mem::discriminant(right)
+        }
+        _ => false,
+    }
+}
+
+pub fn unuary_op_is_valid(op: UnuaryOp, body: &Value) -> bool {
+    match (op, body) {
+        (UnuaryOp::IntNeg, Value::Int(_)) => true,
+        (UnuaryOp::BoolNot, Value::Bool(_)) => true,
+        (UnuaryOp::StrToInt, Value::Str(_)) => true,
+        (UnuaryOp::IntToStr, Value::Int(i)) => !i.is_negative(),
+        _ => false,
+    }
+}
+
+fn size(node: &NodeRef) -> usize {
+    serialize_str(node.clone()).len()
+}
+
+// folds a node, returning the smallest equivalent node along with its value if it is constant
+fn fold(node: &NodeRef, source_map: &mut SourceMap) -> (NodeRef, Option<Value>) {
+    let (folded, value) = fold_children(node, source_map);
+
+    // keep track of where the new node comes from
+    if !Rc::ptr_eq(node, &folded) {
+        if let Some(span) = source_map.get(node) {
+            source_map.insert(&folded, span);
+        }
+    }
+    (folded, value)
+}
+
+// rebuilds a node from its folded children, if any of them changed
+fn rebuild(
+    node: &NodeRef,
+    children: &[&NodeRef],
+    new_children: &[&NodeRef],
+    f: impl FnOnce() -> Node,
+) -> NodeRef {
+    if children
+        .iter()
+        .zip(new_children.iter())
+        .all(|(a, b)| Rc::ptr_eq(a, b))
+    {
+        node.clone()
+    } else {
+        Rc::new(f())
+    }
+}
+
+fn fold_children(node: &NodeRef, source_map: &mut SourceMap) -> (NodeRef, Option<Value>) {
+    let (folded, value) = match node.as_ref() {
+        Node::Value(value) => return (node.clone(), Some(value.clone())),
+        Node::Variable(_) => return (node.clone(), None),
+        Node::Lambda { var, body } => {
+            let (new_body, _) = fold(body, source_map);
+            let folded = rebuild(node, &[body], &[&new_body], || Node::Lambda {
+                var: *var,
+                body: new_body.clone(),
+            });
+            (folded, None)
+        }
+        // applications are left alone, as evaluating them may not terminate
+        Node::Apply { strat, f, value } => {
+            let (new_f, _) = fold(f, source_map);
+            let (new_value, _) = fold(value, source_map);
+            let folded = rebuild(node, &[f, value], &[&new_f, &new_value], || Node::Apply {
+                strat: *strat,
+                f: new_f.clone(),
+                value: new_value.clone(),
+            });
+            (folded, None)
+        }
+        Node::BinaryOp { op, left, right } => {
+            let (new_left, left_value) = fold(left, source_map);
+            let (new_right, right_value) = fold(right, source_map);
+            let folded = rebuild(node, &[left, right], &[&new_left, &new_right], || {
+                Node::BinaryOp {
+                    op: *op,
+                    left: new_left.clone(),
+                    right: new_right.clone(),
+                }
+            });
+            match (left_value, right_value) {
+                (Some(l), Some(r)) if binary_op_is_valid(*op, &l, &r) => {
+                    let constant = Rc::new(Node::BinaryOp {
+                        op: *op,
+                        left: Rc::new(Node::Value(l)),
+                        right: Rc::new(Node::Value(r)),
+                    });
+                    (folded, Some(evaluate(constant)))
+                }
+                _ => (folded, None),
+            }
+        }
+        Node::UnuaryOp { op, body } => {
+            let (new_body, body_value) = fold(body, source_map);
+            let folded = rebuild(node, &[body], &[&new_body], || Node::UnuaryOp {
+                op: *op,
+                body: new_body.clone(),
+            });
+            match body_value {
+                Some(v) if unuary_op_is_valid(*op, &v) => {
+                    let constant = Rc::new(Node::UnuaryOp {
+                        op: *op,
+                        body: Rc::new(Node::Value(v)),
+                    });
+                    (folded, Some(evaluate(constant)))
+                }
+                _ => (folded, None),
+            }
+        }
+        Node::If {
+            cond,
+            then_do,
+            else_do,
+        } => {
+            let (new_cond, cond_value) = fold(cond, source_map);
+            let (new_then_do, then_value) = fold(then_do, source_map);
+            let (new_else_do, else_value) = fold(else_do, source_map);
+            // a known condition always makes the node smaller
+            match cond_value {
+                Some(Value::Bool(true)) => return (new_then_do, then_value),
+                Some(Value::Bool(false)) => return (new_else_do, else_value),
+                _ => (
+                    rebuild(
+                        node,
+                        &[cond, then_do, else_do],
+                        &[&new_cond, &new_then_do, &new_else_do],
+                        || Node::If {
+                            cond: new_cond.clone(),
+                            then_do: new_then_do.clone(),
+                            else_do: new_else_do.clone(),
+                        },
+                    ),
+                    None,
+                ),
+            }
+        }
+    };
+
+    // only inline the value if it is smaller than the expression computing it
+    match value {
+        Some(value) => {
+            let constant = Rc::new(Node::Value(value.clone()));
+            if size(&constant) < size(&folded) {
+                (constant, Some(value))
+            } else {
+                (folded, Some(value))
+            }
+        }
+        None => (folded, None),
+    }
+}
+
+// evaluates the closed operator subtrees of a node at compile time, when it makes the node smaller
+pub fn fold_constants(node: NodeRef, source_map: &mut SourceMap) -> NodeRef {
+    fold(&node, source_map).0
+}
@@ -0,0 +1,735 @@
+use num::Signed;
+
+use super::{
+    ast::{BinaryOp, Binding, EvalStrat, Pattern, Program, Test, UnuaryOp, Value},
+    parser::parse_program,
+    source::Diagnostic,
+    LNode, LNodeRef,
+};
+
+// the maximum width of a line, longer expressions are split
+const WIDTH: usize = 100;
+const INDENT: usize = 4;
+
+// where an expression appears, which decides whether it needs parentheses
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Position {
+    // a let body, a binding value, or inside parentheses or braces
+    Expr,
+    // the operand of a prefix operator
+    Prefixed,
+    // the operand of a binary operator
+    Operand,
+    // the function of an application
+    Head,
+    // the parameter of an application
+    Arg,
+}
+
+fn needs_parens(node: &LNode, pos: Position) -> bool {
+    match node {
+        LNode::Let { .. } => pos != Position::Expr,
+        // prefix operators bind tighter than binary ones, and looser than applications
+        LNode::UnuaryOp { .. } => matches!(pos, Position::Head | Position::Arg),
+        LNode::BinaryOp { .. } => pos != Position::Expr,
+        // applications in head position are only nested when their strategies differ
+        LNode::Apply { .. } | LNode::If { .. } | LNode::Match { .. } => {
+            matches!(pos, Position::Head | Position::Arg)
+        }
+        LNode::Cons { .. } => {
+            list_items(node).is_none() && matches!(pos, Position::Head | Position::Arg)
+        }
+        // `f -1` is a subtraction
+        LNode::Litteral(Value::Int(val)) => {
+            val.is_negative() && matches!(pos, Position::Head | Position::Arg)
+        }
+        LNode::Litteral(_) | LNode::Variable(_) | LNode::Tuple(_) | LNode::Nil => false,
+    }
+}
+
+// the items of a list ending with nil, which is written [a, b, c]
+fn list_items(node: &LNode) -> Option<Vec<&LNodeRef>> {
+    let mut items = vec![];
+    let mut node = node;
+    loop {
+        match node {
+            LNode::Nil => return Some(items),
+            LNode::Cons { head, tail } => {
+                items.push(head);
+                node = tail;
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Tuple(names) => {
+            let names: Vec<_> = names.iter().map(|name| name.to_string()).collect();
+            format!("({})", names.join(", "))
+        }
+        Pattern::Nil => "nil".to_owned(),
+        Pattern::Cons(head, tail) => format!("cons {head} {tail}"),
+    }
+}
+
+fn quote(s: &str) -> String {
+    // multi-line strings are kept raw, the opening newline is skipped by the parser
+    if s.trim_end_matches('\n').contains('\n') {
+        let mut hashes = String::new();
+        while s.contains(&format!("\"{hashes}")) {
+            hashes.push('#');
+        }
+        return format!("r{hashes}\"\n{s}\"{hashes}");
+    }
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    format!("\"{escaped}\"")
+}
+
+fn litteral(val: &Value) -> String {
+    match val {
+        Value::Str(s) => quote(s),
+        Value::Int(i) => i.to_string(),
+        Value::Bool(b) => b.to_string(),
+    }
+}
+
+pub fn binary_op(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::IntAdd => "+",
+        BinaryOp::IntSub => "-",
+        BinaryOp::IntMul => "*",
+        BinaryOp::IntDiv => "/",
+        BinaryOp::IntMod => "%",
+        BinaryOp::IntLt => "<",
+        BinaryOp::IntGt => ">",
+        BinaryOp::BoolOr => "|",
+        BinaryOp::BoolAnd => "&",
+        BinaryOp::StrConcat => ".",
+        BinaryOp::StrTake => "take",
+        BinaryOp::StrDrop => "drop",
+        BinaryOp::Eq => "==",
+    }
+}
+
+pub fn unuary_op(op: UnuaryOp) -> &'static str {
+    match op {
+        UnuaryOp::IntNeg => "-",
+        UnuaryOp::BoolNot => "!",
+        UnuaryOp::StrToInt => "str2int",
+        UnuaryOp::IntToStr => "int2str",
+    }
+}
+
+fn apply_marker(strat: EvalStrat) -> &'static str {
+    match strat {
+        EvalStrat::Name => "",
+        EvalStrat::Value => "!",
+        EvalStrat::Lazy => "~",
+    }
+}
+
+// lets are strict by default, there is no way to write a call by name let
+fn let_marker(strat: EvalStrat) -> &'static str {
+    match strat {
+        EvalStrat::Name | EvalStrat::Value => "",
+        EvalStrat::Lazy => "~",
+    }
+}
+
+// `f a b` is parsed as `(f a) b`, returns f along with [a, b]
+fn apply_chain(node: &LNodeRef) -> (EvalStrat, &LNodeRef, Vec<&LNodeRef>) {
+    let LNode::Apply { strat, .. } = node.as_ref() else {
+        unreachable!("not an application");
+    };
+    let mut head = node;
+    let mut args = vec![];
+    while let LNode::Apply {
+        strat: s,
+        func,
+        param,
+    } = head.as_ref()
+    {
+        if s != strat {
+            break;
+        }
+        args.push(param);
+        head = func;
+    }
+    args.reverse();
+    (*strat, head, args)
+}
+
+// `a + b - c` is parsed as `(a + b) - c`, returns a along with [(+, b), (-, c)]
+fn operator_chain(node: &LNodeRef) -> (&LNodeRef, Vec<(BinaryOp, &LNodeRef)>) {
+    let mut left = node;
+    let mut rest = vec![];
+    while let LNode::BinaryOp {
+        op,
+        left: l,
+        right: r,
+    } = left.as_ref()
+    {
+        // take and drop have their operands reversed
+        let (l, r) = match op {
+            BinaryOp::StrTake | BinaryOp::StrDrop => (r, l),
+            _ => (l, r),
+        };
+        rest.push((*op, r));
+        left = l;
+    }
+    rest.reverse();
+    (left, rest)
+}
+
+fn binding_head(binding: &Binding) -> String {
+    let mut res = String::new();
+    if binding.constant {
+        res.push_str("const ");
+    }
+    if binding.rec {
+        res.push_str("rec ");
+    }
+    res.push_str(&binding.name.to_string());
+    for param in binding.params.iter() {
+        res.push(' ');
+        res.push_str(&param.to_string());
+    }
+    res.push_str(" = ");
+    res
+}
+
+// an expression on a single line
+fn flat(node: &LNodeRef, pos: Position) -> String {
+    if needs_parens(node, pos) {
+        return format!("({})", flat(node, Position::Expr));
+    }
+    match node.as_ref() {
+        LNode::Litteral(val) => litteral(val),
+        LNode::Variable(name) => name.to_string(),
+        LNode::Let {
+            strat,
+            bindings,
+            body,
+        } => {
+            let mut res = format!("let{}", let_marker(*strat));
+            for binding in bindings {
+                res.push(' ');
+                res.push_str(&binding_head(binding));
+                res.push_str(&flat(&binding.value, Position::Expr));
+                res.push(';');
+            }
+            format!("{res} in {}", flat(body, Position::Expr))
+        }
+        LNode::Apply { .. } => {
+            let (strat, head, args) = apply_chain(node);
+            let mut res = format!("{}{}", flat(head, Position::Head), apply_marker(strat));
+            for arg in args {
+                res.push(' ');
+                res.push_str(&flat(arg, Position::Arg));
+            }
+            res
+        }
+        LNode::BinaryOp { .. } => {
+            let (first, rest) = operator_chain(node);
+            let mut res = flat(first, Position::Operand);
+            for (op, operand) in rest {
+                res.push_str(&format!(
+                    " {} {}",
+                    binary_op(op),
+                    flat(operand, Position::Operand)
+                ));
+            }
+            res
+        }
+        LNode::UnuaryOp { op, body } => {
+            format!(
+                "{}{}",
+                unuary_op(*op),
+                prefixed_body(*op, flat(body, Position::Prefixed))
+            )
+        }
+        LNode::If {
+            cond,
+            then_do,
+            else_do,
+        } => format!(
+            "if {} {{ {} }} else {{ {} }}",
+            flat(cond, Position::Expr),
+            flat(then_do, Position::Expr),
+            flat(else_do, Position::Expr)
+        ),
+        LNode::Tuple(items) => {
+            let items: Vec<_> = items.iter().map(|i| flat(i, Position::Expr)).collect();
+            format!("({})", items.join(", "))
+        }
+        LNode::Nil => "nil".to_owned(),
+        LNode::Cons { head, tail } => match list_items(node) {
+            Some(items) => {
+                let items: Vec<_> = items.iter().map(|i| flat(i, Position::Expr)).collect();
+                format!("[{}]", items.join(", "))
+            }
+            None => format!(
+                "cons {} {}",
+                flat(head, Position::Arg),
+                flat(tail, Position::Arg)
+            ),
+        },
+        LNode::Match { value, arms } => {
+            let arms: Vec<_> = arms
+                .iter()
+                .map(|(p, body)| format!("{} => {}", pattern(p), flat(body, Position::Expr)))
+                .collect();
+            format!(
+                "match {} {{ {} }}",
+                flat(value, Position::Expr),
+                arms.join(", ")
+            )
+        }
+    }
+}
+
+// symbolic prefix operators are glued to their operand, which must not merge with them.
+// word operators are separated from it like function names
+fn prefixed_body(op: UnuaryOp, rendered: String) -> String {
+    match op {
+        UnuaryOp::IntNeg if rendered.starts_with(|c: char| c.is_ascii_digit()) => {
+            format!("({rendered})")
+        }
+        UnuaryOp::IntNeg | UnuaryOp::BoolNot => rendered,
+        UnuaryOp::StrToInt | UnuaryOp::IntToStr => format!(" {rendered}"),
+    }
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    // the comments of the source along with their offset, in order
+    comments: Vec<(usize, String)>,
+    next_comment: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            comments: find_comments(source),
+            next_comment: 0,
+        }
+    }
+
+    fn has_comments(&self, start: usize, end: usize) -> bool {
+        self.comments[self.next_comment..]
+            .iter()
+            .any(|(pos, _)| start <= *pos && *pos < end)
+    }
+
+    fn in_comment(&self, offset: usize) -> bool {
+        self.comments
+            .iter()
+            .any(|(pos, text)| *pos <= offset && offset < pos + text.len())
+    }
+
+    // whether an item starts its line, and follows an empty line
+    fn blank_before(&self, offset: usize) -> bool {
+        let before = &self.source[..offset.min(self.source.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        if line_start == 0 || !before[line_start..].trim().is_empty() {
+            return false;
+        }
+        let previous = &before[..line_start - 1];
+        previous[previous.rfind('\n').map_or(0, |i| i + 1)..]
+            .trim()
+            .is_empty()
+    }
+
+    // writes the comments found before an offset, keeping the empty lines which separate them
+    fn flush_comments(&mut self, offset: usize, indent: usize, first: &mut bool, out: &mut String) {
+        while let Some((pos, text)) = self.comments.get(self.next_comment).cloned() {
+            if pos >= offset {
+                break;
+            }
+            self.next_comment += 1;
+            if !*first && self.blank_before(pos) {
+                out.push('\n');
+            }
+            *first = false;
+            out.push_str(&" ".repeat(indent));
+            out.push_str(&text);
+            out.push('\n');
+        }
+    }
+
+    // prepares the line of an item which starts at offset, comments come with the item that follows
+    // them unless they end the line of the previous one
+    fn item(&mut self, offset: usize, indent: usize, first: &mut bool, out: &mut String) {
+        self.flush_comments(offset, indent, first, out);
+        if !*first && self.blank_before(offset) {
+            out.push('\n');
+        }
+        *first = false;
+        out.push_str(&" ".repeat(indent));
+    }
+
+    // appends the comment which follows an item ending at offset on the same line, if any
+    fn trailing_comment(&mut self, offset: usize, out: &mut String) {
+        // spans may extend over the comments which follow the code
+        let mut end = offset.min(self.source.len());
+        loop {
+            end = self.source[..end].trim_end().len();
+            match self
+                .comments
+                .iter()
+                .find(|(pos, text)| pos + text.len() == end)
+            {
+                Some((pos, _)) => end = *pos,
+                None => break,
+            }
+        }
+        let Some((pos, text)) = self.comments.get(self.next_comment) else {
+            return;
+        };
+        if *pos < end || self.source[end..*pos].contains('\n') {
+            return;
+        }
+        out.push_str("  ");
+        out.push_str(text);
+        self.next_comment += 1;
+    }
+
+    // where a binding starts, including its keywords
+    fn binding_start(&self, binding: &Binding) -> usize {
+        if binding.rec {
+            self.keyword_before("rec", binding.span.start)
+        } else if binding.constant {
+            self.keyword_before("const", binding.span.start)
+        } else {
+            binding.span.start
+        }
+    }
+
+    // lets are split when comments are found among their bindings
+    fn needs_split(&self, node: &LNodeRef) -> bool {
+        match node.as_ref() {
+            LNode::Litteral(_) | LNode::Variable(_) => false,
+            LNode::Let { bindings, body, .. } => {
+                self.has_comments(node.span.start, body.span.start)
+                    || bindings.iter().any(|b| self.needs_split(&b.value))
+                    || self.needs_split(body)
+            }
+            LNode::Apply { func, param, .. } => self.needs_split(func) || self.needs_split(param),
+            LNode::BinaryOp { left, right, .. } => {
+                self.needs_split(left) || self.needs_split(right)
+            }
+            LNode::UnuaryOp { body, .. } => self.needs_split(body),
+            LNode::If {
+                cond,
+                then_do,
+                else_do,
+            } => self.needs_split(cond) || self.needs_split(then_do) || self.needs_split(else_do),
+            LNode::Tuple(items) => items.iter().any(|item| self.needs_split(item)),
+            LNode::Nil => false,
+            LNode::Cons { head, tail } => self.needs_split(head) || self.needs_split(tail),
+            LNode::Match { value, arms } => {
+                self.needs_split(value) || arms.iter().any(|(_, body)| self.needs_split(body))
+            }
+        }
+    }
+
+    // items on their own lines, between delimiters
+    fn split_items(
+        &mut self,
+        open: &str,
+        items: &[&LNodeRef],
+        close: &str,
+        indent: usize,
+    ) -> String {
+        let inner = indent + INDENT;
+        let items: Vec<_> = items
+            .iter()
+            .map(|item| {
+                format!(
+                    "{}{}",
+                    " ".repeat(inner),
+                    self.expr(item, Position::Expr, inner, inner)
+                )
+            })
+            .collect();
+        format!(
+            "{open}\n{}\n{}{close}",
+            items.join(",\n"),
+            " ".repeat(indent)
+        )
+    }
+
+    // an expression starting at column, continuation lines are indented relative to indent
+    fn expr(&mut self, node: &LNodeRef, pos: Position, indent: usize, column: usize) -> String {
+        if needs_parens(node, pos) {
+            return format!("({})", self.expr(node, Position::Expr, indent, column + 1));
+        }
+
+        let single_line = flat(node, pos);
+        if column + single_line.len() <= WIDTH && !self.needs_split(node) {
+            return single_line;
+        }
+
+        let inner = indent + INDENT;
+        let newline = |indent: usize| format!("\n{}", " ".repeat(indent));
+        match node.as_ref() {
+            LNode::Litteral(_) | LNode::Variable(_) => single_line,
+            LNode::Let {
+                strat,
+                bindings,
+                body,
+            } => {
+                let mut res = format!("let{}\n", let_marker(*strat));
+                let mut first = true;
+                for binding in bindings {
+                    self.item(self.binding_start(binding), inner, &mut first, &mut res);
+                    res.push_str(&self.binding(binding, inner));
+                    self.trailing_comment(binding.value.span.end, &mut res);
+                    res.push('\n');
+                }
+                self.flush_comments(body.span.start, inner, &mut first, &mut res);
+                res.push_str(&" ".repeat(indent));
+                res.push_str("in ");
+                res.push_str(&self.expr(body, Position::Expr, indent, indent + 3));
+                res
+            }
+            LNode::Apply { .. } => {
+                let (strat, head, args) = apply_chain(node);
+                let mut res = self.expr(head, Position::Head, indent, column);
+                res.push_str(apply_marker(strat));
+                for arg in args {
+                    res.push_str(&newline(inner));
+                    res.push_str(&self.expr(arg, Position::Arg, inner, inner));
+                }
+                res
+            }
+            LNode::BinaryOp { .. } => {
+                let (first, rest) = operator_chain(node);
+                let mut res = self.expr(first, Position::Operand, indent, column);
+                for (op, operand) in rest {
+                    let op = binary_op(op);
+                    res.push_str(&newline(inner));
+                    res.push_str(op);
+                    res.push(' ');
+                    let operand =
+                        self.expr(operand, Position::Operand, inner, inner + op.len() + 1);
+                    res.push_str(&operand);
+                }
+                res
+            }
+            LNode::UnuaryOp { op, body } => {
+                let prefix = unuary_op(*op);
+                // word operators are followed by a space
+                let width = prefix.len() + prefix.starts_with(char::is_alphabetic) as usize;
+                let rendered = self.expr(body, Position::Prefixed, indent, column + width);
+                format!("{}{}", prefix, prefixed_body(*op, rendered))
+            }
+            LNode::If {
+                cond,
+                then_do,
+                else_do,
+            } => {
+                let cond = self.expr(cond, Position::Expr, indent, column + 3);
+                let then_do = self.expr(then_do, Position::Expr, inner, inner);
+                let else_do = self.expr(else_do, Position::Expr, inner, inner);
+                format!(
+                    "if {cond} {{{}{then_do}{}}} else {{{}{else_do}{}}}",
+                    newline(inner),
+                    newline(indent),
+                    newline(inner),
+                    newline(indent)
+                )
+            }
+            LNode::Tuple(items) => {
+                let items: Vec<_> = items.iter().collect();
+                self.split_items("(", &items, ")", indent)
+            }
+            LNode::Nil => single_line,
+            LNode::Cons { head, tail } => match list_items(node) {
+                Some(items) => self.split_items("[", &items, "]", indent),
+                None => {
+                    let head = self.expr(head, Position::Arg, inner, inner);
+                    let tail = self.expr(tail, Position::Arg, inner, inner);
+                    format!("cons{}{head}{}{tail}", newline(inner), newline(inner))
+                }
+            },
+            LNode::Match { value, arms } => {
+                let value = self.expr(value, Position::Expr, indent, column + 6);
+                let arms: Vec<_> = arms
+                    .iter()
+                    .map(|(p, body)| {
+                        let head = format!("{} => ", pattern(p));
+                        let column = inner + head.len();
+                        format!(
+                            "{}{head}{}",
+                            " ".repeat(inner),
+                            self.expr(body, Position::Expr, inner, column)
+                        )
+                    })
+                    .collect();
+                format!(
+                    "match {value} {{\n{}\n{}}}",
+                    arms.join(",\n"),
+                    " ".repeat(indent)
+                )
+            }
+        }
+    }
+
+    fn binding(&mut self, binding: &Binding, indent: usize) -> String {
+        let head = binding_head(binding);
+        let column = indent + head.len();
+        format!(
+            "{head}{};",
+            self.expr(&binding.value, Position::Expr, indent, column)
+        )
+    }
+
+    fn test(&mut self, test: &Test) -> String {
+        let head = format!("test {} {{", quote(&test.name));
+        let single_line = format!(
+            "{head} {} }} == {};",
+            flat(&test.expr, Position::Expr),
+            flat(&test.expected, Position::Expr)
+        );
+        if single_line.len() <= WIDTH
+            && !self.needs_split(&test.expr)
+            && !self.needs_split(&test.expected)
+        {
+            return single_line;
+        }
+        format!(
+            "{head}\n{}{}\n}} == {};",
+            " ".repeat(INDENT),
+            self.expr(&test.expr, Position::Expr, INDENT, INDENT),
+            self.expr(&test.expected, Position::Expr, 0, 5)
+        )
+    }
+
+    // the offset of a keyword, outside of comments
+    fn keyword_before(&self, keyword: &str, offset: usize) -> usize {
+        self.source[..offset]
+            .rmatch_indices(keyword)
+            .map(|(pos, _)| pos)
+            .find(|pos| !self.in_comment(*pos))
+            .unwrap_or(0)
+    }
+
+    // the offset of the first token of the source
+    fn first_token(&self) -> usize {
+        let mut offset = 0;
+        loop {
+            let rest = &self.source[offset..];
+            let trimmed = rest.trim_start();
+            offset += rest.len() - trimmed.len();
+            if !trimmed.starts_with("//") {
+                return offset;
+            }
+            offset += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+
+    fn program(&mut self, program: &Program) -> String {
+        let mut res = String::new();
+        let mut first = true;
+
+        // imports don't keep track of where they are, the comments of the header come first
+        if !program.imports.is_empty() {
+            self.item(self.first_token(), 0, &mut first, &mut res);
+            let imports: Vec<_> = program
+                .imports
+                .iter()
+                .map(|import| format!("import {};", quote(import)))
+                .collect();
+            res.push_str(&imports.join("\n"));
+            res.push('\n');
+        }
+
+        // tests may come before or after the bindings
+        let main_start = match (&program.body, program.bindings.first()) {
+            (Some(body), _) => body.span.start,
+            (None, Some(binding)) => self.keyword_before("let", self.binding_start(binding)),
+            (None, None) => self.source.len(),
+        };
+        let (leading, trailing): (Vec<_>, Vec<_>) = program
+            .tests
+            .iter()
+            .partition(|test| test.span.start < main_start);
+        for test in leading {
+            self.item(test.span.start, 0, &mut first, &mut res);
+            res.push_str(&self.test(test));
+            self.trailing_comment(test.expected.span.end, &mut res);
+            res.push('\n');
+        }
+
+        if let Some(body) = &program.body {
+            self.item(body.span.start, 0, &mut first, &mut res);
+            res.push_str(&self.expr(body, Position::Expr, 0, 0));
+            self.trailing_comment(body.span.end, &mut res);
+            res.push('\n');
+        } else if !program.bindings.is_empty() {
+            self.item(main_start, 0, &mut first, &mut res);
+            res.push_str("let\n");
+            let mut first = true;
+            for binding in program.bindings.iter() {
+                self.item(self.binding_start(binding), INDENT, &mut first, &mut res);
+                res.push_str(&self.binding(binding, INDENT));
+                self.trailing_comment(binding.value.span.end, &mut res);
+                res.push('\n');
+            }
+        }
+
+        for test in trailing {
+            self.item(test.span.start, 0, &mut first, &mut res);
+            res.push_str(&self.test(test));
+            self.trailing_comment(test.expected.span.end, &mut res);
+            res.push('\n');
+        }
+
+        self.flush_comments(self.source.len(), 0, &mut first, &mut res);
+        res
+    }
+}
+
+// finds the comments of a source, skipping over string litterals
+fn find_comments(source: &str) -> Vec<(usize, String)> {
+    let mut res = vec![];
+    let mut chars = source.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if source[i..].starts_with("//") => {
+                let end = source[i..].find('\n').map_or(source.len(), |len| i + len);
+                res.push((i, source[i..end].trim_end().to_owned()));
+                for _ in source[i + 1..end].chars() {
+                    chars.next();
+                }
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+// re-emits a program in the canonical style, keeping its comments
+pub fn format(source: &str) -> Result<String, Diagnostic> {
+    let program = parse_program(source)?;
+    Ok(Formatter::new(source).program(&program))
+}
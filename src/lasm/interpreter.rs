@@ -0,0 +1,636 @@
+use std::{cell::RefCell, fmt::Display, rc::Rc};
+
+use crate::icfp::{evaluate, BinaryOp, EvalStrat, Node, UnuaryOp, Value};
+
+use super::{
+    ast::{Binding, Pattern},
+    const_eval::{binary_op_is_valid, unuary_op_is_valid},
+    source::{Diagnostic, SourceFile, Span},
+    Iden, LNode, LNodeRef,
+};
+
+// reserved functions provided by the interpreter. they take two parameters and return the second one,
+// the compiler drops them along with their first parameter
+const BUILTINS: &[&str] = &["trace", "assert"];
+
+// if the function of an application is a builtin given its first parameter, returns them.
+// bindings of the same name shadow the builtins
+pub fn builtin_call(func: &LNode, is_bound: impl Fn(&Iden) -> bool) -> Option<(String, &LNodeRef)> {
+    let LNode::Apply { func, param, .. } = func else {
+        return None;
+    };
+    match func.as_ref() {
+        LNode::Variable(name)
+            if BUILTINS.contains(&name.to_string().as_str()) && !is_bound(name) =>
+        {
+            Some((name.to_string(), param))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Clone)]
+enum RValue {
+    Value(Value),
+    Function(Rc<Closure>),
+    Tuple(Rc<Vec<Thunk>>),
+    Nil,
+    Cons(Thunk, Thunk),
+}
+
+impl Display for RValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RValue::Value(val) => write!(f, "{}", val),
+            RValue::Function(closure) => write!(f, "function {}", closure.name),
+            RValue::Tuple(items) => write!(f, "a tuple of {}", items.len()),
+            RValue::Nil => write!(f, "nil"),
+            RValue::Cons(..) => write!(f, "a list"),
+        }
+    }
+}
+
+// a function along with the parameters it was already given
+struct Closure {
+    name: Iden,
+    params: Vec<Iden>,
+    body: LNodeRef,
+    env: Rc<Env>,
+    args: Vec<Thunk>,
+}
+
+enum ThunkState {
+    Pending(LNodeRef, Rc<Env>),
+    Forcing,
+    Done(RValue),
+}
+
+// a possibly unevaluated expression, evaluated at most once
+#[derive(Clone)]
+struct Thunk(Rc<RefCell<ThunkState>>);
+
+impl Thunk {
+    fn pending(node: LNodeRef, env: Rc<Env>) -> Self {
+        Thunk(Rc::new(RefCell::new(ThunkState::Pending(node, env))))
+    }
+
+    fn done(value: RValue) -> Self {
+        Thunk(Rc::new(RefCell::new(ThunkState::Done(value))))
+    }
+}
+
+// scopes are linked lists, functions are bound lazily so that they can refer to themselves
+enum Env {
+    Empty,
+    Value {
+        name: Iden,
+        value: Thunk,
+        parent: Rc<Env>,
+    },
+    Function {
+        binding: Binding,
+        parent: Rc<Env>,
+    },
+}
+
+fn lookup(env: &Rc<Env>, name: &Iden) -> Option<Thunk> {
+    let mut current = env;
+    loop {
+        match current.as_ref() {
+            Env::Empty => return None,
+            Env::Value {
+                name: n,
+                value,
+                parent,
+            } => {
+                if n == name {
+                    return Some(value.clone());
+                }
+                current = parent;
+            }
+            Env::Function { binding, parent } => {
+                if &binding.name == name {
+                    return Some(Thunk::done(RValue::Function(Rc::new(Closure {
+                        name: binding.name.clone(),
+                        params: binding.params.clone(),
+                        body: binding.value.clone(),
+                        env: current.clone(),
+                        args: vec![],
+                    }))));
+                }
+                current = parent;
+            }
+        }
+    }
+}
+
+struct Frame {
+    name: Iden,
+    // where the function was called from
+    call_site: Span,
+}
+
+// what is left to do once the current expression is evaluated. they are kept on the heap rather
+// than on the call stack, so that deep recursion can't overflow it
+enum Cont {
+    // the thunk being forced, which keeps its value
+    Update(Thunk),
+    // the end of a function call
+    Return,
+    // a let whose binding at the index is being evaluated
+    Let {
+        node: LNodeRef,
+        index: usize,
+        env: Rc<Env>,
+    },
+    // the function of an application, which is then given the parameter
+    Apply {
+        strat: EvalStrat,
+        param: LNodeRef,
+        env: Rc<Env>,
+        span: Span,
+    },
+    // the parameter of an application by value
+    Call {
+        func: RValue,
+        span: Span,
+    },
+    BinaryLeft {
+        op: BinaryOp,
+        left: Span,
+        right: LNodeRef,
+        env: Rc<Env>,
+        span: Span,
+    },
+    BinaryRight {
+        op: BinaryOp,
+        left: Value,
+        right: Span,
+        span: Span,
+    },
+    Unuary {
+        op: UnuaryOp,
+        body: Span,
+        span: Span,
+    },
+    If {
+        cond: Span,
+        then_do: LNodeRef,
+        else_do: LNodeRef,
+        env: Rc<Env>,
+    },
+    Match {
+        node: LNodeRef,
+        env: Rc<Env>,
+    },
+    TraceLabel {
+        value: LNodeRef,
+        env: Rc<Env>,
+    },
+    Trace {
+        label: RValue,
+    },
+    Assert {
+        cond: Span,
+        value: LNodeRef,
+        env: Rc<Env>,
+    },
+}
+
+// what the interpreter does next
+enum Step {
+    Eval(LNodeRef, Rc<Env>),
+    Return(RValue),
+}
+
+pub struct Interpreter<'a> {
+    sources: &'a [SourceFile],
+    stack: Vec<Frame>,
+    conts: Vec<Cont>,
+    // the messages given to trace, in order
+    pub traces: Vec<String>,
+    // whether traces are also printed to stderr as they happen
+    pub echo: bool,
+}
+
+type RunResult<T> = Result<T, Diagnostic>;
+
+impl<'a> Interpreter<'a> {
+    pub fn new(sources: &'a [SourceFile]) -> Self {
+        Self {
+            sources,
+            stack: vec![],
+            conts: vec![],
+            traces: vec![],
+            echo: false,
+        }
+    }
+
+    fn location(&self, span: Span) -> String {
+        match self.sources.get(span.source) {
+            Some(source) => {
+                let (line, col) = source.line_col(span.start);
+                format!("{}:{line}:{col}", source.path.display())
+            }
+            None => format!("{}..{}", span.start, span.end),
+        }
+    }
+
+    // an error, along with the call stack leading to it
+    fn error(&self, message: impl Into<String>, span: Span) -> Diagnostic {
+        self.stack
+            .iter()
+            .rev()
+            .fold(Diagnostic::new(message, span), |diag, frame| {
+                diag.with_note(format!(
+                    "in {}, called at {}",
+                    frame.name,
+                    self.location(frame.call_site)
+                ))
+            })
+    }
+
+    fn force(&mut self, thunk: &Thunk, span: Span) -> RunResult<Step> {
+        let state = std::mem::replace(&mut *thunk.0.borrow_mut(), ThunkState::Forcing);
+        match state {
+            ThunkState::Done(value) => {
+                *thunk.0.borrow_mut() = ThunkState::Done(value.clone());
+                Ok(Step::Return(value))
+            }
+            ThunkState::Forcing => Err(self.error("value depends on itself", span)),
+            ThunkState::Pending(node, env) => {
+                self.conts.push(Cont::Update(thunk.clone()));
+                Ok(Step::Eval(node, env))
+            }
+        }
+    }
+
+    fn expect_value(&self, value: RValue, span: Span) -> RunResult<Value> {
+        match value {
+            RValue::Value(val) => Ok(val),
+            other => Err(self.error(format!("expected a value, got {}", other), span)),
+        }
+    }
+
+    fn apply(&mut self, func: RValue, arg: Thunk, span: Span) -> RunResult<Step> {
+        let closure = match func {
+            RValue::Function(closure) => closure,
+            other => {
+                return Err(self.error(format!("{} isn't a function", other), span));
+            }
+        };
+
+        let mut args = closure.args.clone();
+        args.push(arg);
+        if args.len() < closure.params.len() {
+            return Ok(Step::Return(RValue::Function(Rc::new(Closure {
+                name: closure.name.clone(),
+                params: closure.params.clone(),
+                body: closure.body.clone(),
+                env: closure.env.clone(),
+                args,
+            }))));
+        }
+
+        // all the parameters are given, run the body
+        let env =
+            closure
+                .params
+                .iter()
+                .zip(args)
+                .fold(closure.env.clone(), |parent, (name, value)| {
+                    Rc::new(Env::Value {
+                        name: name.clone(),
+                        value,
+                        parent,
+                    })
+                });
+        // a function calling itself in tail position keeps its frame, so loops run in constant space
+        let tail_call = matches!(self.conts.last(), Some(Cont::Return))
+            && matches!(self.stack.last(), Some(frame) if frame.name == closure.name);
+        if !tail_call {
+            self.stack.push(Frame {
+                name: closure.name.clone(),
+                call_site: span,
+            });
+            self.conts.push(Cont::Return);
+        }
+        Ok(Step::Eval(closure.body.clone(), env))
+    }
+
+    // binds the let from the given index, then evaluates its body
+    fn eval_let(&mut self, node: LNodeRef, index: usize, mut env: Rc<Env>) -> RunResult<Step> {
+        let LNode::Let {
+            strat,
+            bindings,
+            body,
+        } = node.as_ref()
+        else {
+            unreachable!("not a let");
+        };
+        for (index, binding) in bindings.iter().enumerate().skip(index) {
+            env = Rc::new(if !binding.params.is_empty() {
+                Env::Function {
+                    binding: binding.clone(),
+                    parent: env,
+                }
+            } else if binding.constant || *strat == EvalStrat::Value {
+                // constants are always evaluated right away
+                let value = binding.value.clone();
+                self.conts.push(Cont::Let {
+                    node: node.clone(),
+                    index,
+                    env: env.clone(),
+                });
+                return Ok(Step::Eval(value, env));
+            } else {
+                Env::Value {
+                    name: binding.name.clone(),
+                    value: Thunk::pending(binding.value.clone(), env.clone()),
+                    parent: env,
+                }
+            });
+        }
+        Ok(Step::Eval(body.clone(), env))
+    }
+
+    fn eval_match(&mut self, node: &LNodeRef, value: RValue, env: &Rc<Env>) -> RunResult<Step> {
+        let LNode::Match { arms, .. } = node.as_ref() else {
+            unreachable!("not a match");
+        };
+        let bound = arms.iter().find_map(|(pattern, body)| {
+            let values = match (pattern, &value) {
+                (Pattern::Tuple(names), RValue::Tuple(items)) if names.len() == items.len() => {
+                    items.to_vec()
+                }
+                (Pattern::Nil, RValue::Nil) => vec![],
+                (Pattern::Cons(..), RValue::Cons(head, tail)) => vec![head.clone(), tail.clone()],
+                _ => return None,
+            };
+            Some((pattern.names().into_iter().zip(values), body))
+        });
+        let Some((bindings, body)) = bound else {
+            return Err(self.error(format!("no pattern matches {}", value), node.span));
+        };
+        let env = bindings.fold(env.clone(), |parent, (name, value)| {
+            Rc::new(Env::Value {
+                name,
+                value,
+                parent,
+            })
+        });
+        Ok(Step::Eval(body.clone(), env))
+    }
+
+    // evaluates the node, then continues with the value
+    fn push(&mut self, cont: Cont, node: &LNodeRef, env: &Rc<Env>) -> RunResult<Step> {
+        self.conts.push(cont);
+        Ok(Step::Eval(node.clone(), env.clone()))
+    }
+
+    // starts evaluating an expression
+    fn eval(&mut self, node: LNodeRef, env: Rc<Env>) -> RunResult<Step> {
+        let span = node.span;
+        match node.as_ref() {
+            LNode::Litteral(val) => Ok(Step::Return(RValue::Value(val.clone()))),
+            LNode::Variable(name) => match lookup(&env, name) {
+                Some(thunk) => self.force(&thunk, span),
+                None => Err(self.error(format!("unbound variable {}", name), span)),
+            },
+            LNode::Let { .. } => self.eval_let(node.clone(), 0, env),
+            LNode::Apply { strat, func, param } => {
+                let cont = match builtin_call(func, |name| lookup(&env, name).is_some()) {
+                    Some((name, first)) if name == "trace" => {
+                        let cont = Cont::TraceLabel {
+                            value: param.clone(),
+                            env: env.clone(),
+                        };
+                        return self.push(cont, first, &env);
+                    }
+                    Some((_, first)) => {
+                        let cont = Cont::Assert {
+                            cond: first.span,
+                            value: param.clone(),
+                            env: env.clone(),
+                        };
+                        return self.push(cont, first, &env);
+                    }
+                    None => Cont::Apply {
+                        strat: *strat,
+                        param: param.clone(),
+                        env: env.clone(),
+                        span,
+                    },
+                };
+                self.push(cont, func, &env)
+            }
+            LNode::BinaryOp { op, left, right } => {
+                let (op, right) = (*op, right.clone());
+                let cont = Cont::BinaryLeft {
+                    op,
+                    left: left.span,
+                    right,
+                    env: env.clone(),
+                    span,
+                };
+                self.push(cont, left, &env)
+            }
+            LNode::UnuaryOp { op, body } => {
+                let cont = Cont::Unuary {
+                    op: *op,
+                    body: body.span,
+                    span,
+                };
+                self.push(cont, body, &env)
+            }
+            LNode::If {
+                cond,
+                then_do,
+                else_do,
+            } => {
+                let cont = Cont::If {
+                    cond: cond.span,
+                    then_do: then_do.clone(),
+                    else_do: else_do.clone(),
+                    env: env.clone(),
+                };
+                self.push(cont, cond, &env)
+            }
+            // the items of tuples and lists are evaluated lazily, like in the compiled program
+            LNode::Tuple(items) => Ok(Step::Return(RValue::Tuple(Rc::new(
+                items
+                    .iter()
+                    .map(|item| Thunk::pending(item.clone(), env.clone()))
+                    .collect(),
+            )))),
+            LNode::Nil => Ok(Step::Return(RValue::Nil)),
+            LNode::Cons { head, tail } => Ok(Step::Return(RValue::Cons(
+                Thunk::pending(head.clone(), env.clone()),
+                Thunk::pending(tail.clone(), env.clone()),
+            ))),
+            LNode::Match { value, .. } => {
+                let cont = Cont::Match {
+                    node: node.clone(),
+                    env: env.clone(),
+                };
+                self.push(cont, value, &env)
+            }
+        }
+    }
+
+    // continues with the value of the last expression
+    fn resume(&mut self, cont: Cont, value: RValue) -> RunResult<Step> {
+        match cont {
+            Cont::Update(thunk) => {
+                *thunk.0.borrow_mut() = ThunkState::Done(value.clone());
+                Ok(Step::Return(value))
+            }
+            Cont::Return => {
+                self.stack.pop();
+                Ok(Step::Return(value))
+            }
+            Cont::Let { node, index, env } => {
+                let LNode::Let { bindings, .. } = node.as_ref() else {
+                    unreachable!("not a let");
+                };
+                let env = Rc::new(Env::Value {
+                    name: bindings[index].name.clone(),
+                    value: Thunk::done(value),
+                    parent: env,
+                });
+                self.eval_let(node, index + 1, env)
+            }
+            Cont::Apply {
+                strat: EvalStrat::Value,
+                param,
+                env,
+                span,
+            } => {
+                self.conts.push(Cont::Call { func: value, span });
+                Ok(Step::Eval(param, env))
+            }
+            Cont::Apply {
+                param, env, span, ..
+            } => self.apply(value, Thunk::pending(param, env), span),
+            Cont::Call { func, span } => self.apply(func, Thunk::done(value), span),
+            Cont::BinaryLeft {
+                op,
+                left,
+                right,
+                env,
+                span,
+            } => {
+                let left = self.expect_value(value, left)?;
+                // boolean operators short circuit, like the evaluator does
+                match (op, &left) {
+                    (BinaryOp::BoolAnd, Value::Bool(false))
+                    | (BinaryOp::BoolOr, Value::Bool(true)) => {
+                        return Ok(Step::Return(RValue::Value(left)))
+                    }
+                    _ => {}
+                }
+                self.conts.push(Cont::BinaryRight {
+                    op,
+                    left,
+                    right: right.span,
+                    span,
+                });
+                Ok(Step::Eval(right, env))
+            }
+            Cont::BinaryRight {
+                op,
+                left,
+                right,
+                span,
+            } => {
+                let right = self.expect_value(value, right)?;
+                if !binary_op_is_valid(op, &left, &right) {
+                    return Err(self.error(
+                        format!("invalid operands for {}: {} and {}", op, left, right),
+                        span,
+                    ));
+                }
+                Ok(Step::Return(RValue::Value(evaluate(Rc::new(
+                    Node::BinaryOp {
+                        op,
+                        left: Rc::new(Node::Value(left)),
+                        right: Rc::new(Node::Value(right)),
+                    },
+                )))))
+            }
+            Cont::Unuary { op, body, span } => {
+                let value = self.expect_value(value, body)?;
+                if !unuary_op_is_valid(op, &value) {
+                    return Err(self.error(format!("invalid operand for {}: {}", op, value), span));
+                }
+                Ok(Step::Return(RValue::Value(evaluate(Rc::new(
+                    Node::UnuaryOp {
+                        op,
+                        body: Rc::new(Node::Value(value)),
+                    },
+                )))))
+            }
+            Cont::If {
+                cond,
+                then_do,
+                else_do,
+                env,
+            } => match self.expect_value(value, cond)? {
+                Value::Bool(true) => Ok(Step::Eval(then_do, env)),
+                Value::Bool(false) => Ok(Step::Eval(else_do, env)),
+                val => Err(self.error(format!("the condition must be a bool, got {}", val), cond)),
+            },
+            Cont::Match { node, env } => self.eval_match(&node, value, &env),
+            Cont::TraceLabel { value: node, env } => {
+                self.conts.push(Cont::Trace { label: value });
+                Ok(Step::Eval(node, env))
+            }
+            Cont::Trace { label } => {
+                let message = format!("{}: {}", label, value);
+                if self.echo {
+                    eprintln!("trace {}", message);
+                }
+                self.traces.push(message);
+                Ok(Step::Return(value))
+            }
+            Cont::Assert {
+                cond,
+                value: node,
+                env,
+            } => match self.expect_value(value, cond)? {
+                Value::Bool(true) => Ok(Step::Eval(node, env)),
+                Value::Bool(false) => Err(self.error("assertion failed", cond)),
+                val => Err(self.error(format!("assert expects a bool, got {}", val), cond)),
+            },
+        }
+    }
+
+    pub fn run(&mut self, node: &LNodeRef) -> RunResult<Value> {
+        self.stack.clear();
+        self.conts.clear();
+        let mut step = Step::Eval(node.clone(), Rc::new(Env::Empty));
+        let value = loop {
+            step = match step {
+                Step::Eval(node, env) => self.eval(node, env)?,
+                Step::Return(value) => match self.conts.pop() {
+                    Some(cont) => self.resume(cont, value)?,
+                    None => break value,
+                },
+            }
+        };
+        match value {
+            RValue::Value(val) => Ok(val),
+            other => Err(self.error(
+                format!("the program evaluates to {}, not a value", other),
+                node.span,
+            )),
+        }
+    }
+}
+
+// runs a program without compiling it
+pub fn run(node: &LNodeRef, sources: &[SourceFile]) -> Result<Value, Diagnostic> {
+    let mut interpreter = Interpreter::new(sources);
+    interpreter.echo = true;
+    interpreter.run(node)
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    ast::{Binding, EvalStrat, Program, Test},
+    parser::parse_program,
+    source::{SourceFile, Span},
+    LNode, LNodeRef,
+};
+
+// libraries shipped with the compiler, imported with `import "@name";`
+const BUNDLED: &[(&str, &str)] = &[("@prelude", include_str!("prelude.lasm"))];
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+    UnknownLibrary(String),
+    ImportCycle(PathBuf),
+    ImportedProgram(PathBuf),
+    MissingBody(PathBuf),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(path, err) => write!(f, "Failed to read {}: {}", path.display(), err),
+            LoadError::Parse(path, err) => write!(f, "Parsing {} failed:\n{}", path.display(), err),
+            LoadError::UnknownLibrary(name) => write!(f, "Unknown bundled library {}", name),
+            LoadError::ImportCycle(path) => write!(f, "Import cycle through {}", path.display()),
+            LoadError::ImportedProgram(path) => {
+                write!(f, "{} has a body and can't be imported", path.display())
+            }
+            LoadError::MissingBody(path) => {
+                write!(f, "{} only has bindings, a body is missing", path.display())
+            }
+        }
+    }
+}
+
+// the loaded program, along with the files it was read from
+pub type LoadResult = Result<(LNodeRef, Vec<SourceFile>), LoadError>;
+pub type LoadTestsResult = Result<(Vec<Test>, Vec<SourceFile>), LoadError>;
+
+struct Loader {
+    // modules already included, they are only included once
+    loaded: HashSet<PathBuf>,
+    // modules being loaded, to detect cycles
+    stack: Vec<PathBuf>,
+    // the bindings of all the imported modules, in dependency order
+    bindings: Vec<Binding>,
+    // every file read, spans refer to them by index
+    sources: Vec<SourceFile>,
+}
+
+impl Loader {
+    fn new() -> Self {
+        Self {
+            loaded: HashSet::new(),
+            stack: vec![],
+            bindings: vec![],
+            sources: vec![],
+        }
+    }
+
+    fn parse(&mut self, path: &Path, source: &str) -> Result<Program, LoadError> {
+        let file = SourceFile::new(path, source);
+        let program = parse_program(source).map_err(|err| {
+            LoadError::Parse(path.to_owned(), err.render(std::slice::from_ref(&file)))
+        })?;
+
+        let index = self.sources.len();
+        self.sources.push(file);
+        Ok(program.map_spans(&|span| Span {
+            source: index,
+            ..span
+        }))
+    }
+
+    // bundled libraries are keyed by their name, files by their canonical path
+    fn read(&self, base_dir: &Path, import: &str) -> Result<(PathBuf, String), LoadError> {
+        if import.starts_with('@') {
+            let (_, source) = BUNDLED
+                .iter()
+                .find(|(name, _)| *name == import)
+                .ok_or_else(|| LoadError::UnknownLibrary(import.to_owned()))?;
+            return Ok((PathBuf::from(import), source.to_string()));
+        }
+
+        let path = base_dir.join(import);
+        let path = path
+            .canonicalize()
+            .map_err(|err| LoadError::Io(path.clone(), err))?;
+        let source =
+            std::fs::read_to_string(&path).map_err(|err| LoadError::Io(path.clone(), err))?;
+        Ok((path, source))
+    }
+
+    fn import(&mut self, base_dir: &Path, import: &str) -> Result<(), LoadError> {
+        let (path, source) = self.read(base_dir, import)?;
+        if self.stack.contains(&path) {
+            return Err(LoadError::ImportCycle(path));
+        }
+        if !self.loaded.insert(path.clone()) {
+            return Ok(());
+        }
+
+        let module = self.parse(&path, &source)?;
+        if module.body.is_some() {
+            return Err(LoadError::ImportedProgram(path));
+        }
+
+        self.stack.push(path.clone());
+        let module_dir = path.parent().unwrap_or(base_dir).to_owned();
+        for import in module.imports.iter() {
+            self.import(&module_dir, import)?;
+        }
+        self.stack.pop();
+
+        self.bindings.extend(module.bindings);
+        Ok(())
+    }
+
+    // parses a file and loads its imports
+    fn load_program(
+        &mut self,
+        path: &Path,
+        base_dir: &Path,
+        source: &str,
+    ) -> Result<Program, LoadError> {
+        let program = self.parse(path, source)?;
+        for import in program.imports.iter() {
+            self.import(base_dir, import)?;
+        }
+        Ok(program)
+    }
+
+    fn load(mut self, path: &Path, base_dir: &Path, source: &str) -> LoadResult {
+        let program = self.load_program(path, base_dir, source)?;
+        let body = program
+            .body
+            .ok_or_else(|| LoadError::MissingBody(path.to_owned()))?;
+        Ok((with_bindings(self.bindings, body), self.sources))
+    }
+
+    // like load, but a library gets an empty body so that its bindings are still checked
+    fn load_document(mut self, path: &Path, base_dir: &Path, source: &str) -> LoadResult {
+        let program = self.load_program(path, base_dir, source)?;
+        let mut bindings = self.bindings;
+        let body = match program.body {
+            Some(body) => body,
+            None => {
+                bindings.extend(program.bindings);
+                LNode::int(0)
+            }
+        };
+        Ok((with_bindings(bindings, body), self.sources))
+    }
+
+    fn load_tests(mut self, path: &Path, base_dir: &Path, source: &str) -> LoadTestsResult {
+        let program = self.load_program(path, base_dir, source)?;
+
+        // tests see the imported bindings, along with the ones of their module.
+        // in programs, these are the bindings of the outermost let
+        let mut bindings = self.bindings;
+        bindings.extend(program.bindings);
+        if let Some(body) = program.body {
+            if let LNode::Let {
+                bindings: body_bindings,
+                ..
+            } = body.as_ref()
+            {
+                bindings.extend(body_bindings.iter().cloned());
+            }
+        }
+        let tests = program
+            .tests
+            .into_iter()
+            .map(|test| Test {
+                expr: with_bindings(bindings.clone(), test.expr),
+                expected: with_bindings(bindings.clone(), test.expected),
+                ..test
+            })
+            .collect();
+        Ok((tests, self.sources))
+    }
+}
+
+fn with_bindings(bindings: Vec<Binding>, body: LNodeRef) -> LNodeRef {
+    if bindings.is_empty() {
+        return body;
+    }
+
+    // unused bindings are dropped by the compiler
+    let span = body.span;
+    LNodeRef::new(LNode::Let {
+        strat: EvalStrat::Value,
+        bindings,
+        body,
+    })
+    .with_span(span)
+}
+
+// load a program from a file, resolving imports relative to it
+pub fn load(path: &Path) -> LoadResult {
+    let source =
+        std::fs::read_to_string(path).map_err(|err| LoadError::Io(path.to_owned(), err))?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    Loader::new().load(path, base_dir, &source)
+}
+
+// load a program or a library being edited, resolving imports relative to its path
+pub fn load_document(path: &Path, source: &str) -> LoadResult {
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    Loader::new().load_document(path, base_dir, source)
+}
+
+// load a program from a string, resolving imports relative to base_dir
+pub fn load_str(source: &str, base_dir: &Path) -> LoadResult {
+    Loader::new().load(Path::new("<input>"), base_dir, source)
+}
+
+// load the tests of a file, which may be a library
+pub fn load_tests(path: &Path) -> LoadTestsResult {
+    let source =
+        std::fs::read_to_string(path).map_err(|err| LoadError::Io(path.to_owned(), err))?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    Loader::new().load_tests(path, base_dir, &source)
+}
+
+// load the tests of a string, resolving imports relative to base_dir
+pub fn load_tests_str(source: &str, base_dir: &Path) -> LoadTestsResult {
+    Loader::new().load_tests(Path::new("<input>"), base_dir, source)
+}
@@ -0,0 +1,460 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use argh::FromArgs;
+use serde_json::{json, Value};
+
+use super::{
+    compiler::compile_optimized_with_limit,
+    loader::{load_document, load_str, LoadError},
+    optimizer::BindingSize,
+    parser::parse_program,
+    source::{Diagnostic, SourceFile, Span},
+    typing::{type_spans, Type},
+    Iden, LNode, LNodeRef,
+};
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Run the LASM language server over stdio
+#[argh(subcommand, name = "lsp")]
+pub struct LspCommand {}
+
+impl LspCommand {
+    pub fn run(&self) {
+        let mut server = Server::new(io::stdout().lock());
+        if let Err(err) = server.serve(&mut io::stdin().lock()) {
+            eprintln!("language server failed: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+// reads a message, framed by a Content-Length header
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+pub fn write_message(out: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+// positions count UTF-16 code units
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character = before[line_start..].encode_utf16().count();
+    json!({ "line": line, "character": character })
+}
+
+fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let line_start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+fn range(text: &str, span: Span) -> Value {
+    json!({ "start": position(text, span.start), "end": position(text, span.end) })
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    PathBuf::from(path.replace("%20", " "))
+}
+
+fn path_to_uri(path: &Path) -> String {
+    format!("file://{}", path.display().to_string().replace(' ', "%20"))
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.source == 0 && span.start <= offset && offset <= span.end
+}
+
+// the bindings of the outermost lets
+fn top_level_bindings(node: &LNodeRef) -> Vec<(Iden, Span)> {
+    let mut res = vec![];
+    let mut node = node;
+    while let LNode::Let { bindings, body, .. } = node.as_ref() {
+        res.extend(bindings.iter().map(|b| (b.name.clone(), b.span)));
+        node = body;
+    }
+    res
+}
+
+// finds the variable at `offset`, along with the let binding it refers to, if any.
+// `scope` holds the names in scope, parameters and patterns have no binding
+fn definition(
+    node: &LNodeRef,
+    offset: usize,
+    scope: &mut Vec<(Iden, Option<Span>)>,
+) -> Option<Option<Span>> {
+    let len = scope.len();
+    let res = match node.as_ref() {
+        LNode::Litteral(_) | LNode::Nil => None,
+        LNode::Variable(name) if contains(node.span, offset) => Some(
+            scope
+                .iter()
+                .rev()
+                .find(|(n, _)| n == name)
+                .and_then(|(_, span)| *span),
+        ),
+        LNode::Variable(_) => None,
+        LNode::Let { bindings, body, .. } => {
+            let mut res = None;
+            for binding in bindings {
+                // the name of the binding refers to itself
+                if contains(binding.span, offset) {
+                    res = Some(Some(binding.span));
+                    break;
+                }
+                let outer = scope.len();
+                if binding.rec {
+                    scope.push((binding.name.clone(), Some(binding.span)));
+                }
+                scope.extend(binding.params.iter().map(|p| (p.clone(), None)));
+                res = definition(&binding.value, offset, scope);
+                scope.truncate(outer);
+                if res.is_some() {
+                    break;
+                }
+                scope.push((binding.name.clone(), Some(binding.span)));
+            }
+            res.or_else(|| definition(body, offset, scope))
+        }
+        LNode::Apply { func, param, .. } => {
+            definition(func, offset, scope).or_else(|| definition(param, offset, scope))
+        }
+        LNode::BinaryOp { left, right, .. } => {
+            definition(left, offset, scope).or_else(|| definition(right, offset, scope))
+        }
+        LNode::UnuaryOp { body, .. } => definition(body, offset, scope),
+        LNode::If {
+            cond,
+            then_do,
+            else_do,
+        } => definition(cond, offset, scope)
+            .or_else(|| definition(then_do, offset, scope))
+            .or_else(|| definition(else_do, offset, scope)),
+        LNode::Tuple(items) => items
+            .iter()
+            .find_map(|item| definition(item, offset, scope)),
+        LNode::Cons { head, tail } => {
+            definition(head, offset, scope).or_else(|| definition(tail, offset, scope))
+        }
+        LNode::Match { value, arms } => definition(value, offset, scope).or_else(|| {
+            arms.iter().find_map(|(pattern, body)| {
+                let outer = scope.len();
+                scope.extend(pattern.names().into_iter().map(|name| (name, None)));
+                let res = definition(body, offset, scope);
+                scope.truncate(outer);
+                res
+            })
+        }),
+    };
+    scope.truncate(len);
+    res
+}
+
+// the number of reductions a constant may take while a document is analyzed
+const CONST_EVAL_LIMIT: u32 = 100_000;
+
+// what we know about an open document
+struct Document {
+    text: String,
+    program: Option<(LNodeRef, Vec<SourceFile>)>,
+    diagnostics: Vec<Diagnostic>,
+    types: Vec<(Span, Type)>,
+    sizes: Vec<BindingSize>,
+}
+
+impl Document {
+    fn analyze(path: &Path, text: String) -> Self {
+        let mut document = Document {
+            text,
+            program: None,
+            diagnostics: vec![],
+            types: vec![],
+            sizes: vec![],
+        };
+        if let Err(diagnostic) = parse_program(&document.text) {
+            document.diagnostics.push(diagnostic);
+            return document;
+        }
+        let (node, sources) = match load_document(path, &document.text) {
+            Ok(res) => res,
+            Err(err) => {
+                // parse errors of imported files are reported at the top
+                let message = match err {
+                    LoadError::Parse(path, _) => format!("{} doesn't parse", path.display()),
+                    err => err.to_string(),
+                };
+                document
+                    .diagnostics
+                    .push(Diagnostic::new(message, Span::default()));
+                return document;
+            }
+        };
+
+        let (types, res) = type_spans(&node);
+        document.types = types;
+        match res {
+            // documents are analyzed on every change, constants which never finish must not block
+            // the server
+            Ok(_) => match compile_optimized_with_limit(node.clone(), CONST_EVAL_LIMIT) {
+                Ok((_, _, sizes)) => document.sizes = sizes,
+                Err(diagnostic) => document.diagnostics.push(diagnostic),
+            },
+            Err(diagnostic) => document.diagnostics.push(diagnostic),
+        }
+        document.program = Some((node, sources));
+        document
+    }
+
+    fn diagnostics(&self) -> Value {
+        let diagnostics: Vec<_> = self
+            .diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let span = match diagnostic.span.source {
+                    0 => diagnostic.span,
+                    _ => Span::default(),
+                };
+                let mut message = diagnostic.message.clone();
+                for note in diagnostic.notes.iter() {
+                    message.push_str(&format!("\nnote: {note}"));
+                }
+                json!({
+                    "range": range(&self.text, span),
+                    "severity": 1,
+                    "source": "lasm",
+                    "message": message,
+                })
+            })
+            .collect();
+        Value::Array(diagnostics)
+    }
+
+    fn hover(&self, offset: usize) -> Value {
+        let Some((node, _)) = &self.program else {
+            return Value::Null;
+        };
+        let Some((span, ty)) = self
+            .types
+            .iter()
+            .filter(|(span, _)| contains(*span, offset))
+            .min_by_key(|(span, _)| span.end - span.start)
+        else {
+            return Value::Null;
+        };
+        let name = &self.text[span.start..span.end];
+        let mut contents = format!("```lasm\n{name} : {ty}\n```");
+
+        // top level bindings show how much they cost once compiled
+        let binding = definition(node, offset, &mut vec![]).flatten();
+        let top_level = top_level_bindings(node);
+        if let Some((name, _)) = top_level.iter().find(|(_, s)| Some(*s) == binding) {
+            let name = name.to_string();
+            if let Some(size) = self.sizes.iter().find(|size| size.name == name) {
+                contents.push_str(&format!("\n\n{} bytes once compiled", size.size));
+            }
+        }
+        json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": range(&self.text, *span),
+        })
+    }
+
+    fn definition(&self, uri: &str, offset: usize) -> Value {
+        let Some((node, sources)) = &self.program else {
+            return Value::Null;
+        };
+        let Some(Some(span)) = definition(node, offset, &mut vec![]) else {
+            return Value::Null;
+        };
+        let (uri, text) = match span.source {
+            0 => (uri.to_owned(), self.text.as_str()),
+            // bundled libraries aren't files
+            source if sources[source].path.is_absolute() => (
+                path_to_uri(&sources[source].path),
+                sources[source].text.as_str(),
+            ),
+            _ => return Value::Null,
+        };
+        json!({ "uri": uri, "range": range(text, span) })
+    }
+}
+
+// the bindings of the prelude, along with their types
+fn prelude_completions() -> Value {
+    let Ok((node, _)) = load_str("import \"@prelude\"; 0", Path::new(".")) else {
+        return json!([]);
+    };
+    let (types, _) = type_spans(&node);
+    let items: Vec<_> = top_level_bindings(&node)
+        .into_iter()
+        .map(|(name, span)| {
+            let ty = types.iter().find(|(s, _)| *s == span).map(|(_, ty)| ty);
+            let kind = match ty {
+                Some(Type::Function(..)) => 3,
+                _ => 6,
+            };
+            json!({
+                "label": name.to_string(),
+                "kind": kind,
+                "detail": ty.map(|ty| ty.to_string()),
+            })
+        })
+        .collect();
+    Value::Array(items)
+}
+
+pub struct Server<W: Write> {
+    out: W,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    // handles messages until the client asks to exit
+    pub fn serve(&mut self, input: &mut impl BufRead) -> io::Result<()> {
+        while let Some(message) = read_message(input)? {
+            if message["method"] == "exit" {
+                break;
+            }
+            self.handle(&message)?;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, uri: &str, text: String) -> io::Result<()> {
+        let document = Document::analyze(&uri_to_path(uri), text);
+        let params = json!({ "uri": uri, "diagnostics": document.diagnostics() });
+        self.documents.insert(uri.to_owned(), document);
+        self.notify("textDocument/publishDiagnostics", params)
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&mut self.out, &message)
+    }
+
+    // the document and offset a request is about
+    fn target<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a Document, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let offset = offset(&document.text, &params["position"])?;
+        Some((uri, document, offset))
+    }
+
+    fn handle(&mut self, message: &Value) -> io::Result<()> {
+        if let (true, Some(id)) = (self.shutdown, message.get("id")) {
+            let error = json!({ "code": -32600, "message": "the server is shut down" });
+            let response = json!({ "jsonrpc": "2.0", "id": id, "error": error });
+            return write_message(&mut self.out, &response);
+        }
+
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": { "name": "lasm" },
+            }),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                return self.open(uri, text.to_owned());
+            }
+            "textDocument/didChange" => {
+                // the whole text is sent on each change
+                let changes = params["contentChanges"].as_array();
+                let text = changes
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                return match text {
+                    Some(text) => self.open(uri, text.to_owned()),
+                    None => Ok(()),
+                };
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return self.notify(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                );
+            }
+            "textDocument/hover" => match self.target(params) {
+                Some((_, document, offset)) => document.hover(offset),
+                None => Value::Null,
+            },
+            "textDocument/definition" => match self.target(params) {
+                Some((uri, document, offset)) => document.definition(uri, offset),
+                None => Value::Null,
+            },
+            "textDocument/completion" => prelude_completions(),
+            method => {
+                // notifications we don't handle are ignored
+                let Some(id) = message.get("id") else {
+                    return Ok(());
+                };
+                let error =
+                    json!({ "code": -32601, "message": format!("unknown method {method}") });
+                let response = json!({ "jsonrpc": "2.0", "id": id, "error": error });
+                return write_message(&mut self.out, &response);
+            }
+        };
+        let response = json!({ "jsonrpc": "2.0", "id": message["id"], "result": result });
+        write_message(&mut self.out, &response)
+    }
+}
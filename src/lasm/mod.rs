@@ -1,24 +1,80 @@
 mod ast;
 mod compiler;
+mod const_eval;
+mod formatter;
+mod interpreter;
+mod loader;
+mod lsp;
+mod optimizer;
 mod parser;
+mod recursion;
+mod source;
+mod test_runner;
+mod typing;
 
 pub use ast::{Iden, LNode, LNodeRef};
-pub use compiler::compile;
+pub use compiler::{compile, compile_optimized, compile_with_source_map, evaluate};
+pub use formatter::format;
+pub use interpreter::run;
+pub use loader::{load, load_str, load_tests, load_tests_str};
+pub use lsp::LspCommand;
 pub use parser::parse;
+pub use source::SourceFile;
+pub use test_runner::run_tests;
 
 #[cfg(test)]
 mod tests {
-    use std::rc::Rc;
-
     use num::BigInt;
     use num::FromPrimitive;
 
     use super::compile;
+    use super::compile_optimized;
+    use super::compile_with_source_map;
+    use super::evaluate as evaluate_compiled;
+    use super::format;
+    use super::interpreter::Interpreter;
+    use super::loader::LoadError;
     use super::parse;
+    use super::parser::parse_program;
+    use super::run;
+    use super::typing::type_check;
+    use super::SourceFile;
+    use super::{load, load_str, load_tests_str, run_tests};
     use crate::icfp::evaluate;
+    use crate::icfp::evaluate_counting;
+    use crate::icfp::serialize_str;
     use crate::lasm::ast::BinaryOp;
+    use crate::lasm::ast::EvalStrat;
     use crate::lasm::LNode;
 
+    // a temporary directory of its own for each test, removed once the test is done
+    struct TestDir(std::path::PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            static COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+            let count = COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let dir =
+                std::env::temp_dir().join(format!("lasm_{name}_{}_{count}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl std::ops::Deref for TestDir {
+        type Target = std::path::Path;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
     #[test]
     fn test_mess() {
         let sample = r#"
@@ -29,7 +85,7 @@ mod tests {
             in (f 2 a) + fac 3
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         assert_eq!(evaluate(node).as_int(), &10.into());
     }
 
@@ -39,7 +95,7 @@ mod tests {
             "ab" take 1
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         println!("{:#?}", evaluate(node));
         // assert_eq!(evaluate(node).as_str(), "a");
     }
@@ -51,7 +107,7 @@ mod tests {
             a . "b"
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         println!("{:#?}", evaluate(node));
         // assert_eq!(evaluate(node).as_str(), "a");
     }
@@ -66,7 +122,7 @@ mod tests {
             }
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         assert_eq!(evaluate(node).as_int(), &BigInt::from_u8(4).unwrap());
     }
 
@@ -79,7 +135,7 @@ mod tests {
             in (f 2 a)
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         assert_eq!(evaluate(node).as_int(), &4.into());
     }
 
@@ -90,7 +146,7 @@ mod tests {
         "#;
         println!("{}", sample);
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         assert_eq!(evaluate(node).as_str(), "ab\"\\");
     }
 
@@ -100,10 +156,47 @@ mod tests {
             "ab"
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         assert_eq!(evaluate(node).as_str(), "ab");
     }
 
+    #[test]
+    fn test_string_escapes() {
+        let node = parse(r##""a\nb\x41\x7e\"\\" . r"x\y" . r#"say "hi""#"##).unwrap();
+        assert_eq!(
+            evaluate(compile(node).unwrap()).as_str(),
+            "a\nbA~\"\\x\\ysay \"hi\""
+        );
+
+        // the newline after the opening quote is skipped
+        let grid = "let grid = r\"\n###\n#.L\n\"; in grid";
+        let node = parse(grid).unwrap();
+        assert_eq!(
+            evaluate(compile(node.clone()).unwrap()).as_str(),
+            "###\n#.L\n"
+        );
+        assert_eq!(parse(&format(grid).unwrap()), Ok(node));
+
+        // tabs are only for the interpreter
+        let node = parse(r#""a\tb""#).unwrap();
+        let value = Interpreter::new(&[]).run(&node).unwrap();
+        assert_eq!(value.as_str(), "a\tb");
+        let err = compile_with_source_map(node).unwrap_err();
+        assert_eq!(err.message, "ICFP strings can't hold '\\t'");
+
+        let errors = [
+            ("\"caf\u{e9}\"", "strings can't contain '\u{e9}'", 4),
+            (r#""a\qb""#, r"invalid escape sequence \q", 3),
+            (r#""\x01""#, r"invalid escape sequence \x01", 2),
+            (r##"r#"a"##, "unterminated string", 4),
+        ];
+        for (sample, message, offset) in errors {
+            let err = parse(sample).unwrap_err();
+            assert_eq!(err.message, message, "{sample}");
+            assert_eq!(err.span.start, offset, "{sample}");
+        }
+    }
+
     #[test]
     fn test_apply_unuary_precedence() {
         let sample = r#"
@@ -111,7 +204,7 @@ mod tests {
             in mul_two 1 - 1
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         assert_eq!(evaluate(node).as_int(), &1.into());
     }
 
@@ -122,7 +215,7 @@ mod tests {
             in 2 * mul_two 1
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         assert_eq!(evaluate(node).as_int(), &4.into());
     }
 
@@ -132,7 +225,7 @@ mod tests {
             2 + 1 * 2 - 1
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         assert_eq!(evaluate(node).as_int(), &5.into());
     }
 
@@ -143,7 +236,7 @@ mod tests {
             in fac 3
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         assert_eq!(evaluate(node).as_int(), &6.into());
     }
 
@@ -154,7 +247,7 @@ mod tests {
             in fac 3
         "#;
         let node = parse(sample).unwrap();
-        let node = compile(node);
+        let node = compile(node).unwrap();
         assert_eq!(evaluate(node).as_int(), &6.into());
     }
 
@@ -162,7 +255,44 @@ mod tests {
     fn test_integer() {
         let sample = r" 1 ";
         let node = parse(sample).unwrap();
-        assert_eq!(evaluate(compile(node)).as_int(), &1.into());
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &1.into());
+    }
+
+    #[test]
+    fn test_big_integer() {
+        let sample = "123456789012345678901234567890 + 1";
+        let node = parse(sample).unwrap();
+        let expected: BigInt = "123456789012345678901234567891".parse().unwrap();
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &expected);
+
+        let node = parse("let b94_val = 2; b94x = 3; in b94_val * b94x").unwrap();
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &6.into());
+
+        let node = parse(r#"0xff + b94"\"!""#).unwrap();
+        assert_eq!(
+            evaluate(compile(node).unwrap()).as_int(),
+            &(255 + 94).into()
+        );
+    }
+
+    #[test]
+    fn test_negative_integer() {
+        let node = compile(parse("-5").unwrap()).unwrap();
+        assert_eq!(serialize_str(node), "U- I&");
+
+        let node = parse("-5 + 1").unwrap();
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &(-4).into());
+
+        let node = parse("let f x = x * 2; in f (-3) - -1 + 4 -1").unwrap();
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &(-2).into());
+
+        // prefix operators bind tighter than infix ones
+        let node = parse("-(5) + 1").unwrap();
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &(-4).into());
+        let node = parse("let f x = x * 2; in -f 3 + 10").unwrap();
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &4.into());
+        let node = parse("!true | true").unwrap();
+        assert!(evaluate(compile(node).unwrap()).as_bool());
     }
 
     #[test]
@@ -172,7 +302,7 @@ mod tests {
             1
         "#;
         let node = parse(sample).unwrap();
-        assert_eq!(evaluate(compile(node)).as_int(), &1.into());
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &1.into());
     }
 
     #[test]
@@ -195,7 +325,7 @@ mod tests {
             in a
         "#;
         let node = parse(sample).unwrap();
-        assert_eq!(evaluate(compile(node)).as_int(), &1.into());
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &1.into());
     }
 
     #[test]
@@ -204,7 +334,7 @@ mod tests {
             let f a = a; in f 1
         "#;
         let node = parse(sample).unwrap();
-        assert_eq!(evaluate(compile(node)).as_int(), &1.into());
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &1.into());
     }
 
     #[test]
@@ -213,7 +343,7 @@ mod tests {
             if true { 1 } else { 2 }
         "#;
         let node = parse(sample).unwrap();
-        assert_eq!(evaluate(compile(node)).as_int(), &1.into());
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &1.into());
     }
 
     #[test]
@@ -223,11 +353,7 @@ mod tests {
         "#;
         let node = parse(sample).unwrap();
         assert_eq!(
-            Rc::new(LNode::BinaryOp {
-                op: BinaryOp::IntSub,
-                left: LNode::var("x".to_owned()),
-                right: LNode::int(1.into()),
-            }),
+            LNode::binary_op(BinaryOp::IntSub, LNode::var("x".to_owned()), LNode::int(1)),
             node
         );
     }
@@ -252,4 +378,692 @@ mod tests {
         let node = parse(sample).unwrap();
         println!("{:#?}", node);
     }
+
+    #[test]
+    fn test_apply_strat() {
+        let sample = r#"
+            f! a b
+        "#;
+        let node = parse(sample).unwrap();
+        assert_eq!(
+            LNode::apply_strat(
+                EvalStrat::Value,
+                LNode::apply_strat(
+                    EvalStrat::Value,
+                    LNode::var("f".to_owned()),
+                    LNode::var("a".to_owned())
+                ),
+                LNode::var("b".to_owned())
+            ),
+            node
+        );
+
+        let node = parse("f~ a").unwrap();
+        assert_eq!(
+            LNode::apply_strat(
+                EvalStrat::Lazy,
+                LNode::var("f".to_owned()),
+                LNode::var("a".to_owned())
+            ),
+            node
+        );
+
+        let err = parse("f! + 1").unwrap_err();
+        assert_eq!(
+            err.message,
+            "expected an argument after the strategy marker"
+        );
+        assert!(parse("let f x = x; in f~").is_err());
+    }
+
+    #[test]
+    fn test_let_strat() {
+        let sample = r#"
+            let~ a = 1 + 2;
+                 f x = x * 2;
+            in f~ a
+        "#;
+        let node = compile(parse(sample).unwrap()).unwrap();
+        let bin = serialize_str(node.clone());
+        assert_eq!(bin.matches("B~").count(), 3);
+        assert_eq!(evaluate(node).as_int(), &6.into());
+
+        let sample = r#"
+            let! a = 1; in a
+        "#;
+        let node = compile(parse(sample).unwrap()).unwrap();
+        assert!(serialize_str(node.clone()).starts_with("B! "));
+        assert_eq!(evaluate(node).as_int(), &1.into());
+    }
+
+    #[test]
+    fn test_prelude() {
+        let sample = r#"
+            import "@prelude";
+            repeat "ab" 3 . decimal 1207 . char_at "xyz" 1 . decimal (length "abcd")
+        "#;
+        let node = load_str(sample, &TestDir::new("prelude")).unwrap().0;
+        assert_eq!(evaluate(compile(node).unwrap()).as_str(), "ababab1207y4");
+
+        let sample = r#"
+            import "@prelude";
+            let add acc i = acc + i;
+                double x = x * 2;
+            in fold_range 1 5 add 0 + times 3 double 1 + min 3 (0 - 7) + max 3 4 + abs (0 - 5)
+        "#;
+        let node = load_str(sample, &TestDir::new("prelude")).unwrap().0;
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &20.into());
+    }
+
+    #[test]
+    fn test_dead_code_elimination() {
+        let sample = r#"
+            let a = "unused";
+                b = 1;
+                f x = x * b;
+            in b
+        "#;
+        let node = compile(parse(sample).unwrap()).unwrap();
+        assert_eq!(node, compile(parse("let b = 1; in b").unwrap()).unwrap());
+
+        let node = load_str(
+            "import \"@prelude\"; 1",
+            &TestDir::new("dead_code_elimination"),
+        )
+        .unwrap()
+        .0;
+        assert_eq!(serialize_str(compile(node).unwrap()), "I\"");
+    }
+
+    #[test]
+    fn test_import_file() {
+        let dir = TestDir::new("import_file");
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("lib").join("a.lasm"),
+            "import \"b.lasm\"; let a x = b x + 1;",
+        )
+        .unwrap();
+        std::fs::write(dir.join("lib").join("b.lasm"), "let b x = x * 2;").unwrap();
+        std::fs::write(
+            dir.join("main.lasm"),
+            "import \"lib/a.lasm\";\nimport \"lib/b.lasm\";\na (b 3)",
+        )
+        .unwrap();
+        let (node, sources) = load(&dir.join("main.lasm")).unwrap();
+        assert_eq!(sources.len(), 3);
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &13.into());
+
+        std::fs::write(
+            dir.join("lib").join("b.lasm"),
+            "import \"a.lasm\"; let b x = x;",
+        )
+        .unwrap();
+        assert!(matches!(
+            load(&dir.join("main.lasm")),
+            Err(LoadError::ImportCycle(_))
+        ));
+    }
+
+    #[test]
+    fn test_const() {
+        let sample = r#"
+            let double s = s . s;
+                const moves = double ("RRDD" . "LLUU");
+                const n = 94 * 94;
+                f x = x + n;
+            in moves take (f 0 - 8820)
+        "#;
+        let node = compile(parse(sample).unwrap()).unwrap();
+        let bin = serialize_str(node.clone());
+        let moves = serialize_str(compile(LNode::str("RRDDLLUURRDDLLUU")).unwrap());
+        assert!(bin.contains(&moves));
+        assert!(bin.contains("I\"!!"));
+        assert!(!bin.contains("B."));
+        assert_eq!(evaluate(node).as_str(), "RRDDLLUURRDDLLUU");
+
+        // parameters shadow constants
+        let sample = r#"
+            let const x = 1;
+                f x = x * 2;
+            in f 3 + x
+        "#;
+        let node = compile(parse(sample).unwrap()).unwrap();
+        assert_eq!(evaluate(node).as_int(), &7.into());
+    }
+
+    #[test]
+    fn test_const_not_closed() {
+        let sample = r#"
+            let f a = let const b = a + 1; in b;
+            in f 1
+        "#;
+        let err = compile(parse(sample).unwrap()).unwrap_err();
+        assert_eq!(err.message, "const b isn't closed, it uses a");
+    }
+
+    #[test]
+    fn test_const_fails() {
+        let sample = "let const x = 1 / 0;\nin x";
+        let err = compile_with_source_map(parse(sample).unwrap()).unwrap_err();
+        assert_eq!(
+            err.message,
+            "const x failed to evaluate: attempt to divide by zero"
+        );
+        assert_eq!(&sample[err.span.start..err.span.end], "x");
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let node = compile(parse(r#"2 * 3 + 4 - 1"#).unwrap()).unwrap();
+        assert_eq!(serialize_str(node), "I*");
+
+        let sample = r#"let x = 5; in if 1 < 2 { x } else { str2int("ab" . "cd") }"#;
+        let node = compile(parse(sample).unwrap()).unwrap();
+        assert_eq!(node, compile(parse("let x = 5; in x").unwrap()).unwrap());
+
+        let node = compile(parse(r#"let x = "a"; in x . ("ab" . "cd")"#).unwrap()).unwrap();
+        assert!(serialize_str(node).contains("B. v! S!\"#$"));
+
+        // invalid operations are left to the evaluator
+        let node = compile(parse(r#"1 / 0"#).unwrap()).unwrap();
+        assert_eq!(serialize_str(node), "B/ I\" I!");
+    }
+
+    #[test]
+    fn test_spans() {
+        let sample = "let a = 1;\nin a + f 2";
+        let node = parse(sample).unwrap();
+        let LNode::Let { bindings, body, .. } = node.as_ref() else {
+            panic!("expected a let");
+        };
+        assert_eq!(&sample[bindings[0].span.start..bindings[0].span.end], "a");
+        assert_eq!(&sample[body.span.start..body.span.end], "a + f 2");
+        let LNode::BinaryOp { right, .. } = body.as_ref() else {
+            panic!("expected a binary op");
+        };
+        assert_eq!(&sample[right.span.start..right.span.end], "f 2");
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let sample = "let a = 1;\nin a + (2";
+        let err = parse(sample).unwrap_err();
+        let rendered = err.render(&[SourceFile::new("test.lasm", sample)]);
+        assert!(rendered.starts_with("error: "));
+        assert!(rendered.contains("--> test.lasm:2:"));
+        assert!(rendered.contains("2 | in a + (2"));
+    }
+
+    #[test]
+    fn test_unbound_variable() {
+        let sample = "let a = 1;\nin a + b";
+        let err = compile_with_source_map(parse(sample).unwrap()).unwrap_err();
+        assert_eq!(err.message, "unbound variable b");
+        assert_eq!(
+            err.render(&[SourceFile::new("test.lasm", sample)]),
+            "error: unbound variable b\n --> test.lasm:2:8\n  |\n2 | in a + b\n  |        ^\n"
+        );
+    }
+
+    #[test]
+    fn test_source_map() {
+        let sample = "let f x = x + 1;\nin f 2";
+        let (node, source_map) = compile_with_source_map(parse(sample).unwrap()).unwrap();
+        let span = source_map.get(&node).unwrap();
+        assert_eq!(&sample[span.start..span.end], "f");
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let sample = "let f x = 10 / x;\nin f 0 + f 1";
+        let (node, source_map) = compile_with_source_map(parse(sample).unwrap()).unwrap();
+        let err = evaluate_compiled(node, &source_map).unwrap_err();
+        assert_eq!(
+            err.render(&[SourceFile::new("test.lasm", sample)]),
+            "error: evaluation failed: attempt to divide by zero\n --> test.lasm:1:11\n  |\n1 | let f x = 10 / x;\n  |           ^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_interpreter_agrees() {
+        let samples = [
+            "1 + 2 * 3",
+            r#"let f x = x * 2; in f 3 + 4"#,
+            r#"let rec fact n = if n < 2 { 1 } else { n * fact (n - 1) }; in fact 20"#,
+            r#"let~ a = 1 / 0; b = 2; in b"#,
+            r#"let g f x = f (f x); h x = x . "!"; in g h "hey""#,
+            r#"let const n = 3 * 4; f x = x - n; in f (-1) == -13"#,
+            r#"import "@prelude"; repeat "ab" 3 . decimal 1207 . char_at "xyz" 1"#,
+            r#"import "@prelude"; let add acc i = acc + i; in fold_range 1 10 add 0"#,
+        ];
+        for sample in samples {
+            let (node, sources) = load_str(sample, &TestDir::new("interpreter_agrees")).unwrap();
+            let interpreted = run(&node, &sources).unwrap();
+            assert_eq!(interpreted, evaluate(compile(node).unwrap()), "{sample}");
+        }
+    }
+
+    #[test]
+    fn test_interpreter_error() {
+        let sample = "let f x = x + \"a\";\n    g x = f (x * 2);\nin g 1";
+        let sources = [SourceFile::new("test.lasm", sample)];
+        let err = run(&parse(sample).unwrap(), &sources).unwrap_err();
+        assert_eq!(err.message, "invalid operands for IntAdd: 2 and \"a\"");
+        assert_eq!(
+            err.notes,
+            vec![
+                "in f, called at test.lasm:2:11".to_owned(),
+                "in g, called at test.lasm:3:4".to_owned()
+            ]
+        );
+        assert!(err.render(&sources).contains("1 | let f x = x + \"a\";"));
+    }
+
+    #[test]
+    fn test_interpreter_deep_recursion() {
+        // the interpreter keeps its continuations on the heap, loops don't overflow the stack
+        let tail = r#"
+            let rec count n acc = if n < 1 { acc } else { count (n - 1) (acc + n) };
+            in count 100000 0
+        "#;
+        let node = parse(tail).unwrap();
+        assert_eq!(
+            Interpreter::new(&[]).run(&node).unwrap().as_int(),
+            &5000050000u64.into()
+        );
+
+        let not_tail =
+            "let rec count n = if n < 1 { 0 } else { n + count (n - 1) }; in count 100000";
+        let node = parse(not_tail).unwrap();
+        assert_eq!(
+            Interpreter::new(&[]).run(&node).unwrap().as_int(),
+            &5000050000u64.into()
+        );
+    }
+
+    #[test]
+    fn test_trace_assert() {
+        let sample = r#"let f x = assert (x > 0) (trace "x" x * 2); in f 3 + f 4"#;
+        let node = parse(sample).unwrap();
+        let mut interpreter = Interpreter::new(&[]);
+        assert_eq!(interpreter.run(&node).unwrap().as_int(), &14.into());
+        assert_eq!(interpreter.traces, vec!["\"x\": 3", "\"x\": 4"]);
+
+        // builtins are dropped by the compiler
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &14.into());
+
+        let node = parse("let f x = assert (x > 0) x; in f (0 - 1)").unwrap();
+        let err = Interpreter::new(&[]).run(&node).unwrap_err();
+        assert_eq!(err.message, "assertion failed");
+        assert_eq!(err.notes.len(), 1);
+
+        // bindings of the same name shadow the builtins
+        let node = parse("let trace a b = a * b; in trace 3 4").unwrap();
+        assert_eq!(
+            Interpreter::new(&[]).run(&node).unwrap().as_int(),
+            &12.into()
+        );
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &12.into());
+        let node = parse("let f assert = assert 1 2; g a b = a - b; in f g").unwrap();
+        assert_eq!(
+            Interpreter::new(&[]).run(&node).unwrap().as_int(),
+            &(-1).into()
+        );
+        assert_eq!(evaluate(compile(node).unwrap()).as_int(), &(-1).into());
+    }
+
+    #[test]
+    fn test_inline_tests() {
+        let sample = r#"
+            test "double" { f 2 } == 4;
+            test "wrong" { f 1 } == 3;
+            let f x = x * 2;
+            in f 5
+        "#;
+        let (tests, sources) = load_tests_str(sample, &TestDir::new("inline_tests")).unwrap();
+        assert_eq!(tests.len(), 2);
+        let mut out = vec![];
+        assert_eq!(run_tests(&tests, &sources, &mut out).unwrap(), 1);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("test double ... ok"));
+        assert!(out.contains("test wrong ... FAILED"));
+        assert!(out.contains("error: expected 3, got 2"));
+
+        // tests are stripped from the compiled program
+        let (node, _) = load_str(sample, &TestDir::new("inline_tests")).unwrap();
+        assert_eq!(
+            compile(node).unwrap(),
+            compile(parse("let f x = x * 2; in f 5").unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_prelude_tests() {
+        let prelude = include_str!("prelude.lasm");
+        let (tests, sources) = load_tests_str(prelude, &TestDir::new("prelude_tests")).unwrap();
+        assert!(!tests.is_empty());
+        let mut out = vec![];
+        let failed = run_tests(&tests, &sources, &mut out).unwrap();
+        assert_eq!(failed, 0, "{}", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_format() {
+        let sample = r#"
+            // header
+            import "@prelude";
+            let   f x =x*  2;  // doubles
+                  rec   g n = if n<1 {0} else {n + g (n - 1)};
+
+                  s = "a\"b" . (str2int"c" . "d") . "e" drop 1 take 2;
+            in f (-1) + g 3 - -2
+        "#;
+        let expected = r#"// header
+import "@prelude";
+let
+    f x = x * 2;  // doubles
+    rec g n = if n < 1 { 0 } else { n + g (n - 1) };
+
+    s = "a\"b" . (str2int "c" . "d") . "e" drop 1 take 2;
+in f (-1) + g 3 - -2
+"#;
+        let formatted = format(sample).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(parse_program(&formatted), parse_program(sample));
+
+        // long expressions are split
+        let sample = format!(
+            "let f a b = a . b; in f ({}\"z\") x",
+            "\"abcd\" . ".repeat(30)
+        );
+        let formatted = format(&sample).unwrap();
+        assert!(formatted.lines().all(|line| line.len() <= 100));
+        assert_eq!(parse_program(&formatted), parse_program(&sample));
+    }
+
+    #[test]
+    fn test_format_idempotent() {
+        let long_chain = (0..40)
+            .map(|i| format!("\"{i}\""))
+            .collect::<Vec<_>>()
+            .join(" . ");
+        let samples = [
+            include_str!("prelude.lasm").to_owned(),
+            format!("let x = {long_chain}; in x"),
+            format!("test \"t\" {{ {long_chain} }} == \"\"; 1"),
+            "let f x = -(x + 1); g x = !(x < 2); in if g! (f 1) { 1 } else { let~ y = 2; in y }"
+                .to_owned(),
+            format!(
+                "let (a, b) = ({long_chain}, [1, 2]); in match b {{ nil => a, cons h t => h }}"
+            ),
+            // trailing comments stay on the line of their item
+            "// header\ntest \"t\" { f 1 } == 2; // ok\nlet\n    f x = x * 2; // doubles\n    // the rest\n    g = str2int\"c\";  // c\n\n    h = let a = 1; // one\n        in a;\nin f g + h // done\n"
+                .to_owned(),
+        ];
+        for sample in samples {
+            let formatted = format(&sample).unwrap();
+            assert_eq!(
+                parse_program(&formatted),
+                parse_program(&sample),
+                "{formatted}"
+            );
+            assert_eq!(format(&formatted).unwrap(), formatted);
+        }
+    }
+
+    #[test]
+    fn test_tuples_and_lists() {
+        let samples = [
+            r#"let (a, b) = (1, 2); in a + b"#,
+            r#"let p = (1, "a", true); in match p { (n, s, c) => if c { n + 1 } else { n } }"#,
+            r#"let rec sum l = match l { nil => 0, cons h t => h + sum t }; in sum [1, 2, 3]"#,
+            r#"let rec len l = match l { cons h t => 1 + len t, nil => 0 }; in len (cons 7 [8])"#,
+            r#"let x = 4; (y, z) = (x * 2, x + 1); w = y - z; in w"#,
+        ];
+        for sample in samples {
+            let (node, sources) = load_str(sample, &TestDir::new("tuples_and_lists")).unwrap();
+            let interpreted = run(&node, &sources).unwrap();
+            assert_eq!(interpreted, evaluate(compile(node).unwrap()), "{sample}");
+        }
+
+        // items are lazy, and destructuring follows the strategy of its let
+        let node = parse("(1, 2)").unwrap();
+        assert_eq!(serialize_str(compile(node).unwrap()), "L! B~ B~ v! I\" I#");
+        let node = parse("let~ (a, b) = (1 / 0, 2); in b").unwrap();
+        assert_eq!(run(&node, &[]).unwrap().as_int(), &2.into());
+        let node = parse("let (a, b) = (1 / 0, 2); in b").unwrap();
+        assert!(run(&node, &[]).is_err());
+    }
+
+    #[test]
+    fn test_match_errors() {
+        let node = parse("match [1] { nil => 0 }").unwrap();
+        let err = compile_with_source_map(node).unwrap_err();
+        assert_eq!(err.message, "the match doesn't handle cons");
+
+        let node = parse("match (1, 2) { nil => 0, (a, b) => a }").unwrap();
+        let err = compile_with_source_map(node).unwrap_err();
+        assert_eq!(
+            err.message,
+            "the pattern expects ['a], but the matched value is (Int, Int)"
+        );
+    }
+
+    #[test]
+    fn test_type_check() {
+        let samples = [
+            ("1 + 2 * 3", "Int"),
+            (r#"let f x = x . "!"; in f"#, "Str -> Str"),
+            ("let id x = x; in (id 1, id true)", "(Int, Bool)"),
+            ("let pair a b = (b, a); in pair", "'a -> 'b -> ('b, 'a)"),
+            (
+                "let rec map f l = match l { nil => nil, cons h t => cons (f h) (map f t) }; in map",
+                "('a -> 'b) -> ['a] -> ['b]",
+            ),
+            (r#"let x = 3; in trace "x" (assert (x > 2) x)"#, "Int"),
+            ("let f x = int2str x; in f", "Int -> Str"),
+            (r#"int2str (str2int "b" + 1)"#, "Str"),
+            (
+                r#"import "@prelude"; repeat "a" 3 . decimal (fold_range 0 4 max 0)"#,
+                "Str",
+            ),
+        ];
+        for (sample, expected) in samples {
+            let (node, _) = load_str(sample, &TestDir::new("type_check")).unwrap();
+            assert_eq!(type_check(&node).unwrap().to_string(), expected, "{sample}");
+        }
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let samples = [
+            (r#"1 + "a""#, "`+` expects Int, found Str", "1:5"),
+            (
+                "let f x = x * 2;\nin f true",
+                "f expects Int, found Bool",
+                "2:6",
+            ),
+            (
+                "let rec f n = if n < 1 { 0 } else { f (n - 1) . \"\" }; in f 3",
+                "if branches have different types: expected Int, found Str",
+                "1:37",
+            ),
+            (
+                "let f x = x x; in 1",
+                "infinite type: 'a = 'a -> 'b",
+                "1:11",
+            ),
+            (
+                r#"if 1 { 2 } else { 3 }"#,
+                "the condition must be Bool, found Int",
+                "1:4",
+            ),
+            (
+                r#"[1, "a"]"#,
+                "list items have different types: expected [Int], found [Str]",
+                "1:5",
+            ),
+            (
+                "let x = 1; in x 2",
+                "x isn't a function, it has type Int",
+                "1:15",
+            ),
+            (
+                "let f x = x; in (f == f)",
+                "`==` can't compare 'a -> 'a, only Int, Bool and Str",
+                "1:17",
+            ),
+        ];
+        for (sample, message, position) in samples {
+            let source = SourceFile::new("test.lasm", sample);
+            let err = type_check(&parse(sample).unwrap()).unwrap_err();
+            assert_eq!(err.message, message, "{sample}");
+            let rendered = err.render(std::slice::from_ref(&source));
+            assert!(
+                rendered.contains(&format!("test.lasm:{position}\n")),
+                "{rendered}"
+            );
+        }
+
+        let err = type_check(&parse(r#"let g y = (let h z = z + "a"; in h y); in g"#).unwrap());
+        assert_eq!(
+            err.unwrap_err().notes,
+            ["in the definition of h", "in the definition of g"]
+        );
+    }
+
+    #[test]
+    fn test_optimize() {
+        let samples = [
+            "let a = 1; b = a + 1; f x y = x * y + b; in f 2 a + f 3 b",
+            r#"let rec fact n = if n < 2 { 1 } else { n * fact (n - 1) }; in fact 20"#,
+            r#"let rec count n acc = if n < 1 { acc } else { count (n - 1) (acc + n) }; in count 10 0"#,
+            r#"let~ a = 1 / 0; b = 2; in b"#,
+            r#"let rec sum l = match l { nil => 0, cons h t => h + sum t }; in sum [1, 2, 3]"#,
+            r#"import "@prelude"; repeat "ab" 3 . decimal 1207 . char_at "xyz" 1"#,
+            r#"import "@prelude"; let add acc i = acc + i; in fold_range 1 10 add 0"#,
+        ];
+        for sample in samples {
+            let (node, _) = load_str(sample, &TestDir::new("optimize")).unwrap();
+            let (optimized, _, sizes) = compile_optimized(node.clone()).unwrap();
+            let compiled = compile(node).unwrap();
+            let size = serialize_str(optimized.clone()).len();
+            assert!(size <= serialize_str(compiled.clone()).len(), "{sample}");
+            assert_eq!(
+                sizes.iter().map(|s| s.size).sum::<usize>(),
+                size,
+                "{sample}"
+            );
+            assert_eq!(evaluate(optimized), evaluate(compiled), "{sample}");
+        }
+    }
+
+    #[test]
+    fn test_tail_recursion() {
+        let tail = r#"
+            let rec count n acc = if n < 1 { acc } else { count (n - 1) (acc + n) };
+                rec fact n = if n < 2 { 1 } else { n * fact (n - 1) };
+            in count 1000 0
+        "#;
+        // the same loop, which isn't a tail call anymore
+        let not_tail = r#"
+            let rec count n acc = if n < 1 { acc } else { 0 + count (n - 1) (acc + n) };
+            in count 1000 0
+        "#;
+        let compiled = compile(parse(tail).unwrap()).unwrap();
+        let (value, substitutions) = evaluate_counting(compiled.clone());
+        let (expected, y_substitutions) =
+            evaluate_counting(compile(parse(not_tail).unwrap()).unwrap());
+        assert_eq!(value, expected);
+        // three beta reductions per iteration instead of four
+        assert_eq!(substitutions / 1000, 3);
+        assert_eq!(y_substitutions / 1000, 4);
+
+        // the y combinator isn't needed, and the accumulators are passed by value
+        let code = serialize_str(compiled);
+        assert!(code.contains("B!"));
+        let (optimized, _, sizes) = compile_optimized(parse(tail).unwrap()).unwrap();
+        assert_eq!(evaluate(optimized), value);
+        let names: Vec<_> = sizes.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["count", "<body>"]);
+    }
+
+    #[test]
+    fn test_lsp() {
+        use super::lsp::{read_message, write_message, Server};
+        use serde_json::json;
+
+        let uri = "file:///tmp/test.lasm";
+        let broken = "import \"@prelude\";\nlet double n = n * 2;\nin double 3 . \"a\"\n";
+        let fixed = "import \"@prelude\";\nlet double n = n * 2;\nin double (abs 3)\n";
+        let diverging = "let rec f n = f (n + 1);\nconst x = f 0;\nin x\n";
+        let change = |version, text| {
+            json!({ "method": "textDocument/didChange", "params": {
+                "textDocument": { "uri": uri, "version": version },
+                "contentChanges": [{ "text": text }]
+            } })
+        };
+        let position = |line, character| json!({ "textDocument": { "uri": uri }, "position": { "line": line, "character": character } });
+        let script = [
+            json!({ "id": 1, "method": "initialize", "params": {} }),
+            json!({ "method": "initialized", "params": {} }),
+            json!({ "method": "textDocument/didOpen", "params": {
+                "textDocument": { "uri": uri, "languageId": "lasm", "version": 1, "text": broken }
+            } }),
+            change(2, diverging),
+            change(3, fixed),
+            json!({ "id": 2, "method": "textDocument/hover", "params": position(2, 4) }),
+            json!({ "id": 3, "method": "textDocument/definition", "params": position(2, 4) }),
+            json!({ "id": 4, "method": "textDocument/completion", "params": position(2, 3) }),
+            json!({ "id": 5, "method": "shutdown" }),
+            json!({ "method": "exit" }),
+        ];
+        let mut input = vec![];
+        for message in script {
+            write_message(&mut input, &message).unwrap();
+        }
+        let mut output = vec![];
+        Server::new(&mut output)
+            .serve(&mut std::io::Cursor::new(input))
+            .unwrap();
+
+        let mut output = std::io::Cursor::new(output);
+        let mut messages = vec![];
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message);
+        }
+        assert_eq!(messages.len(), 8);
+        assert_eq!(messages[0]["result"]["capabilities"]["hoverProvider"], true);
+
+        let diagnostics = &messages[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["message"], "`.` expects Str, found Int");
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({ "start": { "line": 2, "character": 3 }, "end": { "line": 2, "character": 11 } })
+        );
+        // constants which never finish are reported instead of blocking the server
+        let diagnostics = &messages[2]["params"]["diagnostics"];
+        assert_eq!(
+            diagnostics[0]["message"],
+            "const x failed to evaluate: Too many substitutions"
+        );
+        assert_eq!(messages[3]["params"]["diagnostics"], json!([]));
+
+        let hover = messages[4]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.contains("double : Int -> Int"), "{hover}");
+        assert!(hover.contains("bytes once compiled"), "{hover}");
+
+        assert_eq!(
+            messages[5]["result"],
+            json!({ "uri": uri, "range": {
+                "start": { "line": 1, "character": 4 }, "end": { "line": 1, "character": 10 }
+            } })
+        );
+
+        let completions = messages[6]["result"].as_array().unwrap();
+        let repeat = completions.iter().find(|c| c["label"] == "repeat").unwrap();
+        assert_eq!(repeat["detail"], "Str -> Int -> Str");
+        assert_eq!(messages[7]["result"], json!(null));
+    }
 }
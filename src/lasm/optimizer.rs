@@ -0,0 +1,281 @@
+use std::{collections::HashSet, rc::Rc};
+
+use crate::icfp::{serialize, serialize_str, Node, NodeRef, VarId};
+
+use super::{
+    const_eval::fold_constants,
+    source::{SourceMap, Span},
+    LNode, LNodeRef,
+};
+
+pub fn size(node: &NodeRef) -> usize {
+    serialize_str(node.clone()).len()
+}
+
+fn children(node: &Node) -> Vec<&NodeRef> {
+    match node {
+        Node::Value(_) | Node::Variable(_) => vec![],
+        Node::Lambda { body, .. } | Node::UnuaryOp { body, .. } => vec![body],
+        Node::Apply { f, value, .. } => vec![f, value],
+        Node::BinaryOp { left, right, .. } => vec![left, right],
+        Node::If {
+            cond,
+            then_do,
+            else_do,
+        } => vec![cond, then_do, else_do],
+    }
+}
+
+// rebuilds a node with new children, keeping it as is if none of them changed
+pub fn with_children(node: &NodeRef, new: Vec<NodeRef>, source_map: &mut SourceMap) -> NodeRef {
+    if children(node)
+        .into_iter()
+        .zip(new.iter())
+        .all(|(a, b)| Rc::ptr_eq(a, b))
+    {
+        return node.clone();
+    }
+
+    let mut new = new.into_iter();
+    let mut next = || new.next().unwrap();
+    let rebuilt = Rc::new(match node.as_ref() {
+        Node::Value(_) | Node::Variable(_) => unreachable!("leaves have no children"),
+        Node::Lambda { var, .. } => Node::Lambda {
+            var: *var,
+            body: next(),
+        },
+        Node::UnuaryOp { op, .. } => Node::UnuaryOp {
+            op: *op,
+            body: next(),
+        },
+        Node::Apply { strat, .. } => Node::Apply {
+            strat: *strat,
+            f: next(),
+            value: next(),
+        },
+        Node::BinaryOp { op, .. } => Node::BinaryOp {
+            op: *op,
+            left: next(),
+            right: next(),
+        },
+        Node::If { .. } => Node::If {
+            cond: next(),
+            then_do: next(),
+            else_do: next(),
+        },
+    });
+    if let Some(span) = source_map.get(node) {
+        source_map.insert(&rebuilt, span);
+    }
+    rebuilt
+}
+
+// counts the free uses of a variable, and tells whether one of them may be evaluated several times
+pub fn uses(node: &Node, var: VarId) -> (usize, bool) {
+    match node {
+        Node::Variable(v) => ((*v == var) as usize, false),
+        Node::Lambda { var: v, .. } if *v == var => (0, false),
+        Node::Lambda { body, .. } => {
+            let (count, _) = uses(body, var);
+            (count, count > 0)
+        }
+        // arguments are passed by name, the function may use them several times
+        Node::Apply { f, value, .. } => {
+            let (f_count, f_repeated) = match f.as_ref() {
+                // the body of a let is evaluated once
+                Node::Lambda { var: v, body } if *v != var => uses(body, var),
+                _ => uses(f, var),
+            };
+            let (value_count, _) = uses(value, var);
+            (f_count + value_count, f_repeated || value_count > 0)
+        }
+        _ => children(node)
+            .into_iter()
+            .map(|child| uses(child, var))
+            .fold((0, false), |(count, repeated), (c, r)| {
+                (count + c, repeated || r)
+            }),
+    }
+}
+
+// whether replacing the variable with a node using `free` would capture some of them
+fn captures(node: &Node, var: VarId, free: &HashSet<VarId>) -> bool {
+    match node {
+        Node::Lambda { var: v, .. } if *v == var => false,
+        Node::Lambda { var: v, body } if free.contains(v) && uses(body, var).0 > 0 => true,
+        _ => children(node)
+            .into_iter()
+            .any(|child| captures(child, var, free)),
+    }
+}
+
+pub fn substitute(
+    node: &NodeRef,
+    var: VarId,
+    value: &NodeRef,
+    source_map: &mut SourceMap,
+) -> NodeRef {
+    match node.as_ref() {
+        Node::Variable(v) if *v == var => value.clone(),
+        Node::Lambda { var: v, .. } if *v == var => node.clone(),
+        _ => {
+            let new = children(node)
+                .into_iter()
+                .map(|child| substitute(child, var, value, source_map))
+                .collect();
+            with_children(node, new, source_map)
+        }
+    }
+}
+
+// inlines the let bindings whose value can be copied, when it makes the code smaller
+fn inline_lets(node: &NodeRef, source_map: &mut SourceMap) -> NodeRef {
+    let new = children(node)
+        .into_iter()
+        .map(|child| inline_lets(child, source_map))
+        .collect();
+    let node = with_children(node, new, source_map);
+
+    let Node::Apply { f, value, .. } = node.as_ref() else {
+        return node;
+    };
+    let Node::Lambda { var, body } = f.as_ref() else {
+        return node;
+    };
+    let (count, repeated) = uses(body, *var);
+    if count == 0 {
+        return body.clone();
+    }
+
+    // copying a computation could make the program evaluate it several times
+    let copyable = matches!(
+        value.as_ref(),
+        Node::Lambda { .. } | Node::Value(_) | Node::Variable(_)
+    );
+    if (copyable || (count == 1 && !repeated)) && !captures(body, *var, &value.free_variables()) {
+        let inlined = substitute(body, *var, value, source_map);
+        if size(&inlined) < size(&node) {
+            return inlined;
+        }
+    }
+    node
+}
+
+// makes a compiled program smaller, inlining the lets when it pays off
+pub fn optimize(node: NodeRef, source_map: &mut SourceMap) -> NodeRef {
+    let inlined = inline_lets(&node, source_map);
+    fold_constants(inlined, source_map)
+}
+
+// how many bytes of the compiled program come from a top level binding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindingSize {
+    pub name: String,
+    pub size: usize,
+}
+
+struct SizeCounter<'a> {
+    // the top level bindings, from their name to the end of their value
+    regions: Vec<Span>,
+    source_map: &'a SourceMap,
+    y_combinator: Option<VarId>,
+    // the size of each token, in the order they are serialized
+    tokens: std::vec::IntoIter<usize>,
+    // one for each region, then the y combinator, then the body
+    sizes: Vec<usize>,
+    // who the last token belongs to
+    last: usize,
+}
+
+impl SizeCounter<'_> {
+    fn owner(&self, node: &NodeRef, parent: usize) -> usize {
+        let Some(span) = self.source_map.get(node) else {
+            return parent;
+        };
+        self.regions
+            .iter()
+            .position(|region| {
+                region.source == span.source && region.start <= span.start && span.end <= region.end
+            })
+            .unwrap_or(self.regions.len() + 1)
+    }
+
+    fn token(&mut self, owner: usize) {
+        self.sizes[owner] += self.tokens.next().unwrap_or(0);
+        self.last = owner;
+    }
+
+    // nodes are serialized in pre-order, each one with its own token
+    fn count(&mut self, node: &NodeRef, parent: usize) {
+        let owner = self.owner(node, parent);
+
+        // the binding of the y combinator
+        if let Node::Apply { f, value, .. } = node.as_ref() {
+            if let Node::Lambda { var, body } = f.as_ref() {
+                if Some(*var) == self.y_combinator {
+                    let y = self.regions.len();
+                    self.token(y);
+                    self.token(y);
+                    self.count(body, owner);
+                    self.count(value, y);
+                    return;
+                }
+            }
+        }
+        self.token(owner);
+        for child in children(node) {
+            self.count(child, owner);
+        }
+    }
+}
+
+// the bindings of the outermost lets of a program
+fn top_level_bindings(node: &LNodeRef) -> Vec<(String, Span)> {
+    let mut res = vec![];
+    let mut node = node;
+    while let LNode::Let { bindings, body, .. } = node.as_ref() {
+        for binding in bindings {
+            res.push((
+                binding.name.to_string(),
+                binding.span.cover(binding.value.span),
+            ));
+        }
+        node = body;
+    }
+    res
+}
+
+// attributes the bytes of the compiled program to the top level bindings it comes from
+pub fn binding_sizes(
+    source: &LNodeRef,
+    node: &NodeRef,
+    y_combinator: Option<VarId>,
+    source_map: &SourceMap,
+) -> Vec<BindingSize> {
+    let bindings = top_level_bindings(source);
+    let mut tokens = vec![];
+    serialize(node.clone(), &mut |token| {
+        tokens.push(token.to_string().len() + 1)
+    });
+    let mut counter = SizeCounter {
+        regions: bindings.iter().map(|(_, span)| *span).collect(),
+        source_map,
+        y_combinator,
+        tokens: tokens.into_iter(),
+        sizes: vec![0; bindings.len() + 2],
+        last: 0,
+    };
+    counter.count(node, bindings.len() + 1);
+    // the last token isn't followed by a space
+    counter.sizes[counter.last] -= 1;
+
+    let names = bindings
+        .into_iter()
+        .map(|(name, _)| name)
+        .chain(["<y combinator>".to_owned(), "<body>".to_owned()]);
+    names
+        .zip(counter.sizes)
+        .filter(|(_, size)| *size > 0)
+        .map(|(name, size)| BindingSize { name, size })
+        .collect()
+}
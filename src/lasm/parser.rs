@@ -1,20 +1,50 @@
 use super::{
-    ast::{BinaryOp, Binding, UnuaryOp},
+    ast::{BinaryOp, Binding, EvalStrat, Pattern, Program, Test, UnuaryOp},
+    source::{Diagnostic, Span},
     Iden, LNode, LNodeRef,
 };
+use crate::icfp::{base94_to_int, is_icfp_char, Base94Int};
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag},
-    character::complete::{alpha1, alphanumeric1, char, digit1, multispace1, one_of},
-    combinator::{cut, eof, map, map_res, opt, recognize, value, verify},
-    error::{context, ContextError, ParseError, VerboseError},
-    multi::{fold_many0, many0, many0_count, many1, many1_count},
+    bytes::complete::{is_not, tag, take_while_m_n},
+    character::complete::{
+        alpha1, alphanumeric1, char, digit1, hex_digit1, multispace1, one_of, satisfy,
+    },
+    combinator::{
+        consumed, cut, eof, map, map_opt, map_res, not, opt, peek, recognize, value, verify,
+    },
+    error::{context, ContextError, ErrorKind, ParseError, VerboseError, VerboseErrorKind},
+    multi::{fold_many0, many0, many0_count, many1, many1_count, separated_list0, separated_list1},
     sequence::{delimited, pair, preceded, terminated, tuple},
     Finish, IResult, Parser,
 };
 
 type LNodeResult<'a> = IResult<&'a str, LNodeRef, VerboseError<&'a str>>;
 
+// while parsing, spans hold addresses. they are made relative to the input once parsing is done
+fn address_span(consumed: &str) -> Span {
+    let start = consumed.as_ptr() as usize;
+    Span::new(0, start, start + consumed.len())
+}
+
+fn spanned<'a>(
+    parser: impl FnMut(&'a str) -> LNodeResult<'a>,
+) -> impl FnMut(&'a str) -> LNodeResult<'a> {
+    map(consumed(parser), |(consumed, node): (&str, LNodeRef)| {
+        node.with_span(address_span(consumed))
+    })
+}
+
+// names which can't be used as identifiers
+const KEYWORDS: &[&str] = &["take", "drop", "nil", "cons", "match"];
+
+// a keyword, which isn't the start of a longer identifier
+fn keyword<'a>(
+    keyword: &'static str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, VerboseError<&'a str>> {
+    terminated(tag(keyword), not(alt((alphanumeric1, tag("_")))))
+}
+
 fn identifier(input: &str) -> IResult<&str, Iden, VerboseError<&str>> {
     let (rest, rec) = context(
         "ident",
@@ -23,7 +53,7 @@ fn identifier(input: &str) -> IResult<&str, Iden, VerboseError<&str>> {
                 alt((alpha1, tag("_"))),
                 many0_count(alt((alphanumeric1, tag("_")))),
             )),
-            |iden: &str| iden != "take" && iden != "drop",
+            |iden: &str| !KEYWORDS.contains(&iden),
         ),
     )
     .parse(input)?;
@@ -31,46 +61,231 @@ fn identifier(input: &str) -> IResult<&str, Iden, VerboseError<&str>> {
 }
 
 fn binding(input: &str) -> IResult<&str, Binding, VerboseError<&str>> {
+    // const name = value
+    let const_binding = map(
+        tuple((
+            preceded(sep_many1, tag("const")),
+            preceded(sep_many1, consumed(identifier)),
+            preceded(sep_many1, delimited(char('='), cut(expr), char(';'))),
+        )),
+        |(_, (name, id), expr)| Binding::constant(id, expr).with_span(address_span(name)),
+    );
     // [rec] name [param...] = value
+    let binding = map(
+        tuple((
+            // [rec]
+            opt(preceded(sep_many1, tag("rec"))),
+            // name
+            preceded(sep_many1, consumed(identifier)),
+            // [params...]
+            many0(preceded(sep_many1, identifier)),
+            // = value
+            preceded(sep_many1, delimited(char('='), cut(expr), char(';'))),
+        )),
+        |(rec, (name, id), params, expr)| {
+            Binding::new(rec.is_some(), id, params, expr).with_span(address_span(name))
+        },
+    );
+    context("binding", alt((const_binding, binding)))(input)
+}
+
+// evaluation strategy annotation, written right after `let` or a function: `!` is strict, `~` is lazy
+fn strat_marker(input: &str) -> IResult<&str, EvalStrat, VerboseError<&str>> {
+    alt((
+        value(EvalStrat::Value, char('!')),
+        value(EvalStrat::Lazy, char('~')),
+    ))(input)
+}
+
+// a tuple destructuring binding: (a, b) = value;
+fn destructuring(input: &str) -> IResult<&str, (Vec<Iden>, LNodeRef), VerboseError<&str>> {
     context(
-        "binding",
-        map(
-            tuple((
-                // [rec]
-                opt(preceded(sep_many1, tag("rec"))),
-                // name
-                preceded(sep_many1, identifier),
-                // [params...]
-                many0(preceded(sep_many1, identifier)),
-                // = value
-                preceded(sep_many1, delimited(char('='), cut(expr), char(';'))),
-            )),
-            |(rec, id, params, expr)| Binding::new(rec.is_some(), id, params, expr),
+        "destructuring",
+        preceded(
+            sep_many1,
+            pair(
+                tuple_pattern,
+                preceded(sep_many0, delimited(char('='), cut(expr), char(';'))),
+            ),
         ),
     )(input)
 }
 
+enum LetItem {
+    Binding(Binding),
+    Destructuring(Vec<Iden>, LNodeRef),
+}
+
 fn let_expr(input: &str) -> LNodeResult {
-    let (rest, (bindings, body)) = context(
+    let (rest, (strat, items, body)) = context(
         "let",
         tuple((
-            preceded(tag("let"), cut(many1(binding))),
+            preceded(tag("let"), opt(strat_marker)),
+            cut(many1(alt((
+                map(binding, LetItem::Binding),
+                map(destructuring, |(names, value)| {
+                    LetItem::Destructuring(names, value)
+                }),
+            )))),
             cut(preceded(
                 preceded(sep_many1, tag("in")),
                 context("let body", expr),
             )),
         )),
     )(input)?;
-    Ok((rest, LNode::Let { bindings, body }.into()))
+    // let bindings are strict unless asked otherwise
+    let strat = strat.unwrap_or(EvalStrat::Value);
+
+    // destructuring splits the let, the bindings which follow are matched against the tuple
+    let wrap = |bindings: &mut Vec<Binding>, body: LNodeRef| {
+        if bindings.is_empty() {
+            return body;
+        }
+        bindings.reverse();
+        let span = body.span;
+        LNodeRef::new(LNode::Let {
+            strat,
+            bindings: std::mem::take(bindings),
+            body,
+        })
+        .with_span(span)
+    };
+    let mut bindings = vec![];
+    let mut res = body;
+    for item in items.into_iter().rev() {
+        match item {
+            LetItem::Binding(binding) => bindings.push(binding),
+            LetItem::Destructuring(names, value) => {
+                // the items of tuples are lazy, the names are rebound to follow the let's strategy
+                if strat != EvalStrat::Name {
+                    for name in names.iter().rev() {
+                        let item = LNode::var(name.clone()).with_span(value.span);
+                        bindings.push(
+                            Binding::new(false, name.clone(), vec![], item).with_span(value.span),
+                        );
+                    }
+                }
+                let body = wrap(&mut bindings, res);
+                let span = value.span.cover(body.span);
+                res = LNodeRef::new(LNode::Match {
+                    value,
+                    arms: vec![(Pattern::Tuple(names), body)],
+                })
+                .with_span(span);
+            }
+        }
+    }
+    Ok((rest, wrap(&mut bindings, res)))
 }
 
+// comma separated expressions, within delimiters
+fn expr_list<'a>(
+    open: char,
+    close: char,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<LNodeRef>, VerboseError<&'a str>> {
+    preceded(
+        pair(char(open), sep_many0),
+        cut(terminated(separated_list0(char(','), expr), char(close))),
+    )
+}
+
+// a parenthesized expression, or a tuple
 fn paren_group_expr(input: &str) -> LNodeResult {
     context(
         "paren group",
-        terminated(preceded(char('('), cut(expr)), char(')')),
+        map_res(expr_list('(', ')'), |mut items| match items.len() {
+            0 => Err("empty parentheses"),
+            1 => Ok(items.remove(0)),
+            _ => Ok(LNode::tuple(items)),
+        }),
+    )(input)
+}
+
+// [a, b, c] is cons a (cons b (cons c nil))
+fn list_expr(input: &str) -> LNodeResult {
+    context(
+        "list",
+        map(expr_list('[', ']'), |items| {
+            items.into_iter().rev().fold(LNode::nil(), |tail, head| {
+                // nil has no span, the list is spanned as a whole
+                let span = match tail.as_ref() {
+                    LNode::Nil => head.span,
+                    _ => head.span.cover(tail.span),
+                };
+                LNode::cons(head, tail).with_span(span)
+            })
+        }),
     )(input)
 }
 
+fn nil_expr(input: &str) -> LNodeResult {
+    map(keyword("nil"), |_| LNode::nil())(input)
+}
+
+fn cons_expr(input: &str) -> LNodeResult {
+    // cons head tail
+    context(
+        "cons",
+        map(
+            preceded(
+                keyword("cons"),
+                cut(pair(
+                    preceded(sep_many1, core_expr),
+                    preceded(sep_many1, core_expr),
+                )),
+            ),
+            |(head, tail)| LNode::cons(head, tail),
+        ),
+    )(input)
+}
+
+fn tuple_pattern(input: &str) -> IResult<&str, Vec<Iden>, VerboseError<&str>> {
+    delimited(
+        pair(char('('), sep_many0),
+        separated_list1(char(','), delimited(sep_many0, identifier, sep_many0)),
+        char(')'),
+    )(input)
+}
+
+fn pattern(input: &str) -> IResult<&str, Pattern, VerboseError<&str>> {
+    context(
+        "pattern",
+        alt((
+            map(tuple_pattern, Pattern::Tuple),
+            value(Pattern::Nil, keyword("nil")),
+            map(
+                preceded(
+                    keyword("cons"),
+                    pair(
+                        preceded(sep_many1, identifier),
+                        preceded(sep_many1, identifier),
+                    ),
+                ),
+                |(head, tail)| Pattern::Cons(head, tail),
+            ),
+        )),
+    )(input)
+}
+
+fn match_expr(input: &str) -> LNodeResult {
+    // match value { pattern => expr, ... }
+    let arm = pair(
+        delimited(sep_many0, pattern, sep_many0),
+        preceded(tag("=>"), expr),
+    );
+    let (rest, (value, arms)) = context(
+        "match",
+        preceded(
+            keyword("match"),
+            cut(pair(
+                expr,
+                delimited(char('{'), separated_list1(char(','), arm), char('}')),
+            )),
+        ),
+    )(input)?;
+    Ok((rest, LNodeRef::new(LNode::Match { value, arms })))
+}
+
 pub fn single_line_comment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, (), E> {
     value(
         (), // Output is thrown away.
@@ -95,11 +310,40 @@ fn sep_many1<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
     context("sep many1", value((), many1_count(sep)))(input)
 }
 
-// integers litterals are only positive. negative integers are created using unuary minus
+// the digits of a base 94 integer, as they appear in an ICFP I token. " and \ must be escaped
+fn base94_digits(input: &str) -> IResult<&str, String, VerboseError<&str>> {
+    delimited(
+        char('"'),
+        cut(many1(alt((
+            preceded(char('\\'), cut(one_of("\"\\"))),
+            satisfy(|c| ('!'..='~').contains(&c) && c != '"' && c != '\\'),
+        )))
+        .map(|r| r.into_iter().collect())),
+        char('"'),
+    )(input)
+}
+
+// integers of any size, either decimal, hexadecimal (0x1f) or base 94 (b94"I!"). they may be negative
 fn integer_litteral(input: &str) -> LNodeResult {
-    map_res(digit1, |digit_str: &str| {
-        digit_str.parse::<i64>().map(LNode::int)
-    })(input)
+    let (input, negative) = opt(char('-'))(input)?;
+    let (input, val) = context(
+        "integer",
+        alt((
+            map_opt(preceded(tag("0x"), cut(hex_digit1)), |digits: &str| {
+                Base94Int::parse_bytes(digits.as_bytes(), 16)
+            }),
+            // b94 followed by anything but a quote starts an identifier
+            map_opt(
+                preceded(pair(tag("b94"), peek(char('"'))), cut(base94_digits)),
+                |digits| base94_to_int(&digits).map(Base94Int::from),
+            ),
+            map_res(digit1, |digits: &str| digits.parse::<Base94Int>()),
+        )),
+    )(input)?;
+    Ok((
+        input,
+        LNode::int(if negative.is_some() { -val } else { val }),
+    ))
 }
 
 fn boolean_litteral(input: &str) -> LNodeResult {
@@ -108,14 +352,65 @@ fn boolean_litteral(input: &str) -> LNodeResult {
         .parse(input)
 }
 
+// the characters strings can hold: those of ICFP strings, and tabs for the interpreter
+fn is_string_char(c: char) -> bool {
+    is_icfp_char(c) || c == '\t'
+}
+
+// \n, \t, \", \\ or \xNN for any other character, by its ascii code
+fn escape(input: &str) -> IResult<&str, char, VerboseError<&str>> {
+    alt((
+        value('\n', char('n')),
+        value('\t', char('t')),
+        char('"'),
+        char('\\'),
+        preceded(
+            char('x'),
+            map_opt(
+                take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit()),
+                |code: &str| {
+                    let c = char::from(u8::from_str_radix(code, 16).ok()?);
+                    is_string_char(c).then_some(c)
+                },
+            ),
+        ),
+    ))(input)
+}
+
+fn quoted_string(input: &str) -> IResult<&str, String, VerboseError<&str>> {
+    delimited(
+        char('"'),
+        cut(many0(alt((
+            preceded(char('\\'), cut(context("escape sequence", escape))),
+            satisfy(|c| c != '"' && c != '\\' && is_string_char(c)),
+        )))
+        .map(|r| r.into_iter().collect())),
+        cut(context("string", char('"'))),
+    )(input)
+}
+
+// r"..." or r#"..."#, without escapes. a newline right after the opening quote is skipped,
+// so that multi-line strings can start on their own line
+fn raw_string(input: &str) -> IResult<&str, String, VerboseError<&str>> {
+    let (input, hashes) = delimited(char('r'), many0_count(char('#')), char('"'))(input)?;
+    let input = input.strip_prefix('\n').unwrap_or(input);
+    let end = format!("\"{}", "#".repeat(hashes));
+    let (rest, content) = terminated(
+        recognize(many0_count(preceded(
+            not(tag(end.as_str())),
+            satisfy(is_string_char),
+        ))),
+        cut(context("string", tag(end.as_str()))),
+    )(input)?;
+    Ok((rest, content.to_owned()))
+}
+
+fn string_content(input: &str) -> IResult<&str, String, VerboseError<&str>> {
+    alt((quoted_string, raw_string))(input)
+}
+
 fn string_litteral(input: &str) -> LNodeResult {
-    delimited(char('"'), cut(many0(alt((
-        preceded(char('\\'), cut(one_of("\"\\"))),
-        one_of("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!#$%&'()*+,-./:;<=>?@[]^_`|~ \n"),
-    ))).map(|r| {
-        let s: String = r.into_iter().collect();
-        LNode::str(s)
-    })), char('"'))(input)
+    string_content.map(LNode::str).parse(input)
 }
 
 fn variable(input: &str) -> LNodeResult {
@@ -142,29 +437,55 @@ fn if_expr(input: &str) -> LNodeResult {
 
 // either a ref to a variable, or a function call
 fn core_expr(input: &str) -> LNodeResult {
-    context(
+    spanned(context(
         "core expr",
         alt((
             let_expr,
             paren_group_expr,
+            list_expr,
             if_expr,
+            match_expr,
+            nil_expr,
+            cons_expr,
             integer_litteral,
             boolean_litteral,
             string_litteral,
             variable,
         )),
-    )(input)
+    ))(input)
 }
 
 // a core expr or a function call
 fn callseq_expr(input: &str) -> LNodeResult {
-    // core_expr [core_expr...]
-    let (input, expr) = core_expr(input)?;
-    fold_many0(
-        preceded(sep_many1, core_expr),
-        move || expr.clone(),
-        LNode::apply,
-    )(input)
+    // core_expr[!|~] [core_expr...]
+    // the optional marker sets the evaluation strategy of all the applications in the sequence
+    let (input, (expr, strat)) = pair(core_expr, opt(strat_marker))(input)?;
+    let apply = move |func: LNodeRef, param: LNodeRef| {
+        let span = func.span.cover(param.span);
+        match strat {
+            Some(strat) => LNode::apply_strat(strat, func, param),
+            None => LNode::apply(func, param),
+        }
+        .with_span(span)
+    };
+    // `f -1` is a subtraction, negative arguments must be parenthesized
+    let argument = || preceded(pair(sep_many1, not(char('-'))), core_expr);
+    // a marker applies to arguments, `f!` alone is an error
+    let (input, expr) = match strat {
+        Some(_) => match opt(argument())(input)? {
+            (input, Some(param)) => (input, apply(expr, param)),
+            (_, None) => {
+                return Err(nom::Err::Failure(VerboseError {
+                    errors: vec![(
+                        input,
+                        VerboseErrorKind::Context("an argument after the strategy marker"),
+                    )],
+                }))
+            }
+        },
+        None => (input, expr),
+    };
+    fold_many0(argument(), move || expr.clone(), apply)(input)
 }
 
 #[derive(Clone, Copy)]
@@ -192,51 +513,204 @@ fn infix_operator(input: &str) -> IResult<&str, (BinaryOp, OperandOrder), Verbos
 }
 
 fn infix_expr(input: &str) -> LNodeResult {
-    // core_expr [OP core_expr...]
-    let (input, expr) = callseq_expr(input)?;
+    // prefixed_expr [OP prefixed_expr...]
+    let (input, expr) = prefixed_expr(input)?;
     fold_many0(
         tuple((
             delimited(sep_many0, infix_operator, sep_many0),
-            callseq_expr,
+            prefixed_expr,
         )),
         move || expr.clone(),
-        |left, ((op, order), right)| match order {
-            OperandOrder::Preserved => LNode::binary_op(op, left, right),
-            OperandOrder::Reversed => LNode::binary_op(op, right, left),
+        |left, ((op, order), right)| {
+            let span = left.span.cover(right.span);
+            match order {
+                OperandOrder::Preserved => LNode::binary_op(op, left, right),
+                OperandOrder::Reversed => LNode::binary_op(op, right, left),
+            }
+            .with_span(span)
         },
     )(input)
 }
 
 fn prefix_operator(input: &str) -> IResult<&str, UnuaryOp, VerboseError<&str>> {
     alt((
-        value(UnuaryOp::IntNeg, char('-')),
+        // -1 is a litteral
+        value(UnuaryOp::IntNeg, terminated(char('-'), not(digit1))),
         value(UnuaryOp::BoolNot, char('!')),
-        value(UnuaryOp::StrToInt, tag("str2int")),
-        value(UnuaryOp::IntNeg, tag("int2str")),
+        value(UnuaryOp::StrToInt, keyword("str2int")),
+        value(UnuaryOp::IntToStr, keyword("int2str")),
+    ))(input)
+}
+
+// prefix operators bind tighter than infix ones, but not than calls: `-f x + 1` is `(-(f x)) + 1`
+fn prefixed_expr(input: &str) -> LNodeResult {
+    alt((
+        map(
+            consumed(pair(terminated(prefix_operator, sep_many0), prefixed_expr)),
+            |(consumed, (op, expr))| LNode::unuary_op(op, expr).with_span(address_span(consumed)),
+        ),
+        callseq_expr,
     ))(input)
 }
 
 fn expr(input: &str) -> LNodeResult {
-    delimited(
-        sep_many0,
-        tuple((opt(prefix_operator), infix_expr)),
-        sep_many0,
-    )
-    .map(|(op, expr)| {
-        if let Some(op) = op {
-            LNode::unuary_op(op, expr)
-        } else {
-            expr
-        }
-    })
-    .parse(input)
+    delimited(sep_many0, infix_expr, sep_many0)(input)
 }
 
 fn top_expr(input: &str) -> LNodeResult {
     terminated(expr, eof)(input)
 }
 
-pub fn parse(input: &str) -> Result<LNodeRef, VerboseError<&str>> {
-    let (_, res) = top_expr(input).finish()?;
-    Ok(res)
+fn import_decl(input: &str) -> IResult<&str, String, VerboseError<&str>> {
+    // import "path";
+    context(
+        "import",
+        delimited(
+            pair(tag("import"), sep_many1),
+            cut(string_content),
+            pair(sep_many0, cut(char(';'))),
+        ),
+    )(input)
+}
+
+fn test_decl(input: &str) -> IResult<&str, Test, VerboseError<&str>> {
+    // test "name" { expr } == expected;
+    let (rest, (span, (name, expr, expected))) = context(
+        "test",
+        consumed(preceded(
+            pair(tag("test"), sep_many1),
+            cut(tuple((
+                string_content,
+                braced_expr,
+                preceded(tag("=="), terminated(expr, char(';'))),
+            ))),
+        )),
+    )(input)?;
+    Ok((
+        rest,
+        Test {
+            name,
+            expr,
+            expected,
+            span: address_span(span),
+        },
+    ))
+}
+
+fn tests(input: &str) -> IResult<&str, Vec<Test>, VerboseError<&str>> {
+    many0(terminated(test_decl, sep_many0))(input)
+}
+
+fn module_bindings(input: &str) -> IResult<&str, (Vec<Binding>, Vec<Test>), VerboseError<&str>> {
+    // let bindings without a body, as found in libraries. tests may follow them
+    context(
+        "module",
+        terminated(
+            pair(
+                preceded(tag("let"), many1(binding)),
+                preceded(sep_many0, tests),
+            ),
+            pair(sep_many0, eof),
+        ),
+    )(input)
+}
+
+fn program(input: &str) -> IResult<&str, Program, VerboseError<&str>> {
+    // imports, then tests, then either bindings or the program body
+    let (input, imports) = preceded(sep_many0, many0(terminated(import_decl, sep_many0)))(input)?;
+    let (input, leading_tests) = tests(input)?;
+    let (rest, ((bindings, module_tests), body)) = alt((
+        map(module_bindings, |module| (module, None)),
+        map(top_expr, |body| ((vec![], vec![]), Some(body))),
+    ))(input)?;
+    Ok((
+        rest,
+        Program {
+            imports,
+            bindings,
+            body,
+            tests: leading_tests.into_iter().chain(module_tests).collect(),
+        },
+    ))
+}
+
+fn describe_error(kind: &VerboseErrorKind) -> String {
+    match kind {
+        VerboseErrorKind::Context(context) => format!("expected {context}"),
+        VerboseErrorKind::Char(c) => format!("expected {c:?}"),
+        VerboseErrorKind::Nom(ErrorKind::Eof) => "expected the end of the input".to_owned(),
+        VerboseErrorKind::Nom(kind) => format!("unexpected input ({})", kind.description()),
+    }
+}
+
+// strings report what they couldn't parse, rather than what they expected
+fn string_error(rest: &str, context: &str, span: Span) -> Option<Diagnostic> {
+    match context {
+        "string" => Some(match rest.chars().next() {
+            Some(c) => Diagnostic::new(format!("strings can't contain {c:?}"), span)
+                .with_note("strings can hold printable ascii characters, newlines and tabs"),
+            None => Diagnostic::new("unterminated string", span),
+        }),
+        "escape sequence" => {
+            let len = if rest.starts_with('x') { 3 } else { 1 };
+            let sequence: String = rest.chars().take(len).collect();
+            Some(
+                Diagnostic::new(format!("invalid escape sequence \\{sequence}"), span)
+                    .with_note(r#"the escape sequences are \n, \t, \", \\ and \xNN"#),
+            )
+        }
+        _ => None,
+    }
+}
+
+// the innermost error is reported, along with the context it happened in
+fn diagnostic(input: &str, err: VerboseError<&str>) -> Diagnostic {
+    let span_at = |rest: &str| {
+        let offset = input.len() - rest.len();
+        let len = rest.chars().next().map_or(0, char::len_utf8);
+        Span::new(0, offset, offset + len)
+    };
+    let Some((rest, kind)) = err.errors.first() else {
+        return Diagnostic::new("parsing failed", Span::default());
+    };
+    let string_error = err.errors.iter().find_map(|(rest, kind)| match kind {
+        VerboseErrorKind::Context(context) => string_error(rest, context, span_at(rest)),
+        _ => None,
+    });
+    if let Some(diagnostic) = string_error {
+        return diagnostic;
+    }
+
+    let span = span_at(rest);
+    let mut diagnostic = Diagnostic::new(describe_error(kind), span);
+    for (_, kind) in err.errors.iter().skip(1) {
+        if let VerboseErrorKind::Context(context) = kind {
+            diagnostic = diagnostic.with_note(format!("while parsing {context}"));
+        }
+    }
+    diagnostic
+}
+
+fn relative_spans(input: &str) -> impl Fn(Span) -> Span {
+    let base = input.as_ptr() as usize;
+    move |span| {
+        if span == Span::default() {
+            return span;
+        }
+        Span::new(0, span.start - base, span.end - base)
+    }
+}
+
+pub fn parse(input: &str) -> Result<LNodeRef, Diagnostic> {
+    let (_, res) = top_expr(input)
+        .finish()
+        .map_err(|err| diagnostic(input, err))?;
+    Ok(res.map_spans(&relative_spans(input)))
+}
+
+pub fn parse_program(input: &str) -> Result<Program, Diagnostic> {
+    let (_, res) = program(input)
+        .finish()
+        .map_err(|err| diagnostic(input, err))?;
+    Ok(res.map_spans(&relative_spans(input)))
 }
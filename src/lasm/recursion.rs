@@ -0,0 +1,248 @@
+use crate::icfp::{Base94Int, BinaryOp, EvalStrat, Node, NodeRef, UnuaryOp, Value, VarId};
+
+use super::{
+    optimizer::{substitute, uses, with_children},
+    source::SourceMap,
+};
+
+// splits `f a b` into f and [a, b], along with the applications
+fn apply_chain(node: &NodeRef) -> (&NodeRef, Vec<&NodeRef>) {
+    let mut head = node;
+    let mut applies = vec![];
+    while let Node::Apply { f, .. } = head.as_ref() {
+        applies.push(head);
+        head = f;
+    }
+    applies.reverse();
+    (head, applies)
+}
+
+fn arg(apply: &NodeRef) -> &NodeRef {
+    match apply.as_ref() {
+        Node::Apply { value, .. } => value,
+        _ => unreachable!("not an application"),
+    }
+}
+
+// whether all the uses of `func` are calls in tail position
+fn only_tail_calls(node: &NodeRef, func: VarId) -> bool {
+    let unused = |node: &NodeRef| uses(node, func).0 == 0;
+    match node.as_ref() {
+        Node::If {
+            cond,
+            then_do,
+            else_do,
+        } => unused(cond) && only_tail_calls(then_do, func) && only_tail_calls(else_do, func),
+        Node::Apply { .. } => {
+            let (head, applies) = apply_chain(node);
+            if !applies.iter().all(|apply| unused(arg(apply))) {
+                return false;
+            }
+            match head.as_ref() {
+                Node::Variable(v) => *v == func || unused(head),
+                // a let, its body is still in tail position
+                Node::Lambda { var, body } if applies.len() == 1 => {
+                    *var == func || only_tail_calls(body, func)
+                }
+                _ => unused(head),
+            }
+        }
+        _ => unused(node),
+    }
+}
+
+// whether evaluating the node always evaluates the variable, assuming the calls to `func`
+// evaluate their arguments at the strict positions
+fn evaluates(node: &NodeRef, var: VarId, func: VarId, strict: &[bool]) -> bool {
+    let evaluates = |node: &NodeRef| evaluates(node, var, func, strict);
+    match node.as_ref() {
+        Node::Variable(v) => *v == var,
+        // the right operand of a boolean operator may not be evaluated
+        Node::BinaryOp {
+            op: BinaryOp::BoolAnd | BinaryOp::BoolOr,
+            left,
+            ..
+        } => evaluates(left),
+        Node::BinaryOp { left, right, .. } => evaluates(left) || evaluates(right),
+        Node::UnuaryOp { body, .. } => evaluates(body),
+        Node::If {
+            cond,
+            then_do,
+            else_do,
+        } => evaluates(cond) || (evaluates(then_do) && evaluates(else_do)),
+        Node::Apply { .. } => {
+            let (head, applies) = apply_chain(node);
+            match head.as_ref() {
+                Node::Variable(v) if *v == func && applies.len() == strict.len() => applies
+                    .iter()
+                    .zip(strict.iter())
+                    .any(|(apply, strict)| *strict && evaluates(arg(apply))),
+                Node::Lambda { var: v, body } if *v != var && applies.len() == 1 => evaluates(body),
+                _ => false,
+            }
+        }
+        Node::Value(_) | Node::Lambda { .. } => false,
+    }
+}
+
+// whether evaluating the node eagerly can't fail or loop, given the parameters passed by value
+fn safe(node: &NodeRef, params: &[VarId], by_value: &[bool]) -> bool {
+    let safe = |node: &NodeRef| safe(node, params, by_value);
+    match node.as_ref() {
+        Node::Value(_) | Node::Lambda { .. } => true,
+        Node::Variable(v) => params
+            .iter()
+            .zip(by_value.iter())
+            .any(|(param, by_value)| param == v && *by_value),
+        Node::BinaryOp {
+            op: BinaryOp::IntDiv | BinaryOp::IntMod,
+            left,
+            right,
+        } => {
+            safe(left)
+                && matches!(right.as_ref(), Node::Value(Value::Int(v)) if *v != Base94Int::ZERO)
+        }
+        Node::BinaryOp { left, right, .. } => safe(left) && safe(right),
+        Node::UnuaryOp {
+            op: UnuaryOp::IntToStr,
+            ..
+        } => false,
+        Node::UnuaryOp { body, .. } => safe(body),
+        Node::If {
+            cond,
+            then_do,
+            else_do,
+        } => safe(cond) && safe(then_do) && safe(else_do),
+        Node::Apply { .. } => false,
+    }
+}
+
+// the arguments of a tail call at the given position
+fn tail_args(node: &NodeRef, func: VarId, arity: usize, position: usize) -> Vec<&NodeRef> {
+    match node.as_ref() {
+        Node::If {
+            then_do, else_do, ..
+        } => {
+            let mut args = tail_args(then_do, func, arity, position);
+            args.extend(tail_args(else_do, func, arity, position));
+            args
+        }
+        Node::Apply { .. } => {
+            let (head, applies) = apply_chain(node);
+            match head.as_ref() {
+                Node::Variable(v) if *v == func && applies.len() == arity => {
+                    vec![arg(applies[position])]
+                }
+                Node::Lambda { var, body } if *var != func && applies.len() == 1 => {
+                    tail_args(body, func, arity, position)
+                }
+                _ => vec![],
+            }
+        }
+        _ => vec![],
+    }
+}
+
+// the parameters which can be passed by value: the function always evaluates them, or their
+// arguments are cheap to compute. this assumes the first call gets arguments which can be evaluated
+fn by_value_params(func: VarId, params: &[VarId], body: &NodeRef) -> Vec<bool> {
+    let mut by_value = vec![true; params.len()];
+    loop {
+        let next: Vec<bool> = params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| {
+                by_value[i]
+                    && (evaluates(body, *param, func, &by_value)
+                        || tail_args(body, func, params.len(), i)
+                            .into_iter()
+                            .all(|arg| safe(arg, params, &by_value)))
+            })
+            .collect();
+        if next == by_value {
+            return by_value;
+        }
+        by_value = next;
+    }
+}
+
+// passes the strict arguments of the tail calls by value, so that accumulators are computed
+// on each iteration instead of growing the term
+fn strict_tail_calls(
+    node: &NodeRef,
+    func: VarId,
+    strict: &[bool],
+    source_map: &mut SourceMap,
+) -> NodeRef {
+    match node.as_ref() {
+        Node::If {
+            cond,
+            then_do,
+            else_do,
+        } => {
+            let then_do = strict_tail_calls(then_do, func, strict, source_map);
+            let else_do = strict_tail_calls(else_do, func, strict, source_map);
+            with_children(node, vec![cond.clone(), then_do, else_do], source_map)
+        }
+        Node::Apply { .. } => {
+            let (head, applies) = apply_chain(node);
+            match head.as_ref() {
+                Node::Variable(v) if *v == func && applies.len() == strict.len() => {
+                    let mut res = head.clone();
+                    for (apply, strict) in applies.into_iter().zip(strict.iter()) {
+                        let Node::Apply { strat, value, .. } = apply.as_ref() else {
+                            unreachable!("not an application");
+                        };
+                        let strat = if *strict { EvalStrat::Value } else { *strat };
+                        res = Node::apply(strat, res, value.clone());
+                        if let Some(span) = source_map.get(apply) {
+                            source_map.insert(&res, span);
+                        }
+                    }
+                    res
+                }
+                Node::Lambda { var, body } if *var != func && applies.len() == 1 => {
+                    let body = strict_tail_calls(body, func, strict, source_map);
+                    let lambda = with_children(head, vec![body], source_map);
+                    with_children(node, vec![lambda, arg(node).clone()], source_map)
+                }
+                _ => node.clone(),
+            }
+        }
+        _ => node.clone(),
+    }
+}
+
+// a tail recursive function doesn't need the y combinator, it is given itself as first
+// parameter: `y (λf. λx. ... f x')` becomes `(λf. f f) (λf. λx. ... f f x')`.
+// returns None if the function isn't tail recursive
+pub fn compile_tail_recursive(
+    func: VarId,
+    params: &[VarId],
+    body: &NodeRef,
+    source_map: &mut SourceMap,
+) -> Option<NodeRef> {
+    if params.contains(&func) || !only_tail_calls(body, func) {
+        return None;
+    }
+    let wrap = |body: NodeRef| {
+        params
+            .iter()
+            .rev()
+            .fold(body, |body, param| Node::lambda(*param, body))
+    };
+
+    // a function which never calls itself is its own fixpoint
+    if uses(body, func).0 == 0 {
+        return Some(wrap(body.clone()));
+    }
+    let by_value = by_value_params(func, params, body);
+    let body = strict_tail_calls(body, func, &by_value, source_map);
+    let itself = Node::apply(EvalStrat::Name, Node::var(func), Node::var(func));
+    let body = substitute(&body, func, &itself, source_map);
+    Some(Node::apply(
+        EvalStrat::Name,
+        Node::lambda(func, itself),
+        Node::lambda(func, wrap(body)),
+    ))
+}
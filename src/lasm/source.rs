@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::icfp::{Node, NodeRef};
+
+// a range of bytes in one of the source files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub source: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(source: usize, start: usize, end: usize) -> Self {
+        Span { source, start, end }
+    }
+
+    // the smallest span containing both spans
+    pub fn cover(self, other: Span) -> Span {
+        Span::new(
+            self.source,
+            self.start.min(other.start),
+            self.end.max(other.end),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+impl SourceFile {
+    pub fn new(path: impl AsRef<Path>, text: impl Into<String>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            text: text.into(),
+        }
+    }
+
+    // 1-based line and column of a byte offset
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let before = &self.text[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, before[line_start..].chars().count() + 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            notes: vec![],
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    // renders the diagnostic the way rustc does, quoting the offending line
+    pub fn render(&self, sources: &[SourceFile]) -> String {
+        let mut res = format!("error: {}\n", self.message);
+        if let Some(source) = sources.get(self.span.source) {
+            let (line, col) = source.line_col(self.span.start);
+            let text = source.text.lines().nth(line - 1).unwrap_or_default();
+            let gutter = " ".repeat(line.to_string().len());
+
+            // underline the span, up to the end of its first line
+            let start = self.span.start.min(source.text.len());
+            let width = source.text[start..]
+                .char_indices()
+                .take_while(|(i, c)| start + i < self.span.end && *c != '\n')
+                .count()
+                .max(1);
+
+            let _ = writeln!(res, "{gutter}--> {}:{line}:{col}", source.path.display());
+            let _ = writeln!(res, "{gutter} |");
+            let _ = writeln!(res, "{line} | {text}");
+            let _ = writeln!(
+                res,
+                "{gutter} | {}{}",
+                " ".repeat(col - 1),
+                "^".repeat(width)
+            );
+        }
+        for note in self.notes.iter() {
+            let _ = writeln!(res, "note: {note}");
+        }
+        res
+    }
+}
+
+// maps compiled nodes back to the LASM code they come from
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    // nodes are kept alive so that their address isn't reused
+    spans: HashMap<*const Node, (NodeRef, Span)>,
+}
+
+impl SourceMap {
+    pub fn insert(&mut self, node: &NodeRef, span: Span) {
+        self.spans
+            .entry(node.as_ref() as *const Node)
+            .or_insert_with(|| (node.clone(), span));
+    }
+
+    pub fn get(&self, node: &NodeRef) -> Option<Span> {
+        self.spans
+            .get(&(node.as_ref() as *const Node))
+            .map(|(_, span)| *span)
+    }
+}
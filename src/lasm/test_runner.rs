@@ -0,0 +1,70 @@
+use std::io::Write;
+
+use crate::icfp::Value;
+
+use super::{
+    ast::Test,
+    compiler::{compile_with_source_map, evaluate},
+    interpreter::Interpreter,
+    source::{Diagnostic, SourceFile},
+    LNodeRef,
+};
+
+fn interpret(node: &LNodeRef, sources: &[SourceFile]) -> Result<Value, String> {
+    Interpreter::new(sources)
+        .run(node)
+        .map_err(|diag| diag.render(sources))
+}
+
+// runs a test with the interpreter, then checks the compiled program agrees
+fn run_test(test: &Test, sources: &[SourceFile]) -> Result<(), String> {
+    let value = interpret(&test.expr, sources)?;
+    let expected = interpret(&test.expected, sources)?;
+    if value != expected {
+        let diag = Diagnostic::new(format!("expected {}, got {}", expected, value), test.span);
+        return Err(diag.render(sources));
+    }
+
+    let (node, source_map) =
+        compile_with_source_map(test.expr.clone()).map_err(|diag| diag.render(sources))?;
+    let (compiled, _) = evaluate(node, &source_map).map_err(|diag| diag.render(sources))?;
+    if compiled != value {
+        let diag = Diagnostic::new(
+            format!(
+                "the compiled test evaluates to {}, instead of {}",
+                compiled, value
+            ),
+            test.span,
+        );
+        return Err(diag.render(sources));
+    }
+    Ok(())
+}
+
+// runs all the tests, returning how many of them failed
+pub fn run_tests(
+    tests: &[Test],
+    sources: &[SourceFile],
+    out: &mut dyn Write,
+) -> std::io::Result<usize> {
+    writeln!(out, "running {} tests", tests.len())?;
+    let mut failed = 0;
+    for test in tests {
+        match run_test(test, sources) {
+            Ok(()) => writeln!(out, "test {} ... ok", test.name)?,
+            Err(err) => {
+                failed += 1;
+                writeln!(out, "test {} ... FAILED", test.name)?;
+                write!(out, "{}", err)?;
+            }
+        }
+    }
+    writeln!(
+        out,
+        "\ntest result: {}. {} passed; {} failed",
+        if failed == 0 { "ok" } else { "FAILED" },
+        tests.len() - failed,
+        failed
+    )?;
+    Ok(failed)
+}
@@ -0,0 +1,549 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use super::{
+    ast::{BinaryOp, Binding, Pattern, UnuaryOp, Value},
+    formatter::{binary_op, unuary_op},
+    interpreter::builtin_call,
+    source::{Diagnostic, Span},
+    Iden, LNode, LNodeRef,
+};
+
+type TypeResult<T> = Result<T, Diagnostic>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Bool,
+    Str,
+    Var(usize),
+    Function(Box<Type>, Box<Type>),
+    Tuple(Vec<Type>),
+    List(Box<Type>),
+}
+
+impl Type {
+    fn function(param: Type, result: Type) -> Type {
+        Type::Function(Box::new(param), Box::new(result))
+    }
+
+    fn list(item: Type) -> Type {
+        Type::List(Box::new(item))
+    }
+
+    fn vars(&self, vars: &mut Vec<usize>) {
+        match self {
+            Type::Int | Type::Bool | Type::Str => {}
+            Type::Var(var) => {
+                if !vars.contains(var) {
+                    vars.push(*var);
+                }
+            }
+            Type::Function(param, result) => {
+                param.vars(vars);
+                result.vars(vars);
+            }
+            Type::Tuple(items) => items.iter().for_each(|item| item.vars(vars)),
+            Type::List(item) => item.vars(vars),
+        }
+    }
+
+    // type variables are named 'a, 'b... in the order they appear
+    fn write(&self, names: &[usize], f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Str => write!(f, "Str"),
+            Type::Var(var) => {
+                let index = names.iter().position(|name| name == var).unwrap_or(0);
+                let letter = (b'a' + (index % 26) as u8) as char;
+                match index / 26 {
+                    0 => write!(f, "'{letter}"),
+                    n => write!(f, "'{letter}{n}"),
+                }
+            }
+            Type::Function(param, result) => {
+                if matches!(param.as_ref(), Type::Function(..)) {
+                    write!(f, "(")?;
+                    param.write(names, f)?;
+                    write!(f, ")")?;
+                } else {
+                    param.write(names, f)?;
+                }
+                write!(f, " -> ")?;
+                result.write(names, f)
+            }
+            Type::Tuple(items) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.write(names, f)?;
+                }
+                write!(f, ")")
+            }
+            Type::List(item) => {
+                write!(f, "[")?;
+                item.write(names, f)?;
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names = vec![];
+        self.vars(&mut names);
+        self.write(&names, f)
+    }
+}
+
+// a type whose variables are named along with another one's
+struct Shown<'a>(&'a Type, &'a [usize]);
+
+impl Display for Shown<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.write(self.1, f)
+    }
+}
+
+// both types of a mismatch, with their variables named consistently
+fn show_pair(a: &Type, b: &Type) -> (String, String) {
+    let mut names = vec![];
+    a.vars(&mut names);
+    b.vars(&mut names);
+    (Shown(a, &names).to_string(), Shown(b, &names).to_string())
+}
+
+// a type generalized over some variables, each use gets fresh ones
+#[derive(Clone, Debug)]
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Type,
+}
+
+impl Scheme {
+    fn monomorphic(ty: Type) -> Self {
+        Scheme { vars: vec![], ty }
+    }
+}
+
+type Env = HashMap<Iden, Scheme>;
+
+enum Mismatch {
+    Different,
+    // a variable would contain itself, like the type of `x` in `x x`
+    Infinite,
+}
+
+struct Checker {
+    // what each type variable was unified with
+    substitution: Vec<Option<Type>>,
+    // the operands of `==`, which must end up being Int, Bool or Str
+    comparisons: Vec<(Type, Span)>,
+    // the type of each variable use and binding, for tooling
+    types: Vec<(Span, Type)>,
+}
+
+impl Checker {
+    fn new() -> Self {
+        Self {
+            substitution: vec![],
+            comparisons: vec![],
+            types: vec![],
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.substitution.push(None);
+        Type::Var(self.substitution.len() - 1)
+    }
+
+    // follows the substitution until the type isn't a bound variable
+    fn shallow(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(var) = ty {
+            match &self.substitution[var] {
+                Some(bound) => ty = bound.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    fn resolve(&self, ty: &Type) -> Type {
+        match self.shallow(ty) {
+            Type::Function(param, result) => {
+                Type::function(self.resolve(&param), self.resolve(&result))
+            }
+            Type::Tuple(items) => {
+                Type::Tuple(items.iter().map(|item| self.resolve(item)).collect())
+            }
+            Type::List(item) => Type::list(self.resolve(&item)),
+            ty => ty,
+        }
+    }
+
+    fn occurs(&self, var: usize, ty: &Type) -> bool {
+        let mut vars = vec![];
+        self.resolve(ty).vars(&mut vars);
+        vars.contains(&var)
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> Result<(), Mismatch> {
+        match (self.shallow(a), self.shallow(b)) {
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => {
+                if self.occurs(var, &ty) {
+                    return Err(Mismatch::Infinite);
+                }
+                self.substitution[var] = Some(ty);
+                Ok(())
+            }
+            (Type::Int, Type::Int) | (Type::Bool, Type::Bool) | (Type::Str, Type::Str) => Ok(()),
+            (Type::Function(p1, r1), Type::Function(p2, r2)) => {
+                self.unify(&p1, &p2)?;
+                self.unify(&r1, &r2)
+            }
+            (Type::Tuple(a), Type::Tuple(b)) if a.len() == b.len() => {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    self.unify(a, b)?;
+                }
+                Ok(())
+            }
+            (Type::List(a), Type::List(b)) => self.unify(&a, &b),
+            _ => Err(Mismatch::Different),
+        }
+    }
+
+    // unifies the type found at `span` with the expected one, describing the mismatch with `what`
+    fn expect(
+        &mut self,
+        found: &Type,
+        expected: &Type,
+        span: Span,
+        what: impl FnOnce(String, String) -> String,
+    ) -> TypeResult<()> {
+        self.unify(found, expected).map_err(|mismatch| {
+            let (expected, found) = (self.resolve(expected), self.resolve(found));
+            let (expected_name, found_name) = show_pair(&expected, &found);
+            match mismatch {
+                Mismatch::Different => Diagnostic::new(what(expected_name, found_name), span),
+                Mismatch::Infinite => Diagnostic::new(
+                    format!("infinite type: {found_name} = {expected_name}"),
+                    span,
+                ),
+            }
+        })
+    }
+
+    fn mismatch(expected: String, found: String) -> String {
+        format!("mismatched types: expected {expected}, found {found}")
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let fresh: HashMap<usize, Type> =
+            scheme.vars.iter().map(|var| (*var, self.fresh())).collect();
+        substitute(&self.resolve(&scheme.ty), &fresh)
+    }
+
+    // generalizes the variables of a type which aren't bound by the environment
+    fn generalize(&self, ty: &Type, env: &Env) -> Scheme {
+        let mut bound = vec![];
+        for scheme in env.values() {
+            let mut vars = vec![];
+            self.resolve(&scheme.ty).vars(&mut vars);
+            bound.extend(vars.into_iter().filter(|var| !scheme.vars.contains(var)));
+        }
+        let bound: HashSet<usize> = bound.into_iter().collect();
+
+        let ty = self.resolve(ty);
+        let mut vars = vec![];
+        ty.vars(&mut vars);
+        vars.retain(|var| !bound.contains(var));
+        Scheme { vars, ty }
+    }
+
+    fn check_binding(&mut self, binding: &Binding, env: &Env) -> TypeResult<Scheme> {
+        if binding.params.is_empty() {
+            if binding.rec {
+                return Err(Diagnostic::new(
+                    format!("{} is recursive but isn't a function", binding.name),
+                    binding.span,
+                ));
+            }
+            let ty = self.infer(&binding.value, env)?;
+            return Ok(Scheme::monomorphic(ty));
+        }
+
+        let mut inner = env.clone();
+        let params: Vec<Type> = binding.params.iter().map(|_| self.fresh()).collect();
+        for (param, ty) in binding.params.iter().zip(params.iter()) {
+            inner.insert(param.clone(), Scheme::monomorphic(ty.clone()));
+        }
+        let result = self.fresh();
+        let ty = params.iter().rev().fold(result.clone(), |acc, param| {
+            Type::function(param.clone(), acc)
+        });
+
+        // recursive calls all use the same type
+        if binding.rec {
+            inner.insert(binding.name.clone(), Scheme::monomorphic(ty.clone()));
+        }
+        let value = self.infer(&binding.value, &inner)?;
+        self.expect(&value, &result, binding.value.span, |expected, found| {
+            format!(
+                "{} returns {found}, but its recursive uses expect {expected}",
+                binding.name
+            )
+        })?;
+        Ok(self.generalize(&ty, env))
+    }
+
+    fn infer_let(&mut self, bindings: &[Binding], body: &LNodeRef, env: &Env) -> TypeResult<Type> {
+        let mut env = env.clone();
+        for binding in bindings {
+            let scheme = self
+                .check_binding(binding, &env)
+                .map_err(|diag| diag.with_note(format!("in the definition of {}", binding.name)))?;
+            self.types.push((binding.span, scheme.ty.clone()));
+            env.insert(binding.name.clone(), scheme);
+        }
+        self.infer(body, &env)
+    }
+
+    fn infer_apply(&mut self, func: &LNodeRef, param: &LNodeRef, env: &Env) -> TypeResult<Type> {
+        if let Some((name, first)) = builtin_call(func, |name| env.contains_key(name)) {
+            let first_type = self.infer(first, env)?;
+            if name == "assert" {
+                self.expect(&first_type, &Type::Bool, first.span, |expected, found| {
+                    format!("assert expects {expected}, found {found}")
+                })?;
+            }
+            return self.infer(param, env);
+        }
+
+        let name = match applied_function(func) {
+            Some(name) => name.to_string(),
+            None => "this expression".to_owned(),
+        };
+        let func_type = self.infer(func, env)?;
+        let param_type = self.infer(param, env)?;
+        match self.shallow(&func_type) {
+            Type::Function(expected, result) => {
+                self.expect(&param_type, &expected, param.span, |expected, found| {
+                    format!("{name} expects {expected}, found {found}")
+                })?;
+                Ok(*result)
+            }
+            Type::Var(_) => {
+                let result = self.fresh();
+                let ty = Type::function(param_type, result.clone());
+                self.expect(&func_type, &ty, func.span, Self::mismatch)?;
+                Ok(result)
+            }
+            _ if matches!(func.as_ref(), LNode::Apply { .. }) => Err(Diagnostic::new(
+                format!("{name} is applied to too many arguments"),
+                param.span,
+            )),
+            ty => Err(Diagnostic::new(
+                format!("{name} isn't a function, it has type {}", self.resolve(&ty)),
+                func.span,
+            )),
+        }
+    }
+
+    fn infer_match(
+        &mut self,
+        value: &LNodeRef,
+        arms: &[(Pattern, LNodeRef)],
+        env: &Env,
+    ) -> TypeResult<Type> {
+        let value_type = self.infer(value, env)?;
+        let result = self.fresh();
+        for (pattern, body) in arms {
+            let mut inner = env.clone();
+            let pattern_type = match pattern {
+                Pattern::Tuple(names) => {
+                    let items: Vec<Type> = names.iter().map(|_| self.fresh()).collect();
+                    for (name, ty) in names.iter().zip(items.iter()) {
+                        inner.insert(name.clone(), Scheme::monomorphic(ty.clone()));
+                    }
+                    Type::Tuple(items)
+                }
+                Pattern::Nil => Type::list(self.fresh()),
+                Pattern::Cons(head, tail) => {
+                    let item = self.fresh();
+                    let list = Type::list(item.clone());
+                    inner.insert(head.clone(), Scheme::monomorphic(item));
+                    inner.insert(tail.clone(), Scheme::monomorphic(list.clone()));
+                    list
+                }
+            };
+            self.expect(&value_type, &pattern_type, value.span, |expected, found| {
+                format!("the pattern expects {expected}, but the matched value is {found}")
+            })?;
+            let body_type = self.infer(body, &inner)?;
+            self.expect(&body_type, &result, body.span, |expected, found| {
+                format!("match arms have different types: expected {expected}, found {found}")
+            })?;
+        }
+        Ok(result)
+    }
+
+    fn infer(&mut self, node: &LNodeRef, env: &Env) -> TypeResult<Type> {
+        match node.as_ref() {
+            LNode::Litteral(Value::Int(_)) => Ok(Type::Int),
+            LNode::Litteral(Value::Bool(_)) => Ok(Type::Bool),
+            LNode::Litteral(Value::Str(_)) => Ok(Type::Str),
+            LNode::Variable(name) => match env.get(name) {
+                Some(scheme) => {
+                    let ty = self.instantiate(scheme);
+                    self.types.push((node.span, ty.clone()));
+                    Ok(ty)
+                }
+                None => Err(Diagnostic::new(
+                    format!("unbound variable {}", name),
+                    node.span,
+                )),
+            },
+            LNode::Let { bindings, body, .. } => self.infer_let(bindings, body, env),
+            LNode::Apply { func, param, .. } => self.infer_apply(func, param, env),
+            LNode::BinaryOp { op, left, right } => {
+                let left_type = self.infer(left, env)?;
+                let right_type = self.infer(right, env)?;
+                let symbol = binary_op(*op);
+                let expects =
+                    |expected, found| format!("`{symbol}` expects {expected}, found {found}");
+                let (left_expected, right_expected, result) = match op {
+                    BinaryOp::IntAdd
+                    | BinaryOp::IntSub
+                    | BinaryOp::IntMul
+                    | BinaryOp::IntDiv
+                    | BinaryOp::IntMod => (Type::Int, Type::Int, Type::Int),
+                    BinaryOp::IntLt | BinaryOp::IntGt => (Type::Int, Type::Int, Type::Bool),
+                    BinaryOp::BoolOr | BinaryOp::BoolAnd => (Type::Bool, Type::Bool, Type::Bool),
+                    BinaryOp::StrConcat => (Type::Str, Type::Str, Type::Str),
+                    BinaryOp::StrTake | BinaryOp::StrDrop => (Type::Int, Type::Str, Type::Str),
+                    BinaryOp::Eq => {
+                        self.expect(&right_type, &left_type, right.span, |expected, found| {
+                            format!("`==` compares {expected} with {found}")
+                        })?;
+                        self.comparisons.push((left_type, node.span));
+                        return Ok(Type::Bool);
+                    }
+                };
+                self.expect(&left_type, &left_expected, left.span, expects)?;
+                self.expect(&right_type, &right_expected, right.span, expects)?;
+                Ok(result)
+            }
+            LNode::UnuaryOp { op, body } => {
+                let body_type = self.infer(body, env)?;
+                let (expected, result) = match op {
+                    UnuaryOp::IntNeg => (Type::Int, Type::Int),
+                    UnuaryOp::BoolNot => (Type::Bool, Type::Bool),
+                    UnuaryOp::StrToInt => (Type::Str, Type::Int),
+                    UnuaryOp::IntToStr => (Type::Int, Type::Str),
+                };
+                self.expect(&body_type, &expected, body.span, |expected, found| {
+                    format!("`{}` expects {expected}, found {found}", unuary_op(*op))
+                })?;
+                Ok(result)
+            }
+            LNode::If {
+                cond,
+                then_do,
+                else_do,
+            } => {
+                let cond_type = self.infer(cond, env)?;
+                self.expect(&cond_type, &Type::Bool, cond.span, |expected, found| {
+                    format!("the condition must be {expected}, found {found}")
+                })?;
+                let then_type = self.infer(then_do, env)?;
+                let else_type = self.infer(else_do, env)?;
+                self.expect(&else_type, &then_type, else_do.span, |expected, found| {
+                    format!("if branches have different types: expected {expected}, found {found}")
+                })?;
+                Ok(then_type)
+            }
+            LNode::Tuple(items) => Ok(Type::Tuple(
+                items
+                    .iter()
+                    .map(|item| self.infer(item, env))
+                    .collect::<TypeResult<_>>()?,
+            )),
+            LNode::Nil => Ok(Type::list(self.fresh())),
+            LNode::Cons { head, tail } => {
+                let head_type = self.infer(head, env)?;
+                let tail_type = self.infer(tail, env)?;
+                let expected = Type::list(head_type);
+                self.expect(&tail_type, &expected, tail.span, |expected, found| {
+                    format!("list items have different types: expected {expected}, found {found}")
+                })?;
+                Ok(expected)
+            }
+            LNode::Match { value, arms } => self.infer_match(value, arms, env),
+        }
+    }
+
+    // values of other types can't be compared once compiled
+    fn check_comparisons(&self) -> TypeResult<()> {
+        for (ty, span) in self.comparisons.iter() {
+            let ty = self.resolve(ty);
+            if matches!(ty, Type::Function(..) | Type::Tuple(_) | Type::List(_)) {
+                return Err(Diagnostic::new(
+                    format!("`==` can't compare {ty}, only Int, Bool and Str"),
+                    *span,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn substitute(ty: &Type, fresh: &HashMap<usize, Type>) -> Type {
+    match ty {
+        Type::Var(var) => fresh.get(var).cloned().unwrap_or(Type::Var(*var)),
+        Type::Function(param, result) => {
+            Type::function(substitute(param, fresh), substitute(result, fresh))
+        }
+        Type::Tuple(items) => {
+            Type::Tuple(items.iter().map(|item| substitute(item, fresh)).collect())
+        }
+        Type::List(item) => Type::list(substitute(item, fresh)),
+        ty => ty.clone(),
+    }
+}
+
+// the name of the function at the head of `f a b`
+fn applied_function(node: &LNode) -> Option<&Iden> {
+    match node {
+        LNode::Variable(name) => Some(name),
+        LNode::Apply { func, .. } => applied_function(func),
+        _ => None,
+    }
+}
+
+// infers the type of a program, reporting the first type error
+pub fn type_check(node: &LNodeRef) -> Result<Type, Diagnostic> {
+    type_spans(node).1
+}
+
+// the types of the variable uses and bindings of a program, as far as the checking went,
+// along with the result of the checking
+pub fn type_spans(node: &LNodeRef) -> (Vec<(Span, Type)>, Result<Type, Diagnostic>) {
+    let mut checker = Checker::new();
+    let res = checker
+        .infer(node, &Env::new())
+        .and_then(|ty| checker.check_comparisons().map(|_| checker.resolve(&ty)));
+    let types = checker
+        .types
+        .iter()
+        .map(|(span, ty)| (*span, checker.resolve(ty)))
+        .collect();
+    (types, res)
+}
@@ -1,8 +1,11 @@
 use std::io::{stdin, Read, Write};
+use std::rc::Rc;
 
 use icfp::evaluate;
 use icfp::parse;
 use icfp::serialize_str;
+use icfp::BinaryOp;
+use icfp::Node;
 use icfp::Token;
 use icfp::Value;
 use logos::Logos;
@@ -38,8 +41,12 @@ enum CliSubcommands {
     Eval(EvalCommand),
     Comm(CommCommand),
     Compile(CompileCommand),
+    Lsp(lasm::LspCommand),
     Solve(runner::SolveCommand),
+    Status(runner::StatusCommand),
+    Submit(runner::SubmitCommand),
     ThreeD(three_d::ThreeDCommand),
+    Tune(runner::TuneCommand),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -65,6 +72,10 @@ struct EvalCommand {
     #[argh(switch, short = 'r')]
     /// print raw token values (no newline, no quotes, etc.)
     raw: bool,
+
+    #[argh(switch, short = 's')]
+    /// print the number of beta reductions to stderr
+    stats: bool,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -78,6 +89,38 @@ struct CompileCommand {
     #[argh(option, short = 'o')]
     /// a file to write the output to
     output: Option<String>,
+
+    #[argh(switch, short = 'r')]
+    /// run the program with the interpreter instead of compiling it
+    run: bool,
+
+    #[argh(switch, short = 't')]
+    /// run the tests of the program instead of compiling it
+    test: bool,
+
+    #[argh(switch, short = 'f')]
+    /// format the program instead of compiling it
+    fmt: bool,
+
+    #[argh(switch, short = 'O')]
+    /// minimize the size of the output, and report the size of each top level binding
+    optimize: bool,
+
+    #[argh(switch, short = 's')]
+    /// print the size of the compiled program to stderr
+    stats: bool,
+
+    #[argh(switch, short = 'p')]
+    /// print the compiled program's ast instead of its icfp code
+    print: bool,
+
+    #[argh(switch, short = 'e')]
+    /// evaluate the compiled program, and print its value and beta reductions to stderr
+    eval: bool,
+
+    #[argh(option, short = 'w')]
+    /// a string to concatenate in front of the program's value, such as "solve lambdaman7 "
+    wrap: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -107,6 +150,7 @@ fn main() -> std::io::Result<()> {
             file,
             output,
             raw,
+            stats,
         }) => {
             // read the program input
             let program = if let Some(program) = program {
@@ -135,7 +179,10 @@ fn main() -> std::io::Result<()> {
             if print {
                 ast.pretty_print(outstream)?;
             } else {
-                let res = icfp::evaluate(ast);
+                let (res, substitutions) = icfp::evaluate_counting(ast);
+                if stats {
+                    eprintln!("{} beta reductions", substitutions);
+                }
                 if raw {
                     match res {
                         Value::Bool(b) => write!(outstream, "{}", b)?,
@@ -173,14 +220,79 @@ fn main() -> std::io::Result<()> {
                 }
             }
         }
-        CliSubcommands::Compile(CompileCommand { program, output }) => {
-            // read the program input
+        CliSubcommands::Compile(CompileCommand {
+            program,
+            output,
+            run,
+            test,
+            fmt,
+            optimize,
+            stats,
+            print,
+            eval,
+            wrap,
+        }) => {
+            // reformat the source, nothing is compiled
+            if fmt {
+                let source = if let Some(program) = &program {
+                    std::fs::read_to_string(program)?
+                } else {
+                    let mut program = String::new();
+                    stdin().lock().read_to_string(&mut program)?;
+                    program
+                };
+                let formatted = match lasm::format(&source) {
+                    Ok(res) => res,
+                    Err(diagnostic) => {
+                        let path = program.unwrap_or_else(|| "<input>".to_owned());
+                        eprint!(
+                            "{}",
+                            diagnostic.render(&[lasm::SourceFile::new(path, source)])
+                        );
+                        std::process::exit(1);
+                    }
+                };
+                match output {
+                    Some(output) => std::fs::write(output, formatted)?,
+                    None => print!("{formatted}"),
+                }
+                return Ok(());
+            }
+
+            // run the inline tests, nothing is compiled
+            if test {
+                let tests = if let Some(program) = program {
+                    lasm::load_tests(std::path::Path::new(&program))
+                } else {
+                    let mut program = String::new();
+                    stdin().lock().read_to_string(&mut program)?;
+                    lasm::load_tests_str(&program, &std::env::current_dir()?)
+                };
+                let (tests, sources) = match tests {
+                    Ok(res) => res,
+                    Err(err) => {
+                        eprintln!("{err}");
+                        std::process::exit(1);
+                    }
+                };
+                let failed = lasm::run_tests(&tests, &sources, &mut std::io::stdout().lock())?;
+                std::process::exit(if failed == 0 { 0 } else { 1 });
+            }
+
+            // read and parse the program input, along with its imports
             let program = if let Some(program) = program {
-                std::fs::read_to_string(program)?
+                lasm::load(std::path::Path::new(&program))
             } else {
                 let mut program = String::new();
                 stdin().lock().read_to_string(&mut program)?;
-                program
+                lasm::load_str(&program, &std::env::current_dir()?)
+            };
+            let (program, sources) = match program {
+                Ok(res) => res,
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                }
             };
 
             // setup the output file, if any
@@ -190,24 +302,83 @@ fn main() -> std::io::Result<()> {
                 &mut std::io::stdout().lock()
             };
 
-            // parse the program
-            let program = match lasm::parse(&program) {
+            // interpret the program, for debugging
+            if run {
+                match lasm::run(&program, &sources) {
+                    Ok(res) => writeln!(outstream, "{res}")?,
+                    Err(diagnostic) => {
+                        eprint!("{}", diagnostic.render(&sources));
+                        std::process::exit(1);
+                    }
+                }
+                return Ok(());
+            }
+
+            // compile and write the result
+            let res = if optimize {
+                lasm::compile_optimized(program).map(|(res, source_map, sizes)| {
+                    for size in sizes {
+                        eprintln!("{:>8} {}", size.size, size.name);
+                    }
+                    (res, source_map)
+                })
+            } else {
+                lasm::compile_with_source_map(program)
+            };
+            let (res, mut source_map) = match res {
                 Ok(res) => res,
-                Err(err) => {
-                    eprintln!(
-                        "Parsing failed:\n{}",
-                        nom::error::convert_error(program.as_str(), err)
-                    );
+                Err(diagnostic) => {
+                    eprint!("{}", diagnostic.render(&sources));
                     std::process::exit(1);
                 }
             };
 
-            // compile and write the result
-            let res = lasm::compile(program);
-            let bin = serialize_str(res);
-            writeln!(outstream, "{bin}")?;
+            // the prefix is part of the program, so that it is counted and evaluated
+            let res = match wrap {
+                Some(prefix) if !prefix.chars().all(icfp::is_icfp_char) => {
+                    eprintln!("error: the prefix {prefix:?} can't be encoded as an ICFP string");
+                    std::process::exit(1);
+                }
+                Some(prefix) => {
+                    let wrapped = Rc::new(Node::BinaryOp {
+                        op: BinaryOp::StrConcat,
+                        left: Rc::new(Node::Value(Value::Str(prefix))),
+                        right: res.clone(),
+                    });
+                    if let Some(span) = source_map.get(&res) {
+                        source_map.insert(&wrapped, span);
+                    }
+                    wrapped
+                }
+                None => res,
+            };
+
+            let bin = serialize_str(res.clone());
+            if stats {
+                eprintln!("{} bytes", bin.len());
+            }
+            if eval {
+                let (value, substitutions) = match lasm::evaluate(res.clone(), &source_map) {
+                    Ok(res) => res,
+                    Err(diagnostic) => {
+                        eprint!("{}", diagnostic.render(&sources));
+                        std::process::exit(1);
+                    }
+                };
+                eprintln!("{value}");
+                eprintln!("{substitutions} beta reductions");
+            }
+            if print {
+                res.pretty_print(outstream)?;
+            } else {
+                writeln!(outstream, "{bin}")?;
+            }
         }
+        CliSubcommands::Lsp(cmd) => cmd.run(),
         CliSubcommands::Solve(cmd) => cmd.run(),
+        CliSubcommands::Status(cmd) => cmd.run(),
+        CliSubcommands::Submit(cmd) => cmd.run(),
+        CliSubcommands::Tune(cmd) => cmd.run(),
         CliSubcommands::ThreeD(cmd) => cmd.run(),
     };
     Ok(())
@@ -224,10 +395,10 @@ fn send_receive_single_command(
     } else {
         comms::send_string(command)
     } {
-        Some(response) => {
+        Ok(response) => {
             print_response(response, print_raw_response, add_newline);
         }
-        None => eprintln!("Failed to send message"),
+        Err(err) => eprintln!("Failed to send message: {err}"),
     }
 }
 
@@ -236,13 +407,18 @@ pub fn print_response(response: String, print_raw_response: bool, add_newline: b
         println!("{}", response);
         return;
     }
-    let node = parse(&mut Token::lexer(&response)).expect("Failed to parse response");
-    match evaluate(node) {
-        Value::Bool(b) => print!("{}", b),
-        Value::Int(i) => print!("{}", i),
-        Value::Str(s) => print!("{}", s),
-    }
+    print!("{}", response_text(response));
     if add_newline {
         println!();
     }
 }
+
+// decodes the value of a response
+pub fn response_text(response: String) -> String {
+    let node = parse(&mut Token::lexer(&response)).expect("Failed to parse response");
+    match evaluate(node) {
+        Value::Bool(b) => b.to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::Str(s) => s,
+    }
+}
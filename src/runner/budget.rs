@@ -0,0 +1,80 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use super::Solution;
+
+// tells solvers when to stop, and where to report the best solution they found so far
+pub struct Budget<'a> {
+    deadline: Option<Instant>,
+    cancelled: &'a AtomicBool,
+    progress: &'a dyn Fn(&Solution),
+}
+
+impl<'a> Budget<'a> {
+    pub fn new(
+        time_limit: Option<Duration>,
+        cancelled: &'a AtomicBool,
+        progress: &'a dyn Fn(&Solution),
+    ) -> Self {
+        Self::until(
+            time_limit.map(|limit| Instant::now() + limit),
+            cancelled,
+            progress,
+        )
+    }
+
+    pub fn until(
+        deadline: Option<Instant>,
+        cancelled: &'a AtomicBool,
+        progress: &'a dyn Fn(&Solution),
+    ) -> Self {
+        Self {
+            deadline,
+            cancelled,
+            progress,
+        }
+    }
+
+    // the deadline and the cancellation flag can be sent to other threads, unlike the budget
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn cancelled(&self) -> &'a AtomicBool {
+        self.cancelled
+    }
+
+    // solvers should check this regularly, and return their best solution once it is true
+    pub fn is_over(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    pub fn report(&self, solution: &Solution) {
+        (self.progress)(solution)
+    }
+}
+
+// parses durations such as 500ms, 60s, 5m or 2h. plain numbers are seconds
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration: {value}"))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "" | "s" => Ok(Duration::from_secs(amount)),
+        "m" => Ok(Duration::from_secs(amount * 60)),
+        "h" => Ok(Duration::from_secs(amount * 3600)),
+        _ => Err(format!(
+            "unknown duration unit {unit:?}, expected ms, s, m or h"
+        )),
+    }
+}
@@ -1,4 +1,6 @@
-use super::{Problem, Solution, Solver};
+use std::collections::HashMap;
+
+use super::{Budget, Parameter, Problem, Solution, Solver};
 
 #[derive(Clone)]
 pub struct Chain {
@@ -9,7 +11,26 @@ pub struct Chain {
 
 impl Solver for Chain {
     fn name(&self) -> String {
-        format!("{}+{}", self.solver0.name(), self.solver1.name())
+        // portfolios bind looser than chains
+        let name = |solver: &dyn Solver| {
+            let name = solver.name();
+            if name.contains('|') {
+                format!("({name})")
+            } else {
+                name
+            }
+        };
+        format!(
+            "{}+{}",
+            name(self.solver0.as_ref()),
+            name(self.solver1.as_ref())
+        )
+    }
+
+    fn parameters(&self) -> HashMap<String, Parameter> {
+        let mut parameters = self.solver0.parameters();
+        parameters.extend(self.solver1.parameters());
+        parameters
     }
 
     fn initialize(&mut self, problem: Problem, solution: Option<Solution>) {
@@ -17,10 +38,11 @@ impl Solver for Chain {
         self.problem = problem;
     }
 
-    fn solve(&mut self) -> Solution {
+    fn solve(&mut self, budget: &Budget) -> Option<Solution> {
+        let solution = self.solver0.solve(budget)?;
         self.solver1
-            .initialize(self.problem.clone(), Some(self.solver0.solve()));
-        self.solver1.solve()
+            .initialize(self.problem.clone(), Some(solution));
+        self.solver1.solve(budget)
     }
 }
 
@@ -1,40 +1,152 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
 use argh::FromArgs;
 use serde::{Deserialize, Serialize};
 
+use super::{
+    parse_duration,
+    submit::{self, Submission},
+    tune::{self, TunedDefaults},
+    verify_solution, Budget, Parameter, Problem, Solution, Solver, Verifier,
+};
+
 #[derive(FromArgs, PartialEq, Debug)]
 /// Evaluate a program
 #[argh(subcommand, name = "solve")]
 pub struct SolveCommand {
-    #[argh(positional)]
-    pub problem_name: String,
-    #[argh(positional)]
-    pub solver_spec: String,
+    #[argh(positional, arg_name = "category solver_spec")]
+    /// the problem category and the solver spec, optional with --list-solvers
+    pub args: Vec<String>,
     #[argh(option, short = 'p')]
     /// solve a single problem
     pub single_problem: Option<String>,
+    #[argh(option, short = 'j', default = "1")]
+    /// the number of problems to solve in parallel
+    pub jobs: usize,
+    #[argh(option, from_str_fn(parse_duration))]
+    /// the time given to each problem, such as 60s or 5m
+    pub time_limit: Option<Duration>,
+    #[argh(switch)]
+    /// list the solvers and their parameters
+    pub list_solvers: bool,
+    #[argh(option)]
+    /// a file of per problem parameters, as written by tune --output
+    pub defaults: Option<PathBuf>,
+    #[argh(switch)]
+    /// start the solvers from the best solution of each problem
+    pub from_best: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SolutionMetadata {
-    solver_spec: String,
-    score: u64,
+// set on ctrl-c, so that solvers stop and their best solutions are saved
+pub static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+// the first ctrl-c sets CANCELLED, a second one stops right away
+pub fn handle_ctrl_c(message: &'static str) {
+    ctrlc::set_handler(move || {
+        if CANCELLED.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        eprintln!("{message}");
+    })
+    .expect("Failed to set the ctrl-c handler");
+}
+
+// older metadata files only have the solver spec and the score
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SolutionMetadata {
+    pub solver_spec: String,
+    pub score: u64,
+    // seconds since the unix epoch
+    pub timestamp: u64,
+    // seconds spent solving the problem
+    pub wall_time: f64,
+    pub parameters: BTreeMap<String, Parameter>,
+    pub seed: Option<u64>,
+    pub git_commit: Option<String>,
+    // the decoded response of the server, once submitted
+    pub response: Option<String>,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+// the commit the solvers were built from, unknown when not built from the repository
+fn git_commit() -> Option<String> {
+    option_env!("GIT_COMMIT").map(str::to_owned)
 }
 
 impl SolveCommand {
     pub fn run(&self) {
-        let solver = super::create_solver(&self.solver_spec);
+        if self.list_solvers {
+            print!("{}", super::list_solvers());
+            return;
+        }
+        let [problem_name, solver_spec] = self.args.as_slice() else {
+            eprintln!("Expected a problem category and a solver spec");
+            std::process::exit(1);
+        };
+        let solver = match super::create_solver(solver_spec) {
+            Ok(solver) => solver,
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        };
         println!("Solver: {}", solver.name());
 
+        // the solvers of problems with tuned parameters are built up front, to report errors early
+        let tuned = match self
+            .defaults
+            .as_ref()
+            .map(|path| tune::load_tuned_defaults(path))
+        {
+            Some(Ok(tuned)) => tuned,
+            Some(Err(err)) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            None => TunedDefaults::new(),
+        };
+        let tuned_solvers = tuned
+            .iter()
+            .map(|(problem, defaults)| {
+                let solver = super::create_solver_with_defaults(solver_spec, defaults)
+                    .map_err(|err| format!("invalid parameters for {problem}: {err}"))?;
+                Ok((problem.clone(), solver))
+            })
+            .collect::<Result<HashMap<_, _>, String>>();
+        let tuned_solvers = match tuned_solvers {
+            Ok(solvers) => solvers,
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        };
+
         let current_dir = std::env::current_dir().expect("Failed to get the current directory");
-        let problem_dir = current_dir.join("problems").join(&self.problem_name);
+        let problem_dir = current_dir.join("problems").join(problem_name);
         let current_solutions_dir = current_dir
             .join("solutions")
             .join("current")
-            .join(&self.problem_name);
+            .join(problem_name);
         let best_solutions_dir = current_dir
             .join("solutions")
             .join("best")
-            .join(&self.problem_name);
+            .join(problem_name);
 
         if !problem_dir.exists() {
             eprintln!("Problem path not found: {}", problem_dir.display());
@@ -48,93 +160,402 @@ impl SolveCommand {
             .expect("Failed to create the best solution directory");
 
         // Load all problems from the problem directory
-        let mut problems = std::fs::read_dir(problem_dir)
-            .expect("Failed to read the problem directory")
-            .map(|entry| entry.expect("Failed to read a problem directory entry"))
-            .map(|entry| {
-                let problem_name = entry.file_name().to_string_lossy().to_string();
-                let problem_path = entry.path();
-                super::Problem::new(problem_path, problem_name)
-            })
-            .filter(|p| p.path.extension().is_none())
-            .collect::<Vec<_>>();
+        let mut problems = Problem::list(&problem_dir);
 
         if let Some(problem) = self.single_problem.as_ref() {
             problems.retain(|p| p.name == *problem);
         }
 
-        // Solve all problems with the given solver
-        for problem in problems {
-            println!("Solving problem: {}", problem.name);
-
-            let mut best_solution_path = best_solutions_dir.join(&problem.name);
-            best_solution_path.set_extension("icfp");
-            let mut current_solution_path = current_solutions_dir.join(&problem.name);
-            current_solution_path.set_extension("icfp");
-
-            let mut solver = solver.clone();
-            solver.initialize(problem.clone(), None);
-            let solution = solver.solve();
-
-            solution.save(&current_solution_path);
-            let current_metadata = SolutionMetadata {
-                solver_spec: solver.name(),
-                score: solution.score,
-            };
-            std::fs::write(
-                current_solution_path.with_extension("meta"),
-                serde_json::to_string(&current_metadata).unwrap(),
-            )
-            .expect("Failed to write the current solution metadata");
-
-            let mut new_best = false;
-            if best_solution_path.exists() {
-                let metadata_file = best_solution_path.with_extension("meta");
-                let best_metadata: SolutionMetadata = serde_json::from_str(
-                    &std::fs::read_to_string(metadata_file)
-                        .expect("Failed to read the metadata file"),
-                )
-                .unwrap();
+        handle_ctrl_c("Interrupted, saving the best solutions found so far");
+
+        // Solve all problems with the given solver, on `jobs` threads
+        let dirs = SolutionDirs {
+            category: problem_name.clone(),
+            current: current_solutions_dir,
+            best: best_solutions_dir,
+            history: current_dir.join("solutions").join("history"),
+            git_commit: git_commit(),
+            verifier: super::create_verifier(problem_name),
+        };
+        let problems = Mutex::new(problems.into_iter());
+        let next_problem = || problems.lock().unwrap().next();
+        std::thread::scope(|scope| {
+            for _ in 0..self.jobs.max(1) {
+                scope.spawn(|| {
+                    while let Some(problem) = next_problem() {
+                        if CANCELLED.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let solver = tuned_solvers.get(&problem.name).unwrap_or(&solver);
+                        self.solve_problem(solver.as_ref(), &problem, &dirs);
+                    }
+                });
+            }
+        });
+    }
+
+    fn solve_problem(&self, solver: &dyn Solver, problem: &Problem, dirs: &SolutionDirs) {
+        println!("Solving problem: {}", problem.name);
+        let mut solver = dyn_clone::clone_box(solver);
+        let initial = if self.from_best {
+            dirs.load_best(problem)
+        } else {
+            None
+        };
+        solver.initialize(problem.clone(), initial);
 
+        // the best of the reported solutions and the final one is kept
+        let best: RefCell<Option<Solution>> = RefCell::new(None);
+        let keep_best = |solution: &Solution| {
+            let mut best = best.borrow_mut();
+            if best.as_ref().is_none_or(|best| solution.score < best.score) {
                 println!(
-                    "Best solution: {} (solver: {})",
-                    best_metadata.score, best_metadata.solver_spec
+                    "{}: found a solution of score {}",
+                    problem.name, solution.score
                 );
+                *best = Some(solution.clone());
+            }
+        };
+        let budget = Budget::new(self.time_limit, &CANCELLED, &keep_best);
+        let start = Instant::now();
+        if let Some(solution) = solver.solve(&budget) {
+            keep_best(&solution);
+        }
+        if budget.is_over() {
+            println!("Stopped solving problem: {}", problem.name);
+        }
 
-                if solution.score < best_metadata.score {
-                    println!("New best solution: {}", current_metadata.score);
-                    println!("!!! WE ARE WINNING SON !!!");
+        // the report of each problem is printed at once
+        let Some(solution) = best.into_inner() else {
+            println!("No solution found for problem: {}", problem.name);
+            return;
+        };
+        let metadata = SolutionMetadata {
+            solver_spec: solver.name(),
+            score: solution.score,
+            timestamp: unix_time(),
+            wall_time: start.elapsed().as_secs_f64(),
+            parameters: solver.parameters().into_iter().collect(),
+            seed: solution.seed,
+            git_commit: dirs.git_commit.clone(),
+            response: None,
+        };
+        let report = dirs.save(problem, metadata, &solution);
+        print!("{report}");
+    }
+}
 
-                    new_best = true;
-                } else {
-                    println!(
-                        "Current solution (not better): {} >= {}",
-                        current_metadata.score, best_metadata.score
-                    );
-                }
-            } else {
-                println!("First solution: {}", current_metadata.score);
+struct SolutionDirs {
+    category: String,
+    current: PathBuf,
+    best: PathBuf,
+    // every attempt is archived there, by problem
+    history: PathBuf,
+    git_commit: Option<String>,
+    // only verified solutions are submitted and kept as the best ones
+    verifier: Option<&'static dyn Verifier>,
+}
+
+// the best solution of a problem is compared, sent and replaced by one thread at a time, which
+// holds its path here. a better solution waits for the one being sent, in case the server fails
+static SUBMITTING: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+static SUBMITTED: Condvar = Condvar::new();
+
+// the best solution of a problem, held until it is dropped
+struct Reservation(PathBuf);
+
+impl Reservation {
+    fn new(best_solution_path: &Path) -> Self {
+        let mut submitting = SUBMITTING.lock().unwrap_or_else(|err| err.into_inner());
+        while submitting.contains(best_solution_path) {
+            submitting = SUBMITTED
+                .wait(submitting)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+        submitting.insert(best_solution_path.to_owned());
+        Self(best_solution_path.to_owned())
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut submitting = SUBMITTING.lock().unwrap_or_else(|err| err.into_inner());
+        submitting.remove(&self.0);
+        SUBMITTED.notify_all();
+    }
+}
 
-                new_best = true;
+// writes to a temporary file first, so that a solution is never seen half written. the temporary
+// file keeps the extension, the solution and its metadata don't share one
+pub fn write_atomically(path: &Path, contents: &str) {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    std::fs::write(&tmp_path, contents).expect("Failed to write a solution file");
+    std::fs::rename(&tmp_path, path).expect("Failed to move a solution file");
+}
+
+pub fn best_metadata(best_solution_path: &Path) -> Option<SolutionMetadata> {
+    let metadata_file = best_solution_path.with_extension("meta");
+    if !metadata_file.exists() {
+        return None;
+    }
+    let text = std::fs::read_to_string(metadata_file).expect("Failed to read the metadata file");
+    Some(serde_json::from_str(&text).unwrap())
+}
+
+// the metadata goes last, it is what tells whether a best solution exists
+pub fn write_best(best_solution_path: &Path, text: &str, metadata_text: &str) {
+    write_atomically(best_solution_path, text);
+    write_atomically(&best_solution_path.with_extension("meta"), metadata_text);
+}
+
+// the first free <timestamp>[-i].icfp name of the directory, attempts may share a second
+fn history_path(dir: &Path, timestamp: u64) -> PathBuf {
+    let mut name = timestamp.to_string();
+    for i in 1.. {
+        if !dir.join(&name).with_extension("icfp").exists() {
+            break;
+        }
+        name = format!("{timestamp}-{i}");
+    }
+    dir.join(name).with_extension("icfp")
+}
+
+impl SolutionDirs {
+    // reads the best solution back, for solvers to improve on it
+    fn load_best(&self, problem: &Problem) -> Option<Solution> {
+        let mut best_solution_path = self.best.join(&problem.name);
+        best_solution_path.set_extension("icfp");
+        let Ok(text) = std::fs::read_to_string(&best_solution_path) else {
+            println!("{}: no best solution to start from", problem.name);
+            return None;
+        };
+        // hand written solutions may have no metadata, their score is their length
+        let score = best_metadata(&best_solution_path)
+            .map_or(text.trim().len() as u64, |metadata| metadata.score);
+        let solution = Solution::parse(&text, score);
+        match &solution {
+            Some(solution) => println!(
+                "{}: starting from the best solution, of score {}",
+                problem.name, solution.score
+            ),
+            None => println!(
+                "{}: the best solution isn't valid ICFP, not starting from it",
+                problem.name
+            ),
+        }
+        solution
+    }
+
+    // saves the solution as the current one, and as the best one if it improves on it.
+    // returns what happened, to be printed
+    fn save(
+        &self,
+        problem: &Problem,
+        mut metadata: SolutionMetadata,
+        solution: &Solution,
+    ) -> String {
+        let mut current_solution_path = self.current.join(&problem.name);
+        current_solution_path.set_extension("icfp");
+        solution.save(&current_solution_path);
+        std::fs::write(
+            current_solution_path.with_extension("meta"),
+            serde_json::to_string(&metadata).unwrap(),
+        )
+        .expect("Failed to write the current solution metadata");
+
+        let report = self.promote(problem, &mut metadata, solution);
+        self.archive(problem, &metadata, solution);
+        report
+    }
+
+    // submits the solution and makes it the best one, if it verifies and improves on it
+    fn promote(
+        &self,
+        problem: &Problem,
+        metadata: &mut SolutionMetadata,
+        solution: &Solution,
+    ) -> String {
+        let mut report = format!("Solved problem: {}\n", problem.name);
+        let mut best_solution_path = self.best.join(&problem.name);
+        best_solution_path.set_extension("icfp");
+
+        let verified = match self.verifier {
+            Some(verifier) => verify_solution(verifier, problem, solution),
+            None => Err(format!(
+                "no verifier for the problem category of {}",
+                problem.name
+            )),
+        };
+        if let Err(err) = verified {
+            let _ = writeln!(
+                report,
+                "ERROR: The solution doesn't verify, not submitting it: {err}"
+            );
+            return report;
+        }
+
+        let _reservation = Reservation::new(&best_solution_path);
+        if let Some(best_metadata) = best_metadata(&best_solution_path) {
+            let _ = writeln!(
+                report,
+                "Best solution: {} (solver: {})",
+                best_metadata.score, best_metadata.solver_spec
+            );
+
+            if solution.score < best_metadata.score {
+                let _ = writeln!(report, "New best solution: {}", metadata.score);
+                let _ = writeln!(report, "!!! WE ARE WINNING SON !!!");
+            } else {
+                let _ = writeln!(
+                    report,
+                    "Current solution (not better): {} >= {}",
+                    metadata.score, best_metadata.score
+                );
+                return report;
             }
+        } else {
+            let _ = writeln!(report, "First solution: {}", metadata.score);
+        }
 
-            if new_best {
-                if let Some(resp) = crate::comms::send_encoded(solution.text.clone()) {
-                    crate::print_response(resp, false, true);
-                } else {
-                    eprintln!(
-                        "ERROR: Failed to send solution to server, not updating best solution"
+        // the best solution is only replaced once the server has it
+        match crate::comms::send_encoded(solution.text.clone()) {
+            Ok(resp) => {
+                let response = crate::response_text(resp);
+                let _ = writeln!(report, "{response}");
+                if !submit::is_accepted(&response) {
+                    let _ = writeln!(report, "ERROR: The server rejected the solution");
+                    return report;
+                }
+                metadata.response = Some(response);
+                // submit --flush may have written a better one meanwhile
+                let improves = match best_metadata(&best_solution_path) {
+                    Some(best) => solution.score < best.score,
+                    None => true,
+                };
+                if improves {
+                    write_best(
+                        &best_solution_path,
+                        &solution.text,
+                        &serde_json::to_string(&metadata).unwrap(),
                     );
                 }
-
-                std::fs::copy(&current_solution_path, &best_solution_path)
-                    .expect("Failed to copy the current solution to the best solution");
-                std::fs::copy(
-                    current_solution_path.with_extension("meta"),
-                    best_solution_path.with_extension("meta"),
-                )
-                .expect("Failed to copy the current solution metadata to the best solution");
+            }
+            Err(err) => {
+                let submission = Submission {
+                    category: self.category.clone(),
+                    problem: problem.name.clone(),
+                    metadata: metadata.clone(),
+                    payload: solution.text.clone(),
+                };
+                let outbox = submit::queue(&submission);
+                let _ = writeln!(
+                    report,
+                    "ERROR: Failed to submit the solution ({err}), queued it in {}",
+                    outbox.display()
+                );
             }
         }
+        report
+    }
+
+    // keeps every attempt in solutions/history/<problem>, named after its timestamp
+    fn archive(&self, problem: &Problem, metadata: &SolutionMetadata, solution: &Solution) {
+        let dir = self.history.join(&problem.name);
+        std::fs::create_dir_all(&dir).expect("Failed to create the history directory");
+        let path = history_path(&dir, metadata.timestamp);
+        solution.save(&path);
+        std::fs::write(
+            path.with_extension("meta"),
+            serde_json::to_string(&metadata).unwrap(),
+        )
+        .expect("Failed to write the history metadata");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::mpsc,
+        time::Duration,
+    };
+
+    use super::{history_path, Problem, Reservation, SolutionDirs};
+
+    #[test]
+    fn test_reservation() {
+        let path = PathBuf::from("best/test/reservation.icfp");
+        let first = Reservation::new(&path);
+        let (sent, received) = mpsc::channel();
+        let waiting = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let _second = Reservation::new(&path);
+                sent.send(()).unwrap();
+            }
+        });
+        // the second solution waits for the first one to be sent
+        assert!(received.recv_timeout(Duration::from_millis(200)).is_err());
+        // solutions of other problems don't
+        drop(Reservation::new(Path::new("best/test/other.icfp")));
+        drop(first);
+        received.recv_timeout(Duration::from_secs(10)).unwrap();
+        waiting.join().unwrap();
+    }
+
+    #[test]
+    fn test_history_path() {
+        let dir = std::env::temp_dir().join(format!("history_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = history_path(&dir, 1720000000);
+        std::fs::write(&first, "").unwrap();
+        let second = history_path(&dir, 1720000000);
+        std::fs::write(&second, "").unwrap();
+        let third = history_path(&dir, 1720000000);
+        let later = history_path(&dir, 1720000001);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(first, dir.join("1720000000.icfp"));
+        assert_eq!(second, dir.join("1720000000-1.icfp"));
+        assert_eq!(third, dir.join("1720000000-2.icfp"));
+        assert_eq!(later, dir.join("1720000001.icfp"));
+    }
+
+    #[test]
+    fn test_load_best() {
+        let dir = std::env::temp_dir().join(format!("load_best_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dirs = SolutionDirs {
+            category: "lambdaman".to_owned(),
+            current: dir.clone(),
+            best: dir.clone(),
+            history: dir.clone(),
+            git_commit: None,
+            verifier: None,
+        };
+        let load = |name: &str| dirs.load_best(&Problem::new(dir.join(name), name.to_owned()));
+        std::fs::write(dir.join("lambdaman1.icfp"), "S3/,6%},!-\"$!-!.^}\n").unwrap();
+        std::fs::write(
+            dir.join("lambdaman1.meta"),
+            r#"{"solver_spec":"lm:walk","score":42}"#,
+        )
+        .unwrap();
+        // hand written solutions are scored by their length
+        std::fs::write(dir.join("lambdaman2.icfp"), "SLLLDDRRR").unwrap();
+        std::fs::write(dir.join("lambdaman3.icfp"), "B$").unwrap();
+
+        let first = load("lambdaman1").unwrap();
+        let second = load("lambdaman2").unwrap();
+        let invalid = load("lambdaman3");
+        let missing = load("lambdaman4");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            (first.text.as_str(), first.score),
+            ("S3/,6%},!-\"$!-!.^}", 42)
+        );
+        assert_eq!((second.text.as_str(), second.score), ("SLLLDDRRR", 9));
+        assert!(invalid.is_none());
+        assert!(missing.is_none());
     }
 }
@@ -1,22 +1,39 @@
+mod budget;
 mod chain;
 mod command;
+mod portfolio;
 mod problem;
+mod repeat;
 mod solution;
 mod solver;
+mod spec;
+mod status;
+mod submit;
+mod tune;
+mod verifier;
 
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use spec::SolverSpec;
+use std::{collections::HashMap, fmt::Write};
 
+pub use budget::{parse_duration, Budget};
 pub use chain::Chain;
 pub use command::SolveCommand;
+pub use portfolio::Portfolio;
 pub use problem::Problem;
+pub use repeat::Repeat;
 pub use solution::Solution;
-pub use solver::{Parameter, Solver};
+pub use solver::{Parameter, ParameterInfo, ParameterKind, Solver};
+pub use status::StatusCommand;
+pub use submit::SubmitCommand;
+pub use tune::TuneCommand;
+pub use verifier::{verify_solution, Verifier};
 
 use crate::{
     compression::dict_compression::LambdamanTreeWalkLzCompressed,
-    lambdaman_alt::{BlindAgentSolver, LambdamanTreeWalk, RandomStepAgent},
-    spaceship::{SpaceshipGreedy, SpaceshipOneByOne},
+    lambdaman_alt::{BlindAgentSolver, LambdamanTreeWalk, LambdamanVerifier, RandomStepAgent},
+    spaceship::{SpaceshipGreedy, SpaceshipOneByOne, SpaceshipVerifier},
+    three_d::ThreeDVerifier,
 };
 
 static SOLVERS: Lazy<HashMap<&'static str, Box<dyn Solver>>> = Lazy::new(|| {
@@ -36,52 +53,122 @@ static SOLVERS: Lazy<HashMap<&'static str, Box<dyn Solver>>> = Lazy::new(|| {
     solvers
 });
 
-pub fn create_solver(solver_spec: &str) -> Box<dyn Solver> {
-    if solver_spec.contains('+') {
-        let mut solvers = solver_spec.split('+').map(create_individual_solver);
-        let chain = Box::new(Chain::new(solvers.next().unwrap(), solvers.next().unwrap()));
-        solvers.fold(chain, |chain, next| Box::new(Chain::new(chain, next)))
-    } else {
-        create_individual_solver(solver_spec)
-    }
+// the verifiers of each problem category
+static VERIFIERS: Lazy<HashMap<&'static str, Box<dyn Verifier>>> = Lazy::new(|| {
+    let mut verifiers: HashMap<&'static str, Box<dyn Verifier>> = HashMap::new();
+    verifiers.insert("lambdaman", Box::<LambdamanVerifier>::default());
+    verifiers.insert("spaceship", Box::<SpaceshipVerifier>::default());
+    verifiers.insert("3d", Box::<ThreeDVerifier>::default());
+    verifiers
+});
+
+pub fn create_verifier(category: &str) -> Option<&'static dyn Verifier> {
+    VERIFIERS.get(category).map(|verifier| verifier.as_ref())
+}
+
+// parameter values to use instead of the schema defaults, by solver name
+pub type SolverDefaults = HashMap<String, HashMap<String, Parameter>>;
+
+pub fn create_solver(solver_spec: &str) -> Result<Box<dyn Solver>, String> {
+    create_solver_with_defaults(solver_spec, &SolverDefaults::new())
+}
+
+pub fn create_solver_with_defaults(
+    solver_spec: &str,
+    defaults: &SolverDefaults,
+) -> Result<Box<dyn Solver>, String> {
+    build_solver(&spec::parse(solver_spec)?, defaults)
 }
 
-fn create_individual_solver(solver_name: &str) -> Box<dyn Solver> {
-    let (solver_name, parameters) = if solver_name.contains('{') {
-        assert!(
-            solver_name.ends_with('}'),
-            "Invalid solver name {}",
-            solver_name
-        );
-        let (solver_name, rest) = solver_name.split_at(solver_name.find('{').unwrap());
-        let rest = &rest[1..rest.len() - 1];
-        let parameters = rest
-            .split(',')
-            .map(|s| {
-                let (name, value) = s.split_at(
-                    s.find('=')
-                        .expect("Invalid parameter format, expected `name=value`"),
-                );
-                (
-                    name.to_owned(),
-                    if value.chars().nth(1).unwrap().is_ascii_digit() {
-                        Parameter::Int(
-                            value[1..]
-                                .parse::<i64>()
-                                .expect("Failed to parse solver parameter as i64"),
-                        )
-                    } else {
-                        Parameter::String(value[1..].to_owned())
-                    },
-                )
+fn build_solver(spec: &SolverSpec, defaults: &SolverDefaults) -> Result<Box<dyn Solver>, String> {
+    let build = |spec| build_solver(spec, defaults);
+    match spec {
+        SolverSpec::Solver { name, parameters } => {
+            let mut solver = SOLVERS
+                .get(name.as_str())
+                .ok_or_else(|| format!("unknown solver `{name}`, see solve --list-solvers"))?
+                .clone();
+            // the parameters of the spec win over the defaults
+            let mut given = defaults.get(name).cloned().unwrap_or_default();
+            given.extend(parameters.clone());
+            let parameters = resolve_parameters(name, &solver.parameter_schema(), &given)?;
+            solver.set_parameters(parameters);
+            Ok(solver)
+        }
+        SolverSpec::Portfolio(specs) => Ok(Box::new(Portfolio::new(
+            specs.iter().map(build).collect::<Result<_, _>>()?,
+        ))),
+        SolverSpec::Repeat(times, spec) => Ok(Box::new(Repeat::new(*times, build(spec)?))),
+        SolverSpec::Chain(specs) => {
+            let mut solvers = specs.iter().map(build);
+            let first = solvers.next().unwrap()?;
+            solvers.try_fold(first, |chain, next| {
+                Ok(Box::new(Chain::new(chain, next?)) as Box<dyn Solver>)
             })
-            .collect::<HashMap<_, _>>();
-        (solver_name, parameters)
-    } else {
-        (solver_name, HashMap::new())
-    };
+        }
+    }
+}
+
+// checks the given parameters against the schema of the solver, and adds the defaults
+fn resolve_parameters(
+    solver_name: &str,
+    schema: &[ParameterInfo],
+    parameters: &HashMap<String, Parameter>,
+) -> Result<HashMap<String, Parameter>, String> {
+    if let Some(name) = parameters
+        .keys()
+        .find(|name| !schema.iter().any(|info| info.name == name.as_str()))
+    {
+        let expected = if schema.is_empty() {
+            "it doesn't take any".to_owned()
+        } else {
+            let names: Vec<&str> = schema.iter().map(|info| info.name).collect();
+            format!("expected {}", names.join(", "))
+        };
+        return Err(format!(
+            "unknown parameter `{name}` for solver `{solver_name}`, {expected}"
+        ));
+    }
 
-    let mut solver: Box<dyn Solver> = SOLVERS.get(solver_name).expect("Unknown solver").clone();
-    solver.set_parameters(parameters);
-    solver
+    schema
+        .iter()
+        .map(|info| {
+            let value = match parameters.get(info.name) {
+                None => info.default.clone(),
+                Some(Parameter::Int(value)) if info.kind == ParameterKind::Float => {
+                    Parameter::Float(*value as f64)
+                }
+                // booleans used to be given as 1 or 0
+                Some(Parameter::Int(value @ (0 | 1))) if info.kind == ParameterKind::Bool => {
+                    Parameter::Bool(*value == 1)
+                }
+                Some(value) if value.kind() == info.kind => value.clone(),
+                Some(value) => {
+                    return Err(format!(
+                    "parameter `{}` of solver `{solver_name}` takes values of type {}, not {value}",
+                    info.name, info.kind
+                ))
+                }
+            };
+            Ok((info.name.to_owned(), value))
+        })
+        .collect()
+}
+
+// the registered solvers and their parameters, for solve --list-solvers
+pub fn list_solvers() -> String {
+    let mut names: Vec<&&str> = SOLVERS.keys().collect();
+    names.sort();
+    let mut list = String::new();
+    for name in names {
+        let _ = writeln!(list, "{name}");
+        for info in SOLVERS[name].parameter_schema() {
+            let _ = writeln!(
+                list,
+                "  {}: {} = {}\n      {}",
+                info.name, info.kind, info.default, info.doc
+            );
+        }
+    }
+    list
 }
@@ -0,0 +1,93 @@
+use std::{collections::HashMap, sync::mpsc};
+
+use super::{Budget, Parameter, Problem, Solution, Solver};
+
+// runs several solvers on the same problem, each on its own thread, and keeps the best solution
+#[derive(Clone)]
+pub struct Portfolio {
+    solvers: Vec<Box<dyn Solver>>,
+}
+
+// solutions can't be sent to other threads, their code is sent instead
+struct SentSolution {
+    text: String,
+    score: u64,
+    seed: Option<u64>,
+}
+
+impl SentSolution {
+    fn new(solution: &Solution) -> Self {
+        Self {
+            text: solution.text.clone(),
+            score: solution.score,
+            seed: solution.seed,
+        }
+    }
+
+    fn solution(self) -> Solution {
+        let solution = Solution::parse(&self.text, self.score)
+            .expect("Failed to parse a solution sent by another thread");
+        Solution {
+            seed: self.seed,
+            ..solution
+        }
+    }
+}
+
+impl Solver for Portfolio {
+    fn name(&self) -> String {
+        let names: Vec<String> = self.solvers.iter().map(|solver| solver.name()).collect();
+        names.join("|")
+    }
+
+    fn parameters(&self) -> HashMap<String, Parameter> {
+        let mut parameters = HashMap::new();
+        for solver in &self.solvers {
+            parameters.extend(solver.parameters());
+        }
+        parameters
+    }
+
+    fn initialize(&mut self, problem: Problem, solution: Option<Solution>) {
+        for solver in &mut self.solvers {
+            solver.initialize(problem.clone(), solution.clone());
+        }
+    }
+
+    fn solve(&mut self, budget: &Budget) -> Option<Solution> {
+        let (deadline, cancelled) = (budget.deadline(), budget.cancelled());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::scope(|scope| {
+            for solver in &mut self.solvers {
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    let report = |solution: &Solution| {
+                        let _ = sender.send(SentSolution::new(solution));
+                    };
+                    let budget = Budget::until(deadline, cancelled, &report);
+                    if let Some(solution) = solver.solve(&budget) {
+                        report(&solution);
+                    }
+                });
+            }
+            drop(sender);
+
+            // the reports of all the solvers are forwarded, until they are all done
+            let mut best: Option<Solution> = None;
+            for sent in receiver {
+                let solution = sent.solution();
+                budget.report(&solution);
+                if best.as_ref().is_none_or(|best| solution.score < best.score) {
+                    best = Some(solution);
+                }
+            }
+            best
+        })
+    }
+}
+
+impl Portfolio {
+    pub fn new(solvers: Vec<Box<dyn Solver>>) -> Self {
+        Portfolio { solvers }
+    }
+}
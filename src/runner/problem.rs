@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Default)]
 pub struct Problem {
@@ -14,4 +14,18 @@ impl Problem {
     pub fn load(&self) -> String {
         std::fs::read_to_string(&self.path).expect("Failed to read the problem file")
     }
+
+    // the problems of a category directory, the files without an extension
+    pub fn list(problem_dir: &Path) -> Vec<Problem> {
+        std::fs::read_dir(problem_dir)
+            .expect("Failed to read the problem directory")
+            .map(|entry| entry.expect("Failed to read a problem directory entry"))
+            .map(|entry| {
+                let problem_name = entry.file_name().to_string_lossy().to_string();
+                let problem_path = entry.path();
+                Problem::new(problem_path, problem_name)
+            })
+            .filter(|p| p.path.extension().is_none())
+            .collect()
+    }
 }
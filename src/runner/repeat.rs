@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+use super::{Budget, Parameter, Problem, Solution, Solver};
+
+// runs a randomized solver several times, and keeps the best solution
+#[derive(Clone)]
+pub struct Repeat {
+    solver: Box<dyn Solver>,
+    times: usize,
+}
+
+impl Solver for Repeat {
+    fn name(&self) -> String {
+        format!("repeat({}, {})", self.times, self.solver.name())
+    }
+
+    fn parameters(&self) -> HashMap<String, Parameter> {
+        self.solver.parameters()
+    }
+
+    fn initialize(&mut self, problem: Problem, solution: Option<Solution>) {
+        self.solver.initialize(problem, solution);
+    }
+
+    fn solve(&mut self, budget: &Budget) -> Option<Solution> {
+        let mut best: Option<Solution> = None;
+        for _ in 0..self.times {
+            if budget.is_over() {
+                break;
+            }
+            // each run starts from the initialized solver
+            let mut solver = dyn_clone::clone_box(self.solver.as_ref());
+            let Some(solution) = solver.solve(budget) else {
+                continue;
+            };
+            if best.as_ref().is_none_or(|best| solution.score < best.score) {
+                best = Some(solution);
+            }
+        }
+        best
+    }
+}
+
+impl Repeat {
+    pub fn new(times: usize, solver: Box<dyn Solver>) -> Self {
+        Repeat { solver, times }
+    }
+}
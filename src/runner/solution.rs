@@ -1,12 +1,16 @@
 use std::{path::Path, rc::Rc};
 
-use crate::icfp::{serialize_str, Node};
+use logos::Logos;
+
+use crate::icfp::{parse, serialize_str, Node, Token};
 
 #[derive(Debug, Clone)]
 pub struct Solution {
     pub icfp_code: Rc<Node>,
     pub text: String,
     pub score: u64,
+    // the seed of randomized solvers, to reproduce the solution
+    pub seed: Option<u64>,
 }
 
 impl Solution {
@@ -15,9 +19,26 @@ impl Solution {
             icfp_code: icfp_code.clone(),
             text: serialize_str(icfp_code),
             score,
+            seed: None,
         }
     }
 
+    // reads a solution back from its ICFP code, None if the code is invalid
+    pub fn parse(text: &str, score: u64) -> Option<Self> {
+        let icfp_code = parse(&mut Token::lexer(text.trim())).ok()?;
+        Some(Self {
+            icfp_code,
+            text: text.trim().to_owned(),
+            score,
+            seed: None,
+        })
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
     pub fn save(&self, current_solution_path: &Path) {
         std::fs::write(current_solution_path, &self.text)
             .expect("Failed to write the solution file");
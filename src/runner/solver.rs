@@ -1,28 +1,100 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Display};
 
 use dyn_clone::DynClone;
+use serde::{Deserialize, Serialize};
 
-use super::{Problem, Solution};
+use super::{Budget, Problem, Solution};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
 pub enum Parameter {
     Int(i64),
+    Float(f64),
+    Bool(bool),
     String(String),
 }
 
+impl Parameter {
+    pub fn kind(&self) -> ParameterKind {
+        match self {
+            Parameter::Int(_) => ParameterKind::Int,
+            Parameter::Float(_) => ParameterKind::Float,
+            Parameter::Bool(_) => ParameterKind::Bool,
+            Parameter::String(_) => ParameterKind::String,
+        }
+    }
+}
+
+impl Display for Parameter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Parameter::Int(value) => write!(f, "{value}"),
+            Parameter::Float(value) => write!(f, "{value:?}"),
+            Parameter::Bool(value) => write!(f, "{value}"),
+            Parameter::String(value) => write!(f, "{value:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParameterKind {
+    Int,
+    Float,
+    Bool,
+    String,
+}
+
+impl Display for ParameterKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParameterKind::Int => write!(f, "int"),
+            ParameterKind::Float => write!(f, "float"),
+            ParameterKind::Bool => write!(f, "bool"),
+            ParameterKind::String => write!(f, "string"),
+        }
+    }
+}
+
+// a parameter a solver accepts
+#[derive(Debug, Clone)]
+pub struct ParameterInfo {
+    pub name: &'static str,
+    pub kind: ParameterKind,
+    pub default: Parameter,
+    pub doc: &'static str,
+}
+
+impl ParameterInfo {
+    pub fn new(name: &'static str, default: Parameter, doc: &'static str) -> Self {
+        Self {
+            name,
+            kind: default.kind(),
+            default,
+            doc,
+        }
+    }
+}
+
 pub trait Solver: DynClone + Sync + Send {
     fn name(&self) -> String;
 
-    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
-        assert!(
-            parameters.is_empty(),
-            "Solver {} doesn't accept parameters",
-            self.name()
-        );
+    // the parameters the solver accepts
+    fn parameter_schema(&self) -> Vec<ParameterInfo> {
+        vec![]
     }
+
+    // called with a value for every parameter of the schema, of the right kind
+    fn set_parameters(&mut self, _parameters: HashMap<String, Parameter>) {}
+
+    // the values the solver runs with, defaults included
+    fn parameters(&self) -> HashMap<String, Parameter> {
+        HashMap::new()
+    }
+
     fn initialize(&mut self, problem: Problem, solution: Option<Solution>);
 
-    fn solve(&mut self) -> Solution;
+    // the best solution found before the budget is over, if any
+    fn solve(&mut self, budget: &Budget) -> Option<Solution>;
 }
 
 dyn_clone::clone_trait_object!(Solver);
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use nom::{
+    branch::alt,
+    bytes::complete::{escaped_transform, is_not, tag, take_while1},
+    character::complete::{char, digit1, multispace0},
+    combinator::{all_consuming, cut, map, map_res, not, opt, peek, recognize, value},
+    error::{context, VerboseError, VerboseErrorKind},
+    multi::{many0, separated_list1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+    Finish, IResult,
+};
+
+use super::Parameter;
+
+// a parsed solver spec, such as `lm:tree_walk+ss:greedy{max_possible_states=1000}`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SolverSpec {
+    Solver {
+        name: String,
+        parameters: HashMap<String, Parameter>,
+    },
+    // each solver improves on the solution of the previous one
+    Chain(Vec<SolverSpec>),
+    // `a|b`: all the solvers run, the best solution is kept
+    Portfolio(Vec<SolverSpec>),
+    // `repeat(n, a)`: the solver runs n times, the best solution is kept
+    Repeat(usize, Box<SolverSpec>),
+}
+
+type SpecResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
+
+fn ws<'a, T>(
+    parser: impl FnMut(&'a str) -> SpecResult<'a, T>,
+) -> impl FnMut(&'a str) -> SpecResult<'a, T> {
+    delimited(multispace0, parser, multispace0)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == ':'
+}
+
+fn name(input: &str) -> SpecResult<'_, &str> {
+    context("solver name", take_while1(is_name_char))(input)
+}
+
+fn quoted_string(input: &str) -> SpecResult<'_, String> {
+    let content = escaped_transform(
+        is_not("\\\""),
+        '\\',
+        alt((value("\\", char('\\')), value("\"", char('"')))),
+    );
+    preceded(
+        char('"'),
+        cut(terminated(
+            map(opt(content), Option::unwrap_or_default),
+            context("closing quote", char('"')),
+        )),
+    )(input)
+}
+
+// a value which isn't the start of a bare string
+fn whole<'a, T>(
+    parser: impl FnMut(&'a str) -> SpecResult<'a, T>,
+) -> impl FnMut(&'a str) -> SpecResult<'a, T> {
+    terminated(parser, not(take_while1(is_bare_char)))
+}
+
+// bare strings are unquoted parameter values
+fn is_bare_char(c: char) -> bool {
+    is_name_char(c) || c == '-' || c == '.'
+}
+
+fn parameter_value(input: &str) -> SpecResult<'_, Parameter> {
+    let number = recognize(tuple((
+        opt(char('-')),
+        digit1,
+        opt(pair(char('.'), digit1)),
+    )));
+    context(
+        "parameter value",
+        alt((
+            map(quoted_string, Parameter::String),
+            whole(value(Parameter::Bool(true), tag("true"))),
+            whole(value(Parameter::Bool(false), tag("false"))),
+            map_res(whole(number), |number: &str| {
+                if number.contains('.') {
+                    number.parse().map(Parameter::Float).map_err(|_| ())
+                } else {
+                    number.parse().map(Parameter::Int).map_err(|_| ())
+                }
+            }),
+            map(take_while1(is_bare_char), |s: &str| {
+                Parameter::String(s.to_owned())
+            }),
+        )),
+    )(input)
+}
+
+fn parameters(input: &str) -> SpecResult<'_, HashMap<String, Parameter>> {
+    let parameter = separated_pair(
+        ws(context("parameter name", take_while1(is_name_char))),
+        cut(context("`=`", char('='))),
+        cut(ws(parameter_value)),
+    );
+    // a parameter can't be given twice
+    let distinct = map_res(
+        delimited(
+            char('{'),
+            cut(separated_list1(char(','), parameter)),
+            cut(context("`}`", char('}'))),
+        ),
+        |parameters| {
+            let mut map = HashMap::new();
+            for (name, value) in parameters {
+                if map.insert(name.to_owned(), value).is_some() {
+                    return Err(());
+                }
+            }
+            Ok(map)
+        },
+    );
+    preceded(
+        peek(char('{')),
+        cut(context("distinct parameter names", distinct)),
+    )(input)
+}
+
+fn repeat(input: &str) -> SpecResult<'_, SolverSpec> {
+    let times = context("a number of runs", map_res(digit1, str::parse));
+    map(
+        preceded(
+            pair(tag("repeat"), ws(char('('))),
+            cut(tuple((
+                terminated(times, ws(context("`,`", char(',')))),
+                portfolio,
+                context("`)`", char(')')),
+            ))),
+        ),
+        |(times, spec, _)| SolverSpec::Repeat(times, Box::new(spec)),
+    )(input)
+}
+
+fn solver(input: &str) -> SpecResult<'_, SolverSpec> {
+    alt((
+        delimited(char('('), cut(portfolio), cut(context("`)`", char(')')))),
+        repeat,
+        map(pair(name, opt(parameters)), |(name, parameters)| {
+            SolverSpec::Solver {
+                name: name.to_owned(),
+                parameters: parameters.unwrap_or_default(),
+            }
+        }),
+    ))(input)
+}
+
+// `a op b op c`, or just `a`
+fn list<'a>(
+    op: char,
+    parser: fn(&'a str) -> SpecResult<'a, SolverSpec>,
+    variant: fn(Vec<SolverSpec>) -> SolverSpec,
+) -> impl FnMut(&'a str) -> SpecResult<'a, SolverSpec> {
+    let rest = many0(preceded(char(op), cut(parser)));
+    map(pair(parser, rest), move |(first, rest)| {
+        if rest.is_empty() {
+            first
+        } else {
+            variant(std::iter::once(first).chain(rest).collect())
+        }
+    })
+}
+
+fn chain(input: &str) -> SpecResult<'_, SolverSpec> {
+    list('+', |input| ws(solver)(input), SolverSpec::Chain)(input)
+}
+
+// chains bind tighter: `a+b|c` runs `a+b` and `c`
+fn portfolio(input: &str) -> SpecResult<'_, SolverSpec> {
+    list('|', chain, SolverSpec::Portfolio)(input)
+}
+
+// describes where parsing stopped and what was expected there
+fn error_message(what: &str, input: &str, err: VerboseError<&str>) -> String {
+    // the innermost error is where parsing stopped, the innermost context is what was expected
+    let (rest, _) = err.errors[0];
+    let expected = err
+        .errors
+        .iter()
+        .find_map(|(_, kind)| match kind {
+            VerboseErrorKind::Context(context) => Some(*context),
+            _ => None,
+        })
+        .unwrap_or("the end of the input");
+    let column = input.len() - rest.len();
+    let found = match rest.chars().next() {
+        Some(c) => format!("{c:?}"),
+        None => "the end of the input".to_owned(),
+    };
+    format!(
+        "invalid {what}, expected {expected} but found {found}\n  {input}\n  {}^",
+        " ".repeat(input[..column].chars().count())
+    )
+}
+
+pub fn parse(spec: &str) -> Result<SolverSpec, String> {
+    all_consuming(portfolio)(spec)
+        .finish()
+        .map(|(_, spec)| spec)
+        .map_err(|err| error_message("solver spec", spec, err))
+}
+
+// comma separated parameter values, as written in specs
+pub fn parse_values(values: &str) -> Result<Vec<Parameter>, String> {
+    all_consuming(separated_list1(char(','), ws(parameter_value)))(values)
+        .finish()
+        .map(|(_, values)| values)
+        .map_err(|err| error_message("parameter values", values, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solver(name: &str, parameters: &[(&str, Parameter)]) -> SolverSpec {
+        SolverSpec::Solver {
+            name: name.to_owned(),
+            parameters: parameters
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("lm:tree_walk"), Ok(solver("lm:tree_walk", &[])));
+        assert_eq!(
+            parse(r#"x{a=1, b=-2.5,c=true, d="1,}\"", e=word}"#),
+            Ok(solver(
+                "x",
+                &[
+                    ("a", Parameter::Int(1)),
+                    ("b", Parameter::Float(-2.5)),
+                    ("c", Parameter::Bool(true)),
+                    ("d", Parameter::String("1,}\"".to_owned())),
+                    ("e", Parameter::String("word".to_owned())),
+                ]
+            ))
+        );
+        assert_eq!(
+            parse("a + (b{x=truth}+c)"),
+            Ok(SolverSpec::Chain(vec![
+                solver("a", &[]),
+                SolverSpec::Chain(vec![
+                    solver("b", &[("x", Parameter::String("truth".to_owned()))]),
+                    solver("c", &[]),
+                ]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_composition() {
+        assert_eq!(
+            parse("a+b | repeat(3, c{x=1}|d)"),
+            Ok(SolverSpec::Portfolio(vec![
+                SolverSpec::Chain(vec![solver("a", &[]), solver("b", &[])]),
+                SolverSpec::Repeat(
+                    3,
+                    Box::new(SolverSpec::Portfolio(vec![
+                        solver("c", &[("x", Parameter::Int(1))]),
+                        solver("d", &[]),
+                    ]))
+                ),
+            ]))
+        );
+        assert_eq!(
+            parse("(a|b)+repeater"),
+            Ok(SolverSpec::Chain(vec![
+                SolverSpec::Portfolio(vec![solver("a", &[]), solver("b", &[])]),
+                solver("repeater", &[]),
+            ]))
+        );
+        assert!(parse("repeat(x, a)")
+            .unwrap_err()
+            .contains("expected a number of runs"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse("a{x}"),
+            Err("invalid solver spec, expected `=` but found '}'\n  a{x}\n     ^".to_owned())
+        );
+        assert_eq!(
+            parse("a+"),
+            Err("invalid solver spec, expected solver name but found the end of the input\n  a+\n    ^".to_owned())
+        );
+        assert!(parse(r#"a{x="1}"#)
+            .unwrap_err()
+            .contains("expected closing quote"));
+        assert!(parse("(a+b").unwrap_err().contains("expected `)`"));
+        assert_eq!(
+            parse_values(r#"1, "a,b""#),
+            Ok(vec![Parameter::Int(1), Parameter::String("a,b".to_owned())])
+        );
+        assert!(parse("a{x=1,x=2}")
+            .unwrap_err()
+            .contains("expected distinct parameter names"));
+    }
+
+    #[test]
+    fn test_resolve_parameters() {
+        let solver = super::super::create_solver("ss:one_by_one{start_stop=1}").unwrap();
+        assert_eq!(solver.parameters()["start_stop"], Parameter::Bool(true));
+        let solver = super::super::create_solver("ss:one_by_one{start_stop=0}").unwrap();
+        assert_eq!(solver.parameters()["start_stop"], Parameter::Bool(false));
+        let Err(err) = super::super::create_solver("ss:one_by_one{start_stop=2}") else {
+            panic!("2 isn't a bool");
+        };
+        assert!(err.contains("takes values of type"), "{err}");
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use argh::FromArgs;
+use serde::Serialize;
+
+use super::command::best_metadata;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Show the best solution of every problem
+#[argh(subcommand, name = "status")]
+pub struct StatusCommand {
+    #[argh(positional)]
+    /// only show this problem category
+    pub category: Option<String>,
+    #[argh(option, short = 'f', default = "StatusFormat::Text")]
+    /// the output format: text, csv or json
+    pub format: StatusFormat,
+    #[argh(
+        option,
+        short = 't',
+        default = "PathBuf::from(\"solutions/targets.json\")"
+    )]
+    /// a json file mapping problem names to target scores
+    pub targets: PathBuf,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum StatusFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+impl FromStr for StatusFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown format {s:?}, expected text, csv or json")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ProblemStatus {
+    category: String,
+    problem: String,
+    solved: bool,
+    score: Option<u64>,
+    solver_spec: Option<String>,
+    target: Option<u64>,
+    // how far the score is above the target
+    delta: Option<i64>,
+}
+
+// lambdaman2 goes before lambdaman10
+pub fn natural_key(name: &str) -> (&str, u64) {
+    let split = name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (prefix, number) = name.split_at(split);
+    (prefix, number.parse().unwrap_or(0))
+}
+
+// the names of the files of a directory, without their extensions
+fn file_stems(dir: &Path) -> BTreeSet<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return BTreeSet::new();
+    };
+    entries
+        .map(|entry| entry.expect("Failed to read a directory entry").path())
+        .filter(|path| path.is_file())
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+        .collect()
+}
+
+fn problem_status(
+    category: &str,
+    problem: &str,
+    best_dir: &Path,
+    targets: &HashMap<String, u64>,
+) -> ProblemStatus {
+    // hand written solutions may have no metadata, or no .icfp extension
+    let solved = std::fs::read_dir(best_dir).is_ok_and(|mut entries| {
+        entries.any(|entry| {
+            let path = entry.expect("Failed to read a directory entry").path();
+            path.file_stem().is_some_and(|stem| stem == problem)
+                && path.extension().is_none_or(|ext| ext != "meta")
+        })
+    });
+    let metadata = best_metadata(&best_dir.join(problem));
+    let score = metadata.as_ref().map(|metadata| metadata.score);
+    let target = targets.get(problem).copied();
+    ProblemStatus {
+        category: category.to_owned(),
+        problem: problem.to_owned(),
+        solved,
+        score,
+        solver_spec: metadata.map(|metadata| metadata.solver_spec),
+        target,
+        delta: score
+            .zip(target)
+            .map(|(score, target)| score as i64 - target as i64),
+    }
+}
+
+fn or_empty<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(T::to_string).unwrap_or_default()
+}
+
+impl StatusCommand {
+    pub fn run(&self) {
+        let current_dir = std::env::current_dir().expect("Failed to get the current directory");
+        let targets: HashMap<String, u64> = match std::fs::read_to_string(&self.targets) {
+            Ok(text) => serde_json::from_str(&text).expect("Failed to parse the target scores"),
+            Err(_) => HashMap::new(),
+        };
+
+        let mut categories: Vec<String> = std::fs::read_dir(current_dir.join("problems"))
+            .expect("Failed to read the problems directory")
+            .map(|entry| entry.expect("Failed to read a directory entry").path())
+            .filter(|path| path.is_dir())
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .filter(|category| self.category.as_ref().is_none_or(|only| only == category))
+            .collect();
+        categories.sort();
+
+        let mut rows = vec![];
+        for category in categories {
+            let best_dir = current_dir.join("solutions").join("best").join(&category);
+            let mut problems: Vec<String> =
+                file_stems(&current_dir.join("problems").join(&category))
+                    .into_iter()
+                    .collect();
+            problems.sort_by(|a, b| natural_key(a).cmp(&natural_key(b)));
+            for problem in problems {
+                rows.push(problem_status(&category, &problem, &best_dir, &targets));
+            }
+        }
+
+        match self.format {
+            StatusFormat::Text => print!("{}", text_table(&rows)),
+            StatusFormat::Csv => print!("{}", csv_table(&rows)),
+            StatusFormat::Json => println!("{}", serde_json::to_string_pretty(&rows).unwrap()),
+        }
+    }
+}
+
+fn columns(row: &ProblemStatus) -> [String; 7] {
+    [
+        row.category.clone(),
+        row.problem.clone(),
+        if row.solved { "yes" } else { "no" }.to_owned(),
+        or_empty(&row.score),
+        or_empty(&row.solver_spec),
+        or_empty(&row.target),
+        or_empty(&row.delta),
+    ]
+}
+
+const HEADER: [&str; 7] = [
+    "category", "problem", "solved", "score", "solver", "target", "delta",
+];
+
+// pads the columns of the lines to the width of their longest cell
+pub fn align_columns(lines: &[Vec<String>]) -> String {
+    let mut widths = vec![0; lines.iter().map(Vec::len).max().unwrap_or(0)];
+    for line in lines {
+        for (width, column) in widths.iter_mut().zip(line) {
+            *width = (*width).max(column.len());
+        }
+    }
+
+    let mut table = String::new();
+    for line in lines {
+        let line: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .map(|(column, width)| format!("{column:width$}"))
+            .collect();
+        let _ = writeln!(table, "{}", line.join("  ").trim_end());
+    }
+    table
+}
+
+fn text_table(rows: &[ProblemStatus]) -> String {
+    let lines: Vec<Vec<String>> = std::iter::once(HEADER.map(str::to_owned))
+        .chain(rows.iter().map(columns))
+        .map(Vec::from)
+        .collect();
+    let mut table = align_columns(&lines);
+    let solved = rows.iter().filter(|row| row.solved).count();
+    let _ = writeln!(table, "\n{solved}/{} problems solved", rows.len());
+    table
+}
+
+fn csv_table(rows: &[ProblemStatus]) -> String {
+    // solver specs may contain commas
+    let quote = |column: &String| {
+        if column.contains([',', '"']) {
+            format!("\"{}\"", column.replace('"', "\"\""))
+        } else {
+            column.clone()
+        }
+    };
+    let mut table = HEADER.join(",") + "\n";
+    for row in rows {
+        let line: Vec<String> = columns(row).iter().map(quote).collect();
+        let _ = writeln!(table, "{}", line.join(","));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{csv_table, natural_key, problem_status, text_table};
+
+    #[test]
+    fn test_problem_status() {
+        let dir = std::env::temp_dir().join(format!("status_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lambdaman1.icfp"), "S").unwrap();
+        std::fs::write(
+            dir.join("lambdaman1.meta"),
+            r#"{"solver_spec":"lm:walk(seed=1,steps=9)","score":120,"timestamp":1720000000,"seed":1}"#,
+        )
+        .unwrap();
+        // older metadata only has the solver spec and the score
+        std::fs::write(dir.join("lambdaman2.icfp"), "S").unwrap();
+        std::fs::write(
+            dir.join("lambdaman2.meta"),
+            r#"{"solver_spec":"lm:greedy","score":90}"#,
+        )
+        .unwrap();
+        // hand written, with no metadata
+        std::fs::write(dir.join("lambdaman3.txt"), "S").unwrap();
+
+        let targets = HashMap::from([
+            ("lambdaman1".to_owned(), 100),
+            ("lambdaman4".to_owned(), 50),
+        ]);
+        let rows: Vec<_> = ["lambdaman1", "lambdaman2", "lambdaman3", "lambdaman4"]
+            .iter()
+            .map(|problem| problem_status("lambdaman", problem, &dir, &targets))
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let summary: Vec<_> = rows
+            .iter()
+            .map(|row| (row.solved, row.score, row.target, row.delta))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (true, Some(120), Some(100), Some(20)),
+                (true, Some(90), None, None),
+                (true, None, None, None),
+                (false, None, Some(50), None),
+            ]
+        );
+        assert_eq!(rows[1].solver_spec.as_deref(), Some("lm:greedy"));
+
+        let text = text_table(&rows);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "category   problem     solved  score  solver                   target  delta"
+        );
+        assert_eq!(
+            lines[1],
+            "lambdaman  lambdaman1  yes     120    lm:walk(seed=1,steps=9)  100     20"
+        );
+        assert_eq!(
+            lines[4],
+            "lambdaman  lambdaman4  no                                      50"
+        );
+        assert!(text.ends_with("\n3/4 problems solved\n"));
+
+        let csv = csv_table(&rows);
+        assert_eq!(
+            csv.lines().nth(1),
+            Some("lambdaman,lambdaman1,yes,120,\"lm:walk(seed=1,steps=9)\",100,20")
+        );
+        assert_eq!(csv.lines().nth(3), Some("lambdaman,lambdaman3,yes,,,,"));
+    }
+
+    #[test]
+    fn test_natural_key() {
+        let mut names = vec!["lambdaman10", "spaceship2", "lambdaman2", "lambdaman"];
+        names.sort_by(|a, b| natural_key(a).cmp(&natural_key(b)));
+        assert_eq!(
+            names,
+            ["lambdaman", "lambdaman2", "lambdaman10", "spaceship2"]
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use argh::FromArgs;
+use serde::{Deserialize, Serialize};
+
+use super::command::{best_metadata, write_best, SolutionMetadata};
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List the solutions waiting to be submitted
+#[argh(subcommand, name = "submit")]
+pub struct SubmitCommand {
+    #[argh(switch)]
+    /// submit the queued solutions, keeping those which fail
+    pub flush: bool,
+}
+
+// a solution which couldn't be sent to the server, kept until it is flushed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Submission {
+    pub category: String,
+    pub problem: String,
+    #[serde(flatten)]
+    pub metadata: SolutionMetadata,
+    pub payload: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SubmissionResponse {
+    category: String,
+    problem: String,
+    solver_spec: String,
+    score: u64,
+    response: String,
+}
+
+fn solutions_dir() -> PathBuf {
+    std::env::current_dir()
+        .expect("Failed to get the current directory")
+        .join("solutions")
+}
+
+fn outbox_path() -> PathBuf {
+    solutions_dir().join("outbox.jsonl")
+}
+
+fn append_line(path: &Path, line: &str) {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .expect("Failed to open a log file");
+    // in a single write, so that lines appended concurrently don't interleave
+    file.write_all(format!("{line}\n").as_bytes())
+        .expect("Failed to append to a log file");
+}
+
+// adds the submission to the outbox, and returns where it is
+pub fn queue(submission: &Submission) -> PathBuf {
+    let path = outbox_path();
+    append_line(&path, &serde_json::to_string(submission).unwrap());
+    path
+}
+
+// whether the decoded response of the server accepts the solution
+pub fn is_accepted(response: &str) -> bool {
+    response.starts_with("Correct")
+}
+
+// keeps the decoded responses of the server, one per line
+pub fn record_response(submission: &Submission, response: &str) {
+    let response = SubmissionResponse {
+        category: submission.category.clone(),
+        problem: submission.problem.clone(),
+        solver_spec: submission.metadata.solver_spec.clone(),
+        score: submission.metadata.score,
+        response: response.to_owned(),
+    };
+    append_line(
+        &solutions_dir().join("responses.jsonl"),
+        &serde_json::to_string(&response).unwrap(),
+    );
+}
+
+// the queued submissions, along with the lines which aren't submissions
+fn load_outbox(path: &Path) -> (Vec<Submission>, Vec<String>) {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return (vec![], vec![]);
+    };
+    let mut submissions = vec![];
+    let mut bad_lines = vec![];
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(submission) => submissions.push(submission),
+            Err(err) => {
+                eprintln!("{}:{}: skipping a bad line: {err}", path.display(), i + 1);
+                bad_lines.push(line.to_owned());
+            }
+        }
+    }
+    (submissions, bad_lines)
+}
+
+impl SubmitCommand {
+    pub fn run(&self) {
+        let path = outbox_path();
+        if !self.flush {
+            let (submissions, _) = load_outbox(&path);
+            if submissions.is_empty() {
+                println!("No queued solutions");
+            }
+            for submission in &submissions {
+                println!(
+                    "{}/{}: {} (solver: {})",
+                    submission.category,
+                    submission.problem,
+                    submission.metadata.score,
+                    submission.metadata.solver_spec
+                );
+            }
+            return;
+        }
+
+        // solvers may queue solutions while flushing, they go to a new outbox
+        let mut flushing = path.as_os_str().to_owned();
+        flushing.push(format!(".{}", std::process::id()));
+        let flushing = PathBuf::from(flushing);
+        if std::fs::rename(&path, &flushing).is_err() {
+            println!("No queued solutions");
+            return;
+        }
+        let (submissions, mut remaining) = load_outbox(&flushing);
+
+        for mut submission in submissions {
+            match crate::comms::send_encoded(submission.payload.clone()) {
+                Ok(response) => {
+                    let response = crate::response_text(response);
+                    println!("{}: {response}", submission.problem);
+                    record_response(&submission, &response);
+                    if is_accepted(&response) {
+                        submission.metadata.response = Some(response);
+                        promote(&submission);
+                    }
+                }
+                Err(err) => {
+                    println!(
+                        "{}: failed to submit, keeping it: {err}",
+                        submission.problem
+                    );
+                    remaining.push(serde_json::to_string(&submission).unwrap());
+                }
+            }
+        }
+
+        for line in &remaining {
+            append_line(&path, line);
+        }
+        std::fs::remove_file(&flushing).expect("Failed to remove the flushed outbox");
+        println!("{} entries put back in the outbox", remaining.len());
+    }
+}
+
+// makes a submitted solution the best one, unless a better one was found meanwhile
+fn promote(submission: &Submission) {
+    let mut best_solution_path = solutions_dir()
+        .join("best")
+        .join(&submission.category)
+        .join(&submission.problem);
+    best_solution_path.set_extension("icfp");
+    std::fs::create_dir_all(best_solution_path.parent().unwrap())
+        .expect("Failed to create the best solution directory");
+
+    if let Some(best) = best_metadata(&best_solution_path) {
+        if best.score <= submission.metadata.score {
+            println!(
+                "Best solution kept: {} <= {}",
+                best.score, submission.metadata.score
+            );
+            return;
+        }
+    }
+    write_best(
+        &best_solution_path,
+        &submission.payload,
+        &serde_json::to_string(&submission.metadata).unwrap(),
+    );
+    println!("New best solution: {}", submission.metadata.score);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append_line, is_accepted, load_outbox, SolutionMetadata, Submission};
+
+    #[test]
+    fn test_outbox() {
+        let dir = std::env::temp_dir().join(format!("outbox_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("outbox.jsonl");
+        let submission = Submission {
+            category: "lambdaman".to_owned(),
+            problem: "lambdaman4".to_owned(),
+            metadata: SolutionMetadata {
+                solver_spec: "lm:walk".to_owned(),
+                score: 42,
+                seed: Some(7),
+                ..Default::default()
+            },
+            payload: "S3/,6%},!-\"$!-!.^}".to_owned(),
+        };
+        append_line(&path, &serde_json::to_string(&submission).unwrap());
+        // a line cut short by a crash doesn't lose the others
+        append_line(&path, "{\"category\":\"lamb");
+        append_line(&path, &serde_json::to_string(&submission).unwrap());
+
+        let (submissions, bad_lines) = load_outbox(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(submissions.len(), 2);
+        assert_eq!(bad_lines, ["{\"category\":\"lamb"]);
+        let loaded = &submissions[1];
+        assert_eq!(loaded.problem, "lambdaman4");
+        assert_eq!(loaded.payload, submission.payload);
+        assert_eq!(loaded.metadata.solver_spec, "lm:walk");
+        assert_eq!((loaded.metadata.score, loaded.metadata.seed), (42, Some(7)));
+
+        assert!(load_outbox(&path).0.is_empty());
+        assert!(is_accepted(
+            "Correct, you solved lambdaman4 with a score of 42!"
+        ));
+        assert!(!is_accepted("Your solution for lambdaman4 is not correct"));
+    }
+}
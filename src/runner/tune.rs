@@ -0,0 +1,373 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use argh::FromArgs;
+use rand::Rng;
+
+use super::{
+    command::{handle_ctrl_c, CANCELLED},
+    parse_duration,
+    spec::{self, SolverSpec},
+    status::{align_columns, natural_key},
+    verify_solution, Budget, Parameter, Problem, Solution, SolverDefaults,
+};
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Try parameters of a solver on a set of problems
+#[argh(subcommand, name = "tune")]
+pub struct TuneCommand {
+    #[argh(positional)]
+    pub category: String,
+    #[argh(positional)]
+    pub solver_name: String,
+    #[argh(option, short = 'g')]
+    /// values to try for a parameter, such as max_possible_states=1000,30000
+    pub grid: Vec<String>,
+    #[argh(option, short = 'r')]
+    /// a range to sample a parameter from, such as max_possible_states=1000..50000
+    pub range: Vec<String>,
+    #[argh(option, short = 'n', default = "10")]
+    /// the number of random samples of the ranges
+    pub samples: usize,
+    #[argh(option, short = 'p')]
+    /// a problem to tune on, all the problems of the category by default
+    pub problem: Vec<String>,
+    #[argh(option, from_str_fn(parse_duration))]
+    /// the time given to each run, such as 10s
+    pub time_limit: Option<Duration>,
+    #[argh(option, from_str_fn(parse_duration))]
+    /// the time given to the whole tuning, such as 1h
+    pub budget: Option<Duration>,
+    #[argh(option, short = 'o')]
+    /// a file to write the best parameters of each problem to, for solve --defaults
+    pub output: Option<PathBuf>,
+}
+
+// the best parameters of each problem, by solver name
+pub type TunedDefaults = HashMap<String, SolverDefaults>;
+
+pub fn load_tuned_defaults(path: &Path) -> Result<TunedDefaults, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+    serde_json::from_str(&text).map_err(|err| format!("failed to parse {}: {err}", path.display()))
+}
+
+type Config = BTreeMap<String, Parameter>;
+
+fn config_label(config: &Config) -> String {
+    if config.is_empty() {
+        return "(defaults)".to_owned();
+    }
+    let parameters: Vec<String> = config
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+    parameters.join(",")
+}
+
+fn split_assignment(assignment: &str) -> Result<(&str, &str), String> {
+    assignment
+        .split_once('=')
+        .ok_or_else(|| format!("expected `name=values`, got {assignment:?}"))
+}
+
+enum Range {
+    Int(i64, i64),
+    Float(f64, f64),
+}
+
+fn parse_range(range: &str) -> Result<Range, String> {
+    let (low, high) = range
+        .split_once("..")
+        .ok_or_else(|| format!("expected a range such as 1..10, got {range:?}"))?;
+    let bound = |bound: &str| match spec::parse_values(bound)?.as_slice() {
+        [value @ (Parameter::Int(_) | Parameter::Float(_))] => Ok(value.clone()),
+        _ => Err(format!("range bounds must be numbers, got {bound:?}")),
+    };
+    match (bound(low)?, bound(high)?) {
+        (Parameter::Int(low), Parameter::Int(high)) if low <= high => Ok(Range::Int(low, high)),
+        (Parameter::Int(_), Parameter::Int(_)) => Err(format!("empty range {range:?}")),
+        (low, high) => {
+            let float = |value| match value {
+                Parameter::Int(value) => value as f64,
+                Parameter::Float(value) => value,
+                _ => unreachable!(),
+            };
+            let (low, high) = (float(low), float(high));
+            if low >= high {
+                return Err(format!("empty range {range:?}"));
+            }
+            Ok(Range::Float(low, high))
+        }
+    }
+}
+
+impl TuneCommand {
+    // every combination of the grid values, each with `samples` samples of the ranges
+    fn configs(&self) -> Result<Vec<Config>, String> {
+        let mut configs = vec![Config::new()];
+        for grid in &self.grid {
+            let (name, values) = split_assignment(grid)?;
+            let values = spec::parse_values(values)?;
+            configs = configs
+                .into_iter()
+                .flat_map(|config| {
+                    values.iter().map(move |value| {
+                        let mut config = config.clone();
+                        config.insert(name.to_owned(), value.clone());
+                        config
+                    })
+                })
+                .collect();
+        }
+
+        let ranges = self
+            .range
+            .iter()
+            .map(|range| {
+                let (name, range) = split_assignment(range)?;
+                Ok((name, parse_range(range)?))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if ranges.is_empty() {
+            return Ok(configs);
+        }
+        let mut rng = rand::thread_rng();
+        Ok(configs
+            .into_iter()
+            .flat_map(|config| std::iter::repeat_n(config, self.samples))
+            .map(|mut config| {
+                for (name, range) in &ranges {
+                    let value = match *range {
+                        Range::Int(low, high) => Parameter::Int(rng.gen_range(low..=high)),
+                        Range::Float(low, high) => Parameter::Float(rng.gen_range(low..high)),
+                    };
+                    config.insert(name.to_string(), value);
+                }
+                config
+            })
+            .collect())
+    }
+
+    fn spec(&self, config: &Config) -> SolverSpec {
+        SolverSpec::Solver {
+            name: self.solver_name.clone(),
+            parameters: config.clone().into_iter().collect(),
+        }
+    }
+
+    pub fn run(&self) {
+        let configs = self.configs().and_then(|configs| {
+            // all the configs are checked before anything runs
+            for config in &configs {
+                super::build_solver(&self.spec(config), &SolverDefaults::new())?;
+            }
+            Ok(configs)
+        });
+        let configs = match configs {
+            Ok(configs) => configs,
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        };
+
+        let current_dir = std::env::current_dir().expect("Failed to get the current directory");
+        let problem_dir = current_dir.join("problems").join(&self.category);
+        if !problem_dir.exists() {
+            eprintln!("Problem path not found: {}", problem_dir.display());
+            std::process::exit(1);
+        }
+        let mut problems = Problem::list(&problem_dir);
+        if !self.problem.is_empty() {
+            problems.retain(|problem| self.problem.contains(&problem.name));
+        }
+        problems.sort_by(|a, b| natural_key(&a.name).cmp(&natural_key(&b.name)));
+
+        handle_ctrl_c("Interrupted, showing the scores so far");
+        let deadline = self.budget.map(|budget| Instant::now() + budget);
+        let verifier = super::create_verifier(&self.category);
+
+        // the scores of each config, None when there is no verified solution
+        let mut scores: Vec<Vec<Option<u64>>> = vec![];
+        for config in &configs {
+            let mut config_scores = vec![];
+            for problem in &problems {
+                let run_deadline = match (deadline, self.time_limit) {
+                    (Some(deadline), Some(limit)) => Some(deadline.min(Instant::now() + limit)),
+                    (deadline, limit) => deadline.or(limit.map(|limit| Instant::now() + limit)),
+                };
+                let best: RefCell<Option<Solution>> = RefCell::new(None);
+                let keep_best = |solution: &Solution| {
+                    let mut best = best.borrow_mut();
+                    if best.as_ref().is_none_or(|best| solution.score < best.score) {
+                        *best = Some(solution.clone());
+                    }
+                };
+                let budget = Budget::until(run_deadline, &CANCELLED, &keep_best);
+                if budget.is_over() {
+                    break;
+                }
+
+                println!("Running {} on {}", config_label(config), problem.name);
+                let mut solver = super::build_solver(&self.spec(config), &SolverDefaults::new())
+                    .expect("the configs are checked");
+                solver.initialize(problem.clone(), None);
+                if let Some(solution) = solver.solve(&budget) {
+                    keep_best(&solution);
+                }
+
+                let score = best.into_inner().and_then(|solution| {
+                    if let Some(Err(err)) =
+                        verifier.map(|verifier| verify_solution(verifier, problem, &solution))
+                    {
+                        println!("{}: the solution doesn't verify: {err}", problem.name);
+                        return None;
+                    }
+                    Some(solution.score)
+                });
+                config_scores.push(score);
+            }
+            // the scores of an interrupted config are kept
+            let over = config_scores.len() < problems.len();
+            scores.push(config_scores);
+            if over {
+                break;
+            }
+        }
+
+        print!("{}", self.score_table(&configs, &problems, &scores));
+        if let Some(output) = &self.output {
+            self.write_defaults(output, &configs, &problems, &scores);
+        }
+    }
+
+    // the index of the config with the lowest score on each problem
+    fn best_configs(problems: &[Problem], scores: &[Vec<Option<u64>>]) -> Vec<Option<usize>> {
+        (0..problems.len())
+            .map(|problem| {
+                scores
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(config, scores)| Some((config, (*scores.get(problem)?)?)))
+                    .min_by_key(|(_, score)| *score)
+                    .map(|(config, _)| config)
+            })
+            .collect()
+    }
+
+    fn score_table(
+        &self,
+        configs: &[Config],
+        problems: &[Problem],
+        scores: &[Vec<Option<u64>>],
+    ) -> String {
+        let mut lines = vec![std::iter::once("parameters".to_owned())
+            .chain(problems.iter().map(|problem| problem.name.clone()))
+            .chain(std::iter::once("total".to_owned()))
+            .collect::<Vec<_>>()];
+        for (config, scores) in configs.iter().zip(scores) {
+            let score = |score: &Option<u64>| score.map_or("-".to_owned(), |s| s.to_string());
+            // runs which didn't happen or failed have no total
+            let total = scores
+                .iter()
+                .copied()
+                .sum::<Option<u64>>()
+                .filter(|_| scores.len() == problems.len());
+            lines.push(
+                std::iter::once(config_label(config))
+                    .chain(scores.iter().map(score))
+                    .chain((scores.len()..problems.len()).map(|_| "-".to_owned()))
+                    .chain(std::iter::once(score(&total)))
+                    .collect(),
+            );
+        }
+
+        let mut table = align_columns(&lines);
+        let _ = writeln!(table);
+        for (problem, best) in problems.iter().zip(Self::best_configs(problems, scores)) {
+            let best = best.map_or("no solution".to_owned(), |best| {
+                config_label(&configs[best])
+            });
+            let _ = writeln!(table, "{}: {best}", problem.name);
+        }
+        table
+    }
+
+    // adds the best config of each problem to the file, keeping the other solvers and problems
+    fn write_defaults(
+        &self,
+        output: &Path,
+        configs: &[Config],
+        problems: &[Problem],
+        scores: &[Vec<Option<u64>>],
+    ) {
+        let mut defaults = if output.exists() {
+            match load_tuned_defaults(output) {
+                Ok(defaults) => defaults,
+                Err(err) => {
+                    eprintln!("{err}, not overwriting it");
+                    return;
+                }
+            }
+        } else {
+            TunedDefaults::new()
+        };
+        for (problem, best) in problems.iter().zip(Self::best_configs(problems, scores)) {
+            if let Some(best) = best {
+                defaults.entry(problem.name.clone()).or_default().insert(
+                    self.solver_name.clone(),
+                    configs[best].clone().into_iter().collect(),
+                );
+            }
+        }
+        let text = serde_json::to_string_pretty(&defaults).unwrap();
+        std::fs::write(output, text).expect("Failed to write the tuned defaults");
+        println!("Wrote the best parameters to {}", output.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tune(grid: &[&str], range: &[&str]) -> TuneCommand {
+        TuneCommand {
+            category: "spaceship".to_owned(),
+            solver_name: "ss:greedy".to_owned(),
+            grid: grid.iter().map(|grid| grid.to_string()).collect(),
+            range: range.iter().map(|range| range.to_string()).collect(),
+            samples: 3,
+            problem: vec![],
+            time_limit: None,
+            budget: None,
+            output: None,
+        }
+    }
+
+    #[test]
+    fn test_configs() {
+        let configs = tune(&["a=1,2", "b=true,false"], &[]).configs().unwrap();
+        let labels: Vec<String> = configs.iter().map(config_label).collect();
+        assert_eq!(
+            labels,
+            ["a=1,b=true", "a=1,b=false", "a=2,b=true", "a=2,b=false"]
+        );
+
+        let configs = tune(&["a=1,2"], &["x=5..7", "y=0..0.5"]).configs().unwrap();
+        assert_eq!(configs.len(), 6);
+        for config in configs {
+            assert!(matches!(config["x"], Parameter::Int(5..=7)));
+            assert!(matches!(config["y"], Parameter::Float(y) if (0.0..0.5).contains(&y)));
+        }
+
+        assert!(tune(&["a"], &[]).configs().is_err());
+        assert!(tune(&[], &["x=7..5"]).configs().is_err());
+        assert!(tune(&[], &["x=a..b"]).configs().is_err());
+    }
+}
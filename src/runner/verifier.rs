@@ -0,0 +1,100 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::icfp::{try_evaluate_beta, Value, EVALUATION_LIMIT};
+
+use super::{Problem, Solution};
+
+// replays the output of a solution against its problem, before it is submitted
+pub trait Verifier: Sync + Send {
+    // `answer` is what follows `solve <problem>` in the solution's output
+    fn verify(&self, problem: &str, answer: &str) -> Result<(), String>;
+}
+
+// evaluates the solution, and checks its answer with the verifier of its category
+pub fn verify_solution(
+    verifier: &dyn Verifier,
+    problem: &Problem,
+    solution: &Solution,
+) -> Result<(), String> {
+    // the solution gets as many beta reductions as the server gives it, generated agents take
+    // many more strict reductions than that
+    let value = try_evaluate_beta(solution.icfp_code.clone(), EVALUATION_LIMIT)
+        .map_err(|err| format!("the solution failed to evaluate: {err}"))?;
+    let Value::Str(output) = value else {
+        return Err(format!("the solution evaluates to {value}, not a string"));
+    };
+    let prefix = format!("solve {}", problem.name);
+    let answer = output
+        .strip_prefix(&prefix)
+        .and_then(|rest| rest.strip_prefix([' ', '\n']))
+        .ok_or_else(|| format!("the solution doesn't start with `{prefix} `"))?;
+    // the models panic on invalid input
+    let problem = problem.load();
+    catch_unwind(AssertUnwindSafe(|| verifier.verify(&problem, answer)))
+        .map_err(|_| "the answer couldn't be replayed".to_owned())?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::super::{create_solver, create_verifier, Budget, Problem};
+    use super::verify_solution;
+
+    #[test]
+    fn test_lambdaman() {
+        let verifier = create_verifier("lambdaman").unwrap();
+        let problem = "###.#...\n...L..##\n.#######\n";
+        assert_eq!(verifier.verify(problem, "UDRRURRLLDLLLLLD"), Ok(()));
+        assert_eq!(
+            verifier.verify(problem, "RRRR"),
+            Err("8 of the 10 pills are left".to_owned())
+        );
+        assert_eq!(
+            verifier.verify(problem, "RX"),
+            Err("invalid move 'X'".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_spaceship() {
+        let verifier = create_verifier("spaceship").unwrap();
+        let problem = "1 -1\n1 -3\n2 -5\n2 -8\n3 -10\n";
+        assert_eq!(verifier.verify(problem, "31619"), Ok(()));
+        assert_eq!(
+            verifier.verify(problem, "316"),
+            Err("2 of the 5 points aren't visited".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_3d() {
+        let verifier = create_verifier("3d").unwrap();
+        let problem =
+            "# Output\n  A + B\n\n# Example\n  * `A = 3`\n    `B = 4`\n    `Answer = 7`\n";
+        assert_eq!(verifier.verify(problem, ". A .\nB + S\n. . ."), Ok(()));
+        assert_eq!(
+            verifier.verify(problem, ". A .\nB - S\n. . ."),
+            Err("with A = 3 and B = 4, the board submits 1 instead of 7".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_generated_agent() {
+        let dir = std::env::temp_dir().join(format!("verifier_agent_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lambdaman0");
+        std::fs::write(&path, "L...\n.##.\n....\n").unwrap();
+        let problem = Problem::new(path, "lambdaman0".to_owned());
+
+        // the agent is a LASM program walking randomly, which only the evaluator can replay
+        let mut solver = create_solver("lm:random_step").unwrap();
+        solver.initialize(problem.clone(), None);
+        let cancelled = AtomicBool::new(false);
+        let solution = solver.solve(&Budget::new(None, &cancelled, &|_| {}));
+        let verifier = create_verifier("lambdaman").unwrap();
+        let verified = verify_solution(verifier, &problem, &solution.unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(verified, Ok(()));
+    }
+}
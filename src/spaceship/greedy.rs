@@ -5,7 +5,7 @@ use std::{
 
 use crate::{
     icfp::{Node, Value},
-    runner::{Parameter, Problem, Solution, Solver},
+    runner::{Budget, Parameter, ParameterInfo, Problem, Solution, Solver},
     spaceship::model::{Command, SpaceshipState},
 };
 
@@ -26,28 +26,30 @@ impl Solver for SpaceshipGreedy {
     fn initialize(&mut self, problem: Problem, _solution: Option<Solution>) {
         self.model = SpaceshipModel::load(&problem.load());
         self.problem = problem;
-        self.max_possible_states = 30_000;
+    }
+
+    fn parameters(&self) -> HashMap<String, Parameter> {
+        HashMap::from([(
+            "max_possible_states".to_owned(),
+            Parameter::Int(self.max_possible_states as i64),
+        )])
+    }
+
+    fn parameter_schema(&self) -> Vec<ParameterInfo> {
+        vec![ParameterInfo::new(
+            "max_possible_states",
+            Parameter::Int(30_000),
+            "give up when more states than this are reachable",
+        )]
     }
 
     fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
-        for (key, value) in parameters {
-            match key.as_str() {
-                "max_possible_states" => match value {
-                    Parameter::Int(value) => {
-                        self.max_possible_states = value as u32;
-                    }
-                    _ => {
-                        panic!("Invalid value for max_possible_states: {:?}", value);
-                    }
-                },
-                _ => {
-                    panic!("Unknown parameter: {}", key);
-                }
-            }
+        if let Some(Parameter::Int(value)) = parameters.get("max_possible_states") {
+            self.max_possible_states = *value as u32;
         }
     }
 
-    fn solve(&mut self) -> Solution {
+    fn solve(&mut self, budget: &Budget) -> Option<Solution> {
         let mut current_state = SpaceshipState::default();
 
         let mut points_to_visit = self.model.points.iter().cloned().collect::<HashSet<_>>();
@@ -86,6 +88,10 @@ impl Solver for SpaceshipGreedy {
                 println!("Visited states: {:?}", visited_states.len());
                 println!("Points to visit: {:?}", points_to_visit.len());
 
+                // a partial path isn't a solution
+                if budget.is_over() {
+                    return None;
+                }
                 if possible_states.len() > self.max_possible_states as usize {
                     eprintln!("Too many possible states, exiting");
                     break;
@@ -216,12 +222,12 @@ impl Solver for SpaceshipGreedy {
 
         println!("Path: {}", current_state.path);
 
-        Solution::new(
+        Some(Solution::new(
             Rc::new(Node::Value(Value::Str(format!(
                 "solve {} {}",
                 self.problem.name, current_state.path
             )))),
             current_state.path.len() as u64,
-        )
+        ))
     }
 }
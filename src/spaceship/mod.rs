@@ -1,6 +1,8 @@
 mod greedy;
 mod model;
 mod one_by_one;
+mod verifier;
 
 pub use greedy::SpaceshipGreedy;
 pub use one_by_one::SpaceshipOneByOne;
+pub use verifier::SpaceshipVerifier;
@@ -30,6 +30,26 @@ pub enum Command {
     DownRight,
 }
 
+// the keypad digits
+impl TryFrom<char> for Command {
+    type Error = ();
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        match value {
+            '7' => Ok(Self::UpLeft),
+            '8' => Ok(Self::Up),
+            '9' => Ok(Self::UpRight),
+            '4' => Ok(Self::Left),
+            '5' => Ok(Self::KeepSpeed),
+            '6' => Ok(Self::Right),
+            '1' => Ok(Self::DownLeft),
+            '2' => Ok(Self::Down),
+            '3' => Ok(Self::DownRight),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct SpaceshipState {
     pub pos: Vector2D,
@@ -5,7 +5,7 @@ use memoize::memoize;
 use crate::{
     geometry::Vector2D,
     icfp::{Node, Value},
-    runner::{Parameter, Problem, Solution, Solver},
+    runner::{Budget, Parameter, ParameterInfo, Problem, Solution, Solver},
     spaceship::model::{Command, SpaceshipState},
 };
 
@@ -26,33 +26,36 @@ impl Solver for SpaceshipOneByOne {
     fn initialize(&mut self, problem: Problem, _solution: Option<Solution>) {
         self.problem = problem;
         self.model = SpaceshipModel::load(&self.problem.load());
-        self.start_stop = false;
+    }
+
+    fn parameters(&self) -> HashMap<String, Parameter> {
+        HashMap::from([("start_stop".to_owned(), Parameter::Bool(self.start_stop))])
+    }
+
+    fn parameter_schema(&self) -> Vec<ParameterInfo> {
+        vec![ParameterInfo::new(
+            "start_stop",
+            Parameter::Bool(false),
+            "accelerate and brake on each axis separately",
+        )]
     }
 
     fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
-        for (key, value) in parameters {
-            match key.as_str() {
-                "start_stop" => match value {
-                    Parameter::Int(1) => {
-                        self.start_stop = true;
-                    }
-                    _ => {
-                        panic!("Invalid value for start_stop: {:?}", value);
-                    }
-                },
-                _ => {
-                    panic!("Unknown parameter: {}", key);
-                }
-            }
+        if let Some(Parameter::Bool(value)) = parameters.get("start_stop") {
+            self.start_stop = *value;
         }
     }
 
-    fn solve(&mut self) -> Solution {
+    fn solve(&mut self, budget: &Budget) -> Option<Solution> {
         let mut current_state = SpaceshipState::default();
 
         let mut points_to_visit = self.model.points.clone();
 
         while !points_to_visit.is_empty() {
+            // a partial path isn't a solution
+            if budget.is_over() {
+                return None;
+            }
             points_to_visit.sort_by(|a, b| {
                 let dist_a = (current_state.pos - *a).manhattan();
                 let dist_b = (current_state.pos - *b).manhattan();
@@ -160,13 +163,13 @@ impl Solver for SpaceshipOneByOne {
 
         println!("Path: {}", current_state.path);
 
-        Solution::new(
+        Some(Solution::new(
             Rc::new(Node::Value(Value::Str(format!(
                 "solve {} {}",
                 self.problem.name, current_state.path
             )))),
             current_state.path.len() as u64,
-        )
+        ))
     }
 }
 
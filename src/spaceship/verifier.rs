@@ -0,0 +1,39 @@
+use std::collections::HashSet;
+
+use crate::runner::Verifier;
+
+use super::model::{Command, SpaceshipModel, SpaceshipState};
+
+// the ship must go through all the points
+#[derive(Debug, Clone, Default)]
+pub struct SpaceshipVerifier;
+
+impl Verifier for SpaceshipVerifier {
+    fn verify(&self, problem: &str, answer: &str) -> Result<(), String> {
+        let model = SpaceshipModel::load(problem);
+        let mut state = SpaceshipState::default();
+        let mut visited = HashSet::new();
+        for c in answer.chars() {
+            let command = Command::try_from(c).map_err(|_| format!("invalid command {c:?}"))?;
+            // the path isn't needed, don't let it grow
+            state = SpaceshipState {
+                path: String::new(),
+                ..state.next(command)
+            };
+            visited.insert(state.pos);
+        }
+
+        let missed = model
+            .points
+            .iter()
+            .filter(|point| !visited.contains(point))
+            .count();
+        if missed > 0 {
+            return Err(format!(
+                "{missed} of the {} points aren't visited",
+                model.points.len()
+            ));
+        }
+        Ok(())
+    }
+}
@@ -2,5 +2,7 @@ mod board;
 mod command;
 mod gui;
 mod sim;
+mod verifier;
 
 pub use command::ThreeDCommand;
+pub use verifier::ThreeDVerifier;
@@ -0,0 +1,79 @@
+use num::BigInt;
+
+use crate::runner::Verifier;
+
+use super::{
+    board::ThreeDBoard,
+    sim::{SimulationStepResult, ThreeDSimulator},
+};
+
+struct Example {
+    a: BigInt,
+    b: BigInt,
+    answer: BigInt,
+}
+
+// the examples of a problem statement, given as `A = 3`, `B = 7` and `Answer = 7` lines
+fn examples(problem: &str) -> Vec<Example> {
+    let mut examples = vec![];
+    let (mut a, mut b) = (BigInt::from(0), BigInt::from(0));
+    for line in problem.lines() {
+        let line = line.trim().trim_start_matches('*').trim().trim_matches('`');
+        let Some((name, value)) = line.split_once(" = ") else {
+            continue;
+        };
+        let Ok(value) = value.trim().parse::<BigInt>() else {
+            continue;
+        };
+        match name.trim() {
+            "A" => a = value,
+            "B" => b = value,
+            "Answer" => {
+                examples.push(Example {
+                    a: std::mem::take(&mut a),
+                    b: std::mem::take(&mut b),
+                    answer: value,
+                });
+            }
+            _ => {}
+        }
+    }
+    examples
+}
+
+// the board must submit the expected answer of every example
+#[derive(Debug, Clone, Default)]
+pub struct ThreeDVerifier;
+
+impl Verifier for ThreeDVerifier {
+    fn verify(&self, problem: &str, answer: &str) -> Result<(), String> {
+        let board = ThreeDBoard::load(answer);
+        let examples = examples(problem);
+        if examples.is_empty() {
+            return Err("the problem has no examples".to_owned());
+        }
+
+        for example in examples {
+            let inputs = format!("with A = {} and B = {}", example.a, example.b);
+            let mut sim = ThreeDSimulator::new(board.clone(), example.a, example.b);
+            let result = loop {
+                match sim.step() {
+                    SimulationStepResult::Ok => {}
+                    SimulationStepResult::Finished(result) => break result,
+                    SimulationStepResult::AlreadyFinished => unreachable!(),
+                    // the simulator also fails after a million ticks
+                    SimulationStepResult::Error(pos) => {
+                        return Err(format!("{inputs}, the board fails at {pos:?}"));
+                    }
+                }
+            };
+            if result != example.answer {
+                return Err(format!(
+                    "{inputs}, the board submits {result} instead of {}",
+                    example.answer
+                ));
+            }
+        }
+        Ok(())
+    }
+}